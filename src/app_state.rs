@@ -1,13 +1,42 @@
-use std::io;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    io::{self, IsTerminal},
+    path::Path,
+};
 
 use crate::{
-    command::Command,
+    chart,
+    command::{Assume, Command, Period},
+    config::Config,
     database::Database,
-    hierarchy::{Project, Task, task_from_command},
+    error::{Result, TodoError},
+    file_storage::FileStorage,
+    hierarchy::{
+        Annotation, Project, Task, TimeEntry, task_from_command, update_task_from_command,
+    },
+    history::Change,
+    render::{ColorChoice, Entry, Renderer},
+    replica::{new_uuid, parse_uuid},
+    stats::StatsQuery,
+    storage::{Storage, Trash},
+    sync::{self, Repo, Snapshot},
+    timeutil::{
+        date_before, format_duration, out_of_range, parse_date, parse_duration, time_before,
+    },
+    urgency::{self, Urgency},
+    working_set::WorkingSet,
 };
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDate, Utc};
 use std::io::Write;
 
-fn prompt(question: &str) -> io::Result<bool> {
+fn prompt(question: &str) -> Result<bool> {
+    if !io::stdin().is_terminal() {
+        return Err(TodoError::invalid(format!(
+            "cannot ask \"{question}\": stdin is not a terminal (pass --yes or --no, or set TODO_ASSUME)"
+        )));
+    }
+
     print!("{question} [y/N] ");
     io::stdout().flush()?;
 
@@ -18,114 +47,307 @@ fn prompt(question: &str) -> io::Result<bool> {
     Ok(answer == "y" || answer == "yes")
 }
 
+fn task_mut(projects: &mut [Project], task_id: usize) -> Result<&mut Task> {
+    projects
+        .iter_mut()
+        .flat_map(|p| p.tasks.iter_mut())
+        .find(|t| t.id() == task_id)
+        .ok_or_else(|| TodoError::not_found("task not found"))
+}
+
+fn parse_index(value: &str) -> Result<usize> {
+    value
+        .parse()
+        .map_err(|_| TodoError::invalid(format!("invalid annotation index '{value}'")))
+}
+
 #[derive(Debug)]
 pub struct AppState {
     next_task_id: usize,
     projects: Vec<Project>,
-    db: Database,
+    storage: Box<dyn Storage>,
+    assume: Assume,
+    config: Config,
+    working_set: WorkingSet,
 }
 
 impl AppState {
-    pub fn load() -> Self {
-        let db = Database::new().expect("Failed to initialize database");
+    /// Loads the state from the default database at `~/.todo.db`.
+    pub fn load() -> Result<Self> {
+        Self::from_database(Database::new()?)
+    }
+
+    /// Loads the state from the data file at `path`, creating it if needed.
+    /// A `.json` extension selects the plain-file backend, anything else SQLite.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        if path.extension().is_some_and(|ext| ext == "json") {
+            Self::with_storage(Box::new(FileStorage::open(path)?))
+        } else {
+            Self::from_database(Database::open(path)?)
+        }
+    }
+
+    pub fn from_database(db: Database) -> Result<Self> {
+        Self::with_storage(Box::new(db))
+    }
 
-        let mut projects = db.load_projects().unwrap_or_else(|_| Vec::new());
+    pub fn with_storage(storage: Box<dyn Storage>) -> Result<Self> {
+        let mut projects = storage.load_projects()?;
 
-        let next_task_id = db.load_next_task_id().unwrap_or(0);
+        let next_task_id = storage.load_next_task_id()?;
 
         // If no projects exist, create and save the default Home project
         if projects.is_empty() {
             let home_project = Project {
                 name: String::from("Home"),
                 id: 0,
+                uuid: new_uuid(),
                 parent_id: 0,
                 tasks: Vec::new(),
             };
-            let _ = db.save_projects(&[home_project.clone()]);
-            projects = vec![home_project];
+            // Another process starting at the same time may have beaten us to it.
+            if let Err(e) = storage.insert_project(&home_project) {
+                projects = storage.load_projects()?;
+                if projects.is_empty() {
+                    return Err(e);
+                }
+            } else {
+                projects = vec![home_project];
+            }
         }
 
-        AppState {
+        Ok(AppState {
             next_task_id,
             projects,
-            db,
+            storage,
+            assume: Assume::Ask,
+            config: Config::default(),
+            working_set: WorkingSet::default(),
+        })
+    }
+
+    /// Replaces task and project uuids in `args` with the `#id` or the name
+    /// that commands take, so that a uuid works anywhere those do. A leading `-`,
+    /// as in `--depends -<uuid>`, is kept.
+    pub fn resolve_uuids(&self, args: Vec<String>) -> Result<Vec<String>> {
+        let uuid_of = |arg: &str| parse_uuid(arg.strip_prefix('-').unwrap_or(arg));
+        if !args.iter().skip(1).any(|arg| uuid_of(arg).is_some()) {
+            return Ok(args);
+        }
+
+        let trash = self.storage.load_trash()?;
+        let archive = self.storage.load_archive()?;
+        let projects: Vec<&Project> = self
+            .projects
+            .iter()
+            .chain(trash.projects.iter().map(|p| &p.project))
+            .collect();
+        let mut known: BTreeMap<&str, String> = BTreeMap::new();
+        for project in &projects {
+            known.insert(&project.uuid, project.name.clone());
+        }
+        let tasks = projects
+            .iter()
+            .flat_map(|p| p.tasks.iter())
+            .chain(trash.tasks.iter().map(|t| &t.task))
+            .chain(archive.iter());
+        for task in tasks {
+            known.insert(task.uuid(), format!("#{}", task.id()));
+        }
+
+        let mut resolved = vec![args[0].clone()];
+        for arg in args.into_iter().skip(1) {
+            let Some(uuid) = uuid_of(&arg) else {
+                resolved.push(arg);
+                continue;
+            };
+            let value = known.get(uuid.as_str()).ok_or_else(|| {
+                TodoError::not_found(format!("no task or project has uuid {uuid}"))
+            })?;
+            let sign = if arg.starts_with('-') { "-" } else { "" };
+            resolved.push(format!("{sign}{value}"));
         }
+        Ok(resolved)
     }
 
-    fn save(&self) {
-        if let Err(e) = self.db.save_projects(&self.projects) {
-            eprintln!("Failed to save projects: {}", e);
+    pub fn set_config(&mut self, config: Config) {
+        self.config = config;
+    }
+
+    /// Loads the working set if the `working_set` option is on and turns the
+    /// numbers on the command line into stored ids. Call after `set_config`.
+    pub fn apply_working_set(&mut self, cmd: &mut Command) -> Result<()> {
+        if !self.config.working_set {
+            return Ok(());
         }
-        if let Err(e) = self.db.save_next_task_id(self.next_task_id) {
-            eprintln!("Failed to save next_task_id: {}", e);
+        self.working_set = WorkingSet::new(self.storage.load_working_set()?);
+        // `next` takes a count, not a task.
+        if cmd.op() == "next" {
+            return Ok(());
         }
+        cmd.resolve_ids(|number| self.working_set.resolve(number))
     }
 
-    fn new_task_id(&mut self) -> usize {
-        let id: usize = self.next_task_id;
-        self.next_task_id += 1;
-        id
+    /// Sets the answer used for confirmation prompts when the command line gives none.
+    pub fn set_assume(&mut self, assume: Assume) {
+        self.assume = assume;
     }
-    pub fn handle_add(&mut self, cmd: &Command) -> Result<(), &'static str> {
-        let (project_name, _, _) = cmd.parameters().fields();
 
-        let project_id = if let Some(name) = project_name.clone() {
-            self.resolve_project_id(name)
-                .map_err(|_| "io error while prompting")?
-                .ok_or("project not created")?
-        } else {
-            0
+    fn confirm(&self, cmd: &Command, question: &str) -> Result<bool> {
+        let assume = match cmd.parameters().assume() {
+            Assume::Ask => self.assume,
+            explicit => explicit,
         };
-        let id = self.new_task_id();
+        match assume {
+            Assume::Yes => Ok(true),
+            Assume::No => Ok(false),
+            Assume::Ask => prompt(question),
+        }
+    }
+
+    /// Reserves an id in storage, since another process may have taken ours.
+    fn new_task_id(&mut self) -> Result<usize> {
+        let id = self.storage.allocate_task_id()?;
+        self.next_task_id = id + 1;
+        Ok(id)
+    }
+
+    pub fn handle_add(&mut self, cmd: &Command) -> Result<()> {
+        let (project_name, _, _) = cmd.parameters().fields();
 
-        let task = task_from_command(cmd, id, project_id).map_err(|_| "invalid command fields")?;
+        let name = project_name
+            .clone()
+            .unwrap_or_else(|| self.config.default_project.clone());
+        let project_id = self
+            .resolve_project_id(cmd, name)?
+            .ok_or_else(|| TodoError::cancelled("project not created"))?;
 
-        self.add_task_to_project(project_id, task)?;
-        self.save();
+        // Validate the fields before consuming an id.
+        let mut task = task_from_command(cmd, self.next_task_id, project_id)?;
+        if cmd.parameters().fields().2.is_none() {
+            task.set_priority(self.config.default_priority());
+        }
+        self.check_dependencies(&task, &[])?;
+        let id = self.new_task_id()?;
+        task.set_id(id);
+
+        self.storage.insert_task(&task)?;
+        if self.working_set.enabled() {
+            self.storage.extend_working_set(id)?;
+            self.working_set.push(id);
+        }
+        self.add_task_to_project(project_id, task)
+    }
+
+    fn find_task(&self, task_id: usize) -> Result<&Task> {
+        self.projects
+            .iter()
+            .flat_map(|p| p.tasks.iter())
+            .find(|t| t.id() == task_id)
+            .ok_or_else(|| TodoError::not_found("task not found"))
+    }
+
+    /// Rejects dependencies on the task itself or that would close a cycle, and
+    /// new ones on unknown tasks. Dependencies in `previous` may have gone away
+    /// since they were added, e.g. into the archive or the trash.
+    fn check_dependencies(&self, task: &Task, previous: &[usize]) -> Result<()> {
+        let mut pending = task.depends_on().to_vec();
+        let mut seen = Vec::new();
+        while let Some(id) = pending.pop() {
+            if id == task.id() {
+                return Err(TodoError::invalid(format!(
+                    "task {} cannot depend on itself, directly or through other tasks",
+                    self.working_set.label(task.id())
+                )));
+            }
+            if seen.contains(&id) {
+                continue;
+            }
+            seen.push(id);
+            match self.find_task(id) {
+                Ok(dependency) => pending.extend_from_slice(dependency.depends_on()),
+                Err(_) if previous.contains(&id) => {}
+                Err(_) => {
+                    return Err(TodoError::not_found(format!(
+                        "dependency {} not found",
+                        self.working_set.label(id)
+                    )));
+                }
+            }
+        }
         Ok(())
     }
 
+    /// Whether some dependency of `task` is still open.
+    fn is_blocked(&self, task: &Task) -> bool {
+        task.depends_on()
+            .iter()
+            .filter_map(|&id| self.find_task(id).ok())
+            .any(|t| t.completed_at().is_none())
+    }
+
+    fn urgency(&self, task: &Task, project: &str) -> Urgency {
+        let context = urgency::Context {
+            project,
+            blocked: self.is_blocked(task),
+            blocking: self
+                .projects
+                .iter()
+                .flat_map(|p| p.tasks.iter())
+                .any(|t| t.completed_at().is_none() && t.depends_on().contains(&task.id())),
+            today: Local::now().date_naive(),
+            now: Utc::now(),
+        };
+        urgency::score(task, &context, &self.config.urgency)
+    }
+
     fn find_project_id(&self, name: &str) -> Option<usize> {
         self.projects.iter().find(|p| p.name == name).map(|p| p.id)
     }
 
-    fn resolve_project_id(&mut self, name: String) -> io::Result<Option<usize>> {
+    fn resolve_project_id(&mut self, cmd: &Command, name: String) -> Result<Option<usize>> {
         if let Some(id) = self.find_project_id(&name) {
             return Ok(Some(id));
         }
 
-        if prompt(&format!("Project '{name}' does not exist. Create it?'"))? {
-            let id = self.create_project(name.to_string());
+        let auto_create =
+            self.config.auto_create_projects && cmd.parameters().assume() == Assume::Ask;
+        if auto_create
+            || self.confirm(cmd, &format!("Project '{name}' does not exist. Create it?"))?
+        {
+            let id = self.create_project(name.to_string())?;
             Ok(Some(id))
         } else {
             Ok(None)
         }
     }
-    fn create_project(&mut self, name: String) -> usize {
-        let id = self.projects.iter().map(|p| p.id).max().unwrap_or(0) + 1;
+    fn create_project(&mut self, name: String) -> Result<usize> {
+        let id = self.storage.allocate_project_id()?;
         let project = Project {
             name,
             id,
+            uuid: new_uuid(),
             parent_id: 0,
             tasks: Vec::new(),
         };
 
+        self.storage.insert_project(&project)?;
         self.projects.push(project);
-        self.save();
-        id
+        Ok(id)
     }
-    fn projects(&self) -> &Vec<Project> {
+    pub fn projects(&self) -> &Vec<Project> {
         &self.projects
     }
 
-    fn add_task_to_project(&mut self, project_id: usize, task: Task) -> Result<(), &'static str> {
+    fn add_task_to_project(&mut self, project_id: usize, task: Task) -> Result<()> {
         let project = self
             .projects
             .iter_mut()
             .find(|p| p.id == project_id)
-            .ok_or("project not found");
+            .ok_or_else(|| TodoError::not_found("project not found"))?;
 
-        project.unwrap().tasks.push(task);
+        project.tasks.push(task);
         Ok(())
     }
     fn children_of(&self, parent_id: usize) -> impl Iterator<Item = &Project> {
@@ -134,30 +356,187 @@ impl AppState {
             .filter(move |p| p.parent_id == parent_id && p.id != parent_id)
     }
 
-    fn print_subtree(&self, project_id: usize, depth: usize) {
+    fn collect_subtree<'a>(
+        &'a self,
+        project_id: usize,
+        depth: usize,
+        show_completed: bool,
+        entries: &mut Vec<Entry<'a>>,
+    ) {
         if let Some(p) = self.projects.iter().find(|p| p.id == project_id) {
-            println!("{:indent$}{}", "", p.name, indent = depth * 2);
+            entries.push(Entry::Project {
+                depth,
+                name: &p.name,
+            });
 
             for task in &p.tasks {
-                println!(
-                    "{:indent$}- [{}: {}]",
-                    "",
-                    task.id(),
-                    task.name(),
-                    indent = (depth + 1) * 2
-                );
+                if !show_completed && task.completed_at().is_some() {
+                    continue;
+                }
+                entries.push(Entry::Task {
+                    depth: depth + 1,
+                    task,
+                });
             }
             for child in self.children_of(p.id) {
-                self.print_subtree(child.id, depth + 1);
+                self.collect_subtree(child.id, depth + 1, show_completed, entries);
             }
         }
     }
 
-    pub fn handle_list(&self, project_id: usize) {
-        self.print_subtree(project_id, 0);
+    fn renderer(&self, cmd: &Command) -> Result<Renderer> {
+        let choice = match cmd.parameters().color() {
+            Some(value) => ColorChoice::parse(value)?,
+            None => self.config.color_choice(),
+        };
+        Ok(Renderer::new(choice.enabled(), self.working_set.clone()))
     }
 
-    pub fn handle_remove(&mut self, cmd: &Command) -> Result<(), &'static str> {
+    pub fn handle_list(&self, cmd: &Command) -> Result<()> {
+        let (project_name, _, _) = cmd.parameters().fields();
+
+        let project_id = match project_name.as_ref().or(self.config.list.project.as_ref()) {
+            Some(name) => self
+                .find_project_id(name)
+                .ok_or_else(|| TodoError::not_found("project not found"))?,
+            None => 0,
+        };
+        let show_completed = self.config.list.show_completed || cmd.parameters().all();
+        if cmd.parameters().archived() {
+            return self.list_archived(cmd, project_id);
+        }
+        if cmd.parameters().json() {
+            let subtree = self.subtree_ids(project_id);
+            let projects: Vec<Project> = self
+                .projects
+                .iter()
+                .filter(|p| subtree.contains(&p.id))
+                .map(|p| {
+                    let mut project = p.clone();
+                    project
+                        .tasks
+                        .retain(|t| show_completed || t.completed_at().is_none());
+                    project
+                })
+                .collect();
+            return print_json(&projects);
+        }
+
+        let mut entries = Vec::new();
+        self.collect_subtree(project_id, 0, show_completed, &mut entries);
+        print!("{}", self.renderer(cmd)?.list(&entries));
+        Ok(())
+    }
+
+    /// Archived tasks of the `project_id` subtree, grouped by project.
+    fn list_archived(&self, cmd: &Command, project_id: usize) -> Result<()> {
+        let archive = self.storage.load_archive()?;
+        let mut entries = Vec::new();
+        for id in self.subtree_ids(project_id) {
+            let mut tasks = archive.iter().filter(|t| t.project_id() == id).peekable();
+            if tasks.peek().is_none() {
+                continue;
+            }
+            let project = self
+                .projects
+                .iter()
+                .find(|p| p.id == id)
+                .expect("in subtree");
+            entries.push(Entry::Project {
+                depth: 0,
+                name: &project.name,
+            });
+            entries.extend(tasks.map(|task| Entry::Task { depth: 1, task }));
+        }
+
+        if entries.is_empty() {
+            println!("No archived tasks");
+            return Ok(());
+        }
+        print!("{}", self.renderer(cmd)?.list(&entries));
+        Ok(())
+    }
+
+    /// Moves tasks of the `root` subtree completed before `before` into the archive.
+    fn archive_completed(&mut self, root: usize, before: DateTime<Utc>) -> Result<usize> {
+        let ids: Vec<usize> = self
+            .subtree_tasks(root)
+            .into_iter()
+            .filter(|t| t.completed_at().is_some_and(|done| done < before))
+            .map(|t| t.id())
+            .collect();
+        if ids.is_empty() {
+            return Ok(0);
+        }
+
+        self.storage.archive_tasks(&ids)?;
+        for project in self.projects.iter_mut() {
+            project.tasks.retain(|t| !ids.contains(&t.id()));
+        }
+        Ok(ids.len())
+    }
+
+    /// Cut-off for `--completed-before`, or now when it isn't given.
+    fn completed_before(cmd: &Command) -> Result<DateTime<Utc>> {
+        let now = Utc::now();
+        match cmd.parameters().completed_before() {
+            Some(span) => time_before(span, now),
+            None => Ok(now),
+        }
+    }
+
+    /// `archive [--completed-before 30d] [-p project]` moves completed tasks out
+    /// of the active set.
+    pub fn handle_archive(&mut self, cmd: &Command) -> Result<()> {
+        let root = self.filter_root(cmd)?;
+        let count = self.archive_completed(root, Self::completed_before(cmd)?)?;
+        println!("Archived {count} task(s)");
+        Ok(())
+    }
+
+    /// Archives tasks completed longer ago than the `archive.after` setting and
+    /// empties the trash of anything older than `trash.expire_after`.
+    pub fn apply_retention_policies(&mut self) -> Result<()> {
+        if let Some(after) = &self.config.archive.after {
+            let before = time_before(after, Utc::now())?;
+            self.archive_completed(0, before)?;
+        }
+        if self.config.trash.expire_after != "never" {
+            let before = time_before(&self.config.trash.expire_after, Utc::now())?;
+            self.empty_trash(before)?;
+        }
+        Ok(())
+    }
+
+    /// `purge [--completed-before 90d] [-p project]` permanently deletes archived tasks.
+    pub fn handle_purge(&mut self, cmd: &Command) -> Result<()> {
+        let before = Self::completed_before(cmd)?;
+        let subtree = self.subtree_ids(self.filter_root(cmd)?);
+        let ids: Vec<usize> = self
+            .storage
+            .load_archive()?
+            .iter()
+            .filter(|t| subtree.contains(&t.project_id()))
+            .filter(|t| t.completed_at().is_none_or(|done| done < before))
+            .map(|t| t.id())
+            .collect();
+
+        if ids.is_empty() {
+            println!("No archived tasks to purge");
+            return Ok(());
+        }
+        if !self.confirm(
+            cmd,
+            &format!("Permanently delete {} archived task(s)?", ids.len()),
+        )? {
+            return Err(TodoError::cancelled("nothing purged"));
+        }
+        self.storage.purge_archived(&ids)?;
+        println!("Purged {} task(s)", ids.len());
+        Ok(())
+    }
+
+    pub fn handle_remove(&mut self, cmd: &Command) -> Result<()> {
         if let Some(task_id) = cmd.parameters().task_id() {
             // Remove task by ID in a single pass
             let mut found = false;
@@ -170,63 +549,786 @@ impl AppState {
                 }
             }
             if !found {
-                return Err("task not found");
+                return Err(TodoError::not_found("task not found"));
             }
+            self.storage.trash_task(task_id, Utc::now())?;
+            println!(
+                "Task {} moved to the trash",
+                self.working_set.label(task_id)
+            );
+            Ok(())
         } else {
-            return Err("task ID required");
+            Err(TodoError::invalid("task ID required"))
+        }
+    }
+
+    pub fn handle_modify(&mut self, cmd: &Command) -> Result<()> {
+        let task_id = cmd
+            .parameters()
+            .task_id()
+            .ok_or_else(|| TodoError::invalid("task ID required"))?;
+        // Looked up first, so an unknown task fails before a project is created.
+        let old_task = self.find_task(task_id)?.clone();
+        let project_id = match cmd.parameters().fields().0 {
+            Some(name) => Some(
+                self.resolve_project_id(cmd, name.clone())?
+                    .ok_or_else(|| TodoError::cancelled("project not created"))?,
+            ),
+            None => None,
+        };
+
+        let mut new_task = old_task.clone();
+        update_task_from_command(&mut new_task, cmd)?;
+        if let Some(project_id) = project_id {
+            new_task.set_project_id(project_id);
         }
-        self.save();
+        self.check_dependencies(&new_task, old_task.depends_on())?;
+        let moved = new_task.project_id() != old_task.project_id();
+        self.storage.update_task(&mut new_task)?;
+        if moved {
+            for project in self.projects.iter_mut() {
+                project.tasks.retain(|t| t.id() != task_id);
+            }
+            return self.add_task_to_project(new_task.project_id(), new_task);
+        }
+        *task_mut(&mut self.projects, task_id)? = new_task;
         Ok(())
     }
 
-    pub fn handle_modify(&mut self, cmd: &Command) -> Result<(), &'static str> {
-        let task_id = cmd.parameters().task_id().ok_or("task ID required")?;
+    /// `note <id> <text>` appends an annotation; `--edit <n>` replaces the text of
+    /// annotation `n` and `--delete <n>` removes it.
+    pub fn handle_note(&mut self, cmd: &Command) -> Result<()> {
+        let task_id = cmd
+            .parameters()
+            .task_id()
+            .ok_or_else(|| TodoError::invalid("task ID required"))?;
+        let text = cmd.parameters().tasks().join(" ");
+        let label = self.working_set.label(task_id);
+        let task = task_mut(&mut self.projects, task_id)?;
 
-        let mut found = false;
-        'outer: for project in self.projects.iter_mut() {
-            for task in project.tasks.iter_mut() {
-                if task.id() == task_id {
-                    let new_task = task_from_command(cmd, task_id, task.project_id())
-                        .map_err(|_| "invalid command fields")?;
-                    *task = new_task;
-                    found = true;
-                    break 'outer;
+        if let Some(index) = cmd.parameters().delete() {
+            let index = parse_index(index)?;
+            task.remove_annotation(index)?;
+            println!("Annotation {index} removed from task {label}");
+        } else {
+            if text.is_empty() {
+                return Err(TodoError::invalid("annotation text required"));
+            }
+            if let Some(index) = cmd.parameters().edit() {
+                let index = parse_index(index)?;
+                task.edit_annotation(index, text)?;
+                println!("Annotation {index} of task {label} updated");
+            } else {
+                task.annotate(Annotation {
+                    created_at: chrono::Utc::now(),
+                    text,
+                });
+                println!("Task {label} annotated");
+            }
+        }
+
+        self.storage.update_task(task)
+    }
+
+    /// Lists tasks whose name, description or annotations contain the search text.
+    pub fn handle_search(&self, cmd: &Command) -> Result<()> {
+        let needle = cmd.parameters().tasks().join(" ");
+        if needle.is_empty() {
+            return Err(TodoError::invalid("search text required"));
+        }
+
+        let mut entries = Vec::new();
+        for project in &self.projects {
+            let mut matches = project
+                .tasks
+                .iter()
+                .filter(|t| t.matches(&needle))
+                .peekable();
+            if matches.peek().is_none() {
+                continue;
+            }
+            entries.push(Entry::Project {
+                depth: 0,
+                name: &project.name,
+            });
+            entries.extend(matches.map(|task| Entry::Task { depth: 1, task }));
+        }
+
+        if entries.is_empty() {
+            return Err(TodoError::not_found(format!("no tasks match '{needle}'")));
+        }
+        print!("{}", self.renderer(cmd)?.list(&entries));
+        Ok(())
+    }
+
+    /// `track start <id>`, `track stop`, `track add <id> <duration> [--date <day>]`,
+    /// or plain `track` to show the running timer.
+    pub fn handle_track(&mut self, cmd: &Command) -> Result<()> {
+        let now = Utc::now();
+        let action = cmd.parameters().tasks().first().map(String::as_str);
+        let running = self
+            .projects
+            .iter()
+            .flat_map(|p| p.tasks.iter())
+            .find(|t| t.is_tracking())
+            .map(|t| t.id());
+
+        match action {
+            None | Some("status") => match running {
+                Some(id) => {
+                    let task = task_mut(&mut self.projects, id)?;
+                    let name = task.name().clone();
+                    let entry = task.active_time_entry().expect("task is tracking");
+                    println!(
+                        "Tracking task {} ({name}) for {}",
+                        self.working_set.label(id),
+                        format_duration(now - entry.started_at)
+                    );
+                }
+                None => println!("No timer running"),
+            },
+            Some("start") => {
+                let task_id = cmd
+                    .parameters()
+                    .task_id()
+                    .ok_or_else(|| TodoError::invalid("task ID required"))?;
+                if let Some(id) = running {
+                    return Err(TodoError::conflict(format!(
+                        "timer already running on task {}, stop it with 'todo track stop'",
+                        self.working_set.label(id)
+                    )));
+                }
+                let task = task_mut(&mut self.projects, task_id)?;
+                if task.completed_at().is_some() {
+                    return Err(TodoError::invalid("task is already complete"));
+                }
+                task.add_time_entry(TimeEntry {
+                    started_at: now,
+                    ended_at: None,
+                });
+                self.storage.update_task(task)?;
+                println!("Started tracking task {}", self.working_set.label(task_id));
+            }
+            Some("stop") => {
+                let id = running.ok_or_else(|| TodoError::not_found("no timer running"))?;
+                let task = task_mut(&mut self.projects, id)?;
+                let entry = task.active_time_entry().expect("task is tracking");
+                entry.ended_at = Some(now);
+                let spent = now - entry.started_at;
+                self.storage.update_task(task)?;
+                println!(
+                    "Stopped tracking task {} after {}",
+                    self.working_set.label(id),
+                    format_duration(spent)
+                );
+            }
+            Some("add") => {
+                let task_id = cmd
+                    .parameters()
+                    .task_id()
+                    .ok_or_else(|| TodoError::invalid("task ID required"))?;
+                let span = cmd
+                    .parameters()
+                    .tasks()
+                    .get(1)
+                    .ok_or_else(|| TodoError::invalid("duration required, e.g. 1h30m"))?;
+                let duration = parse_duration(span)?;
+
+                // Without --date the interval ends now; with it, it starts at the beginning of that day.
+                let started_at = match cmd.parameters().date() {
+                    Some(date) => local_midnight(parse_date(date, Local::now().date_naive())?),
+                    None => time_before(span, now)?,
+                };
+                let ended_at = started_at
+                    .checked_add_signed(duration)
+                    .ok_or_else(|| out_of_range(span))?;
+                let task = task_mut(&mut self.projects, task_id)?;
+                task.add_time_entry(TimeEntry {
+                    started_at,
+                    ended_at: Some(ended_at),
+                });
+                self.storage.update_task(task)?;
+                println!(
+                    "Added {} to task {}",
+                    format_duration(duration),
+                    self.working_set.label(task_id)
+                );
+            }
+            Some(other) => {
+                return Err(TodoError::invalid(format!(
+                    "unknown track action '{other}', expected start, stop or add"
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn tracked_in_subtree(
+        &self,
+        project_id: usize,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) -> Duration {
+        let own = self
+            .projects
+            .iter()
+            .filter(|p| p.id == project_id)
+            .flat_map(|p| p.tasks.iter())
+            .map(|t| t.tracked_between(from, to, now))
+            .fold(Duration::zero(), |acc, d| acc + d);
+        self.children_of(project_id)
+            .map(|child| self.tracked_in_subtree(child.id, from, to, now))
+            .fold(own, |acc, d| acc + d)
+    }
+
+    fn timesheet_rows(
+        &self,
+        project_id: usize,
+        depth: usize,
+        range: (DateTime<Utc>, DateTime<Utc>, DateTime<Utc>),
+        rows: &mut Vec<(usize, String, Duration)>,
+    ) {
+        let (from, to, now) = range;
+        let total = self.tracked_in_subtree(project_id, from, to, now);
+        let Some(project) = self.projects.iter().find(|p| p.id == project_id) else {
+            return;
+        };
+        if total.is_zero() {
+            return;
+        }
+
+        rows.push((depth, project.name.clone(), total));
+        for task in &project.tasks {
+            let spent = task.tracked_between(from, to, now);
+            if !spent.is_zero() {
+                let name = format!("{}: {}", self.working_set.label(task.id()), task.name());
+                rows.push((depth + 1, name, spent));
+            }
+        }
+        for child in self.children_of(project_id) {
+            self.timesheet_rows(child.id, depth + 1, range, rows);
+        }
+    }
+
+    /// Tracked time for the current week (default) or month, summed per project subtree.
+    pub fn handle_timesheet(&self, cmd: &Command) -> Result<()> {
+        let (project_name, _, _) = cmd.parameters().fields();
+        let project_id = match project_name {
+            Some(name) => self
+                .find_project_id(name)
+                .ok_or_else(|| TodoError::not_found("project not found"))?,
+            None => 0,
+        };
+
+        let today = match cmd.parameters().date() {
+            Some(date) => parse_date(date, Local::now().date_naive())?,
+            None => Local::now().date_naive(),
+        };
+        let (first, last) = match cmd.parameters().period().unwrap_or(Period::Week) {
+            Period::Week => {
+                let monday = today - Duration::days(today.weekday().num_days_from_monday() as i64);
+                (monday, monday + Duration::days(7))
+            }
+            Period::Month => {
+                let first = today.with_day(1).expect("day 1 exists");
+                let next = if first.month() == 12 {
+                    NaiveDate::from_ymd_opt(first.year() + 1, 1, 1)
+                } else {
+                    NaiveDate::from_ymd_opt(first.year(), first.month() + 1, 1)
+                }
+                .expect("first of month exists");
+                (first, next)
+            }
+        };
+
+        let mut rows = Vec::new();
+        let range = (local_midnight(first), local_midnight(last), Utc::now());
+        self.timesheet_rows(project_id, 0, range, &mut rows);
+
+        let r = self.renderer(cmd)?;
+        println!(
+            "{}",
+            r.header(&format!(
+                "Timesheet {} to {}",
+                first,
+                last - Duration::days(1)
+            ))
+        );
+        if rows.is_empty() {
+            println!("No time tracked");
+            return Ok(());
+        }
+        let width = rows
+            .iter()
+            .map(|(depth, name, _)| depth * 2 + name.chars().count())
+            .max()
+            .unwrap_or(0);
+        for (depth, name, spent) in rows {
+            let pad = width - depth * 2 - name.chars().count();
+            println!(
+                "{:indent$}{name}{:pad$}  {:>7}",
+                "",
+                "",
+                format_duration(spent),
+                indent = depth * 2
+            );
+        }
+        Ok(())
+    }
+
+    /// Open-task estimate totals per project subtree: (open tasks, estimated, of which unestimated).
+    fn estimate_in_subtree(&self, project_id: usize) -> (usize, Duration, usize) {
+        let mut open = 0;
+        let mut estimated = Duration::zero();
+        let mut unestimated = 0;
+        for task in self
+            .projects
+            .iter()
+            .filter(|p| p.id == project_id)
+            .flat_map(|p| p.tasks.iter())
+            .filter(|t| t.completed_at().is_none())
+        {
+            open += 1;
+            match task.estimate() {
+                Some(e) => estimated += e,
+                None => unestimated += 1,
+            }
+        }
+        for child in self.children_of(project_id) {
+            let (o, e, u) = self.estimate_in_subtree(child.id);
+            open += o;
+            estimated += e;
+            unestimated += u;
+        }
+        (open, estimated, unestimated)
+    }
+
+    fn estimate_rows(
+        &self,
+        project_id: usize,
+        depth: usize,
+        rows: &mut Vec<(usize, String, (usize, Duration, usize))>,
+    ) {
+        let Some(project) = self.projects.iter().find(|p| p.id == project_id) else {
+            return;
+        };
+        rows.push((
+            depth,
+            project.name.clone(),
+            self.estimate_in_subtree(project_id),
+        ));
+        for child in self.children_of(project_id) {
+            self.estimate_rows(child.id, depth + 1, rows);
+        }
+    }
+
+    /// Ids of `root` and all projects below it.
+    fn subtree_ids(&self, root: usize) -> Vec<usize> {
+        let mut ids = vec![root];
+        let mut i = 0;
+        while i < ids.len() {
+            ids.extend(self.children_of(ids[i]).map(|p| p.id));
+            i += 1;
+        }
+        ids
+    }
+
+    /// Tasks of `root` and all projects below it.
+    fn subtree_tasks(&self, root: usize) -> Vec<&Task> {
+        let subtree = self.subtree_ids(root);
+        self.projects
+            .iter()
+            .filter(|p| subtree.contains(&p.id))
+            .flat_map(|p| p.tasks.iter())
+            .collect()
+    }
+
+    /// Root of the `-p` filter, or the top-level project when none is given.
+    fn filter_root(&self, cmd: &Command) -> Result<usize> {
+        let (project_name, _, _) = cmd.parameters().fields();
+        match project_name {
+            Some(name) => self
+                .find_project_id(name)
+                .ok_or_else(|| TodoError::not_found("project not found")),
+            None => Ok(0),
+        }
+    }
+
+    /// Sums estimates of open tasks per project subtree, then compares estimates
+    /// with tracked time for every task that has both.
+    pub fn handle_estimates(&self, cmd: &Command) -> Result<()> {
+        let (project_name, _, _) = cmd.parameters().fields();
+        let project_id = match project_name {
+            Some(name) => self
+                .find_project_id(name)
+                .ok_or_else(|| TodoError::not_found("project not found"))?,
+            None => 0,
+        };
+        let r = self.renderer(cmd)?;
+
+        let mut rows = Vec::new();
+        self.estimate_rows(project_id, 0, &mut rows);
+        let width = rows
+            .iter()
+            .map(|(depth, name, _)| depth * 2 + name.chars().count())
+            .max()
+            .unwrap_or(0)
+            .max("PROJECT".len());
+        println!(
+            "{}",
+            r.dim(&format!(
+                "{:<width$}  {:>5}  {:>9}  {:>11}",
+                "PROJECT", "OPEN", "ESTIMATE", "UNESTIMATED"
+            ))
+        );
+        for (depth, name, (open, estimated, unestimated)) in &rows {
+            println!(
+                "{:indent$}{:<rest$}  {:>5}  {:>9}  {:>11}",
+                "",
+                name,
+                open,
+                format_duration(*estimated),
+                unestimated,
+                indent = depth * 2,
+                rest = width - depth * 2
+            );
+        }
+
+        let subtree = self.subtree_ids(project_id);
+        let now = Utc::now();
+        let compared: Vec<&Task> = self
+            .projects
+            .iter()
+            .filter(|p| subtree.contains(&p.id))
+            .flat_map(|p| p.tasks.iter())
+            .filter(|t| t.estimate().is_some() && !t.tracked_total(now).is_zero())
+            .collect();
+        if compared.is_empty() {
+            return Ok(());
+        }
+
+        println!();
+        println!(
+            "{}",
+            r.dim(&format!(
+                "{:<5}  {:>9}  {:>9}  {:>6}  NAME",
+                "ID", "ESTIMATE", "ACTUAL", "DIFF"
+            ))
+        );
+        for task in compared {
+            let estimate = task.estimate().expect("filtered on estimate");
+            let actual = task.tracked_total(now);
+            let diff = if estimate.is_zero() {
+                String::from("-")
+            } else {
+                let pct = (actual - estimate).num_minutes() * 100 / estimate.num_minutes();
+                format!("{pct:+}%")
+            };
+            println!(
+                "{:<5}  {:>9}  {:>9}  {:>6}  {}",
+                self.working_set.label(task.id()),
+                format_duration(estimate),
+                format_duration(actual),
+                diff,
+                task.name()
+            );
+        }
+        Ok(())
+    }
+
+    /// Created/completed histogram, lead time, backlog, overdue count and streaks,
+    /// as text or, with `--json`, as JSON.
+    pub fn handle_stats(&self, cmd: &Command) -> Result<()> {
+        let today = Local::now().date_naive();
+        let period = cmd.parameters().period();
+        let since = match cmd.parameters().since() {
+            Some(span) => date_before(span, today)?,
+            None => match period {
+                None => today - Duration::days(13),
+                Some(Period::Week) => today - Duration::weeks(7),
+                Some(Period::Month) => today - Duration::days(365),
+            },
+        };
+        let query = StatsQuery {
+            since,
+            today,
+            period,
+        };
+        let stats = self.storage.stats(&query)?;
+
+        if cmd.parameters().json() {
+            return print_json(&stats);
+        }
+
+        let r = self.renderer(cmd)?;
+        let unit = match period {
+            None => "day",
+            Some(Period::Week) => "week",
+            Some(Period::Month) => "month",
+        };
+        println!("{}", r.header(&format!("Created vs completed per {unit}")));
+        for bucket in &stats.buckets {
+            println!(
+                "  {}  {:>4} created  {:>4} completed",
+                bucket.start, bucket.created, bucket.completed
+            );
+        }
+        println!();
+        match stats.average_lead_time_hours {
+            Some(hours) => println!(
+                "Average lead time: {} over {} completed tasks",
+                format_duration(Duration::minutes((hours * 60.0).round() as i64)),
+                stats.completed_total
+            ),
+            None => println!("Average lead time: no completed tasks"),
+        }
+        println!("Overdue: {}", stats.overdue);
+        println!(
+            "Completion streak: {} days (longest {})",
+            stats.current_streak, stats.longest_streak
+        );
+        println!();
+        println!("{}", r.header("Open backlog"));
+        let width = stats
+            .open_by_project
+            .iter()
+            .map(|p| p.project.chars().count())
+            .max()
+            .unwrap_or(0);
+        for project in &stats.open_by_project {
+            println!("  {:<width$}  {:>4}", project.project, project.open);
+        }
+        Ok(())
+    }
+
+    /// `chart burndown` plots open tasks per day and `chart activity` draws a
+    /// heatmap of completions, both for the `-p` subtree and `--since` window.
+    pub fn handle_chart(&self, cmd: &Command) -> Result<()> {
+        let project_id = self.filter_root(cmd)?;
+        let tasks = self.subtree_tasks(project_id);
+        let project = &self
+            .projects
+            .iter()
+            .find(|p| p.id == project_id)
+            .expect("found above")
+            .name;
+
+        let today = Local::now().date_naive();
+        let kind = cmd.parameters().tasks().first().map(String::as_str);
+        let default_span = match kind {
+            Some("activity") => Duration::weeks(52) - Duration::days(1),
+            _ => Duration::days(13),
+        };
+        let since = match cmd.parameters().since() {
+            Some(span) => date_before(span, today)?,
+            None => today - default_span,
+        };
+        let local_day = |t: DateTime<Utc>| t.with_timezone(&Local).date_naive();
+        let renderer = self.renderer(cmd)?;
+
+        let (text, svg) = match kind {
+            Some("burndown") => {
+                let mut series = Vec::new();
+                let mut day = since;
+                while day <= today {
+                    let open = tasks
+                        .iter()
+                        .filter(|t| local_day(*t.created_at()) <= day)
+                        .filter(|t| t.completed_at().is_none_or(|done| local_day(done) > day))
+                        .count();
+                    series.push((day, open));
+                    day += Duration::days(1);
                 }
+                let title = format!("Open tasks in {project}, {since} to {today}");
+                (
+                    format!("{}\n{}", renderer.header(&title), chart::bars(&series, 8)),
+                    chart::bars_svg(&series, &title),
+                )
+            }
+            Some("activity") => {
+                let mut counts = BTreeMap::new();
+                for done in tasks.iter().filter_map(|t| t.completed_at()) {
+                    let day = local_day(done);
+                    if day >= since && day <= today {
+                        *counts.entry(day).or_insert(0) += 1;
+                    }
+                }
+                let title = format!("Completed tasks in {project}, {since} to {today}");
+                (
+                    format!(
+                        "{}\n{}",
+                        renderer.header(&title),
+                        chart::heatmap(&counts, since, today, &renderer)
+                    ),
+                    chart::heatmap_svg(&counts, since, today, &title),
+                )
+            }
+            _ => {
+                return Err(TodoError::invalid(
+                    "chart type required: burndown or activity",
+                ));
+            }
+        };
+
+        match cmd.parameters().svg() {
+            Some(path) => {
+                fs::write(path, svg)?;
+                println!("Chart written to {path}");
             }
+            None => print!("{text}"),
         }
+        Ok(())
+    }
+
+    /// Open tasks grouped by when they are due, relative to today.
+    pub fn handle_agenda(&self, cmd: &Command) -> Result<()> {
+        let today = Local::now().date_naive();
+        let tomorrow = today + Duration::days(1);
+        let week_end = today + Duration::days(6 - today.weekday().num_days_from_monday() as i64);
+
+        let mut tasks: Vec<&Task> = self
+            .subtree_tasks(self.filter_root(cmd)?)
+            .into_iter()
+            .filter(|t| t.completed_at().is_none())
+            .collect();
+        tasks.sort_by_key(|t| (t.due_time().is_none(), t.due_time(), t.id()));
+
+        let group = |task: &Task| match task.due_time() {
+            Some(due) if due < today => "Overdue",
+            Some(due) if due == today => "Today",
+            Some(due) if due == tomorrow => "Tomorrow",
+            Some(due) if due <= week_end => "This week",
+            Some(_) => "Later",
+            None => "No date",
+        };
 
-        if !found {
-            return Err("task not found");
+        let mut entries = Vec::new();
+        let mut current = None;
+        for task in tasks {
+            let name = group(task);
+            if current != Some(name) {
+                entries.push(Entry::Project { depth: 0, name });
+                current = Some(name);
+            }
+            entries.push(Entry::Task { depth: 1, task });
         }
 
-        self.save();
+        if entries.is_empty() {
+            println!("Nothing to do");
+            return Ok(());
+        }
+        print!("{}", self.renderer(cmd)?.list(&entries));
         Ok(())
     }
 
-    pub fn handle_create_project(&mut self, cmd: &Command) -> Result<(), &'static str> {
+    /// Month grid with the number of open tasks due on each day. `--date` picks
+    /// the month, defaulting to the current one.
+    pub fn handle_calendar(&self, cmd: &Command) -> Result<()> {
+        if cmd.parameters().period() == Some(Period::Week) {
+            return Err(TodoError::invalid("calendar only supports --month"));
+        }
+        let today = Local::now().date_naive();
+        let month = match cmd.parameters().date() {
+            Some(date) => parse_date(date, today)?,
+            None => today,
+        };
+
+        let mut due = BTreeMap::new();
+        for task in self.subtree_tasks(self.filter_root(cmd)?) {
+            if let (Some(day), None) = (task.due_time(), task.completed_at()) {
+                *due.entry(day).or_insert(0) += 1;
+            }
+        }
+        print!("{}", self.renderer(cmd)?.calendar(month, &due));
+        Ok(())
+    }
+
+    /// The most urgent open tasks that aren't blocked, `next [n]` of them.
+    pub fn handle_next(&self, cmd: &Command) -> Result<()> {
+        let count = cmd
+            .parameters()
+            .task_id()
+            .unwrap_or(self.config.urgency.next_count);
+        let root = self.filter_root(cmd)?;
+        let subtree = self.subtree_ids(root);
+
+        let mut ranked: Vec<(f64, &Task, &str)> = self
+            .projects
+            .iter()
+            .filter(|p| subtree.contains(&p.id))
+            .flat_map(|p| p.tasks.iter().map(move |t| (t, p.name.as_str())))
+            .filter(|(t, _)| t.completed_at().is_none() && !self.is_blocked(t))
+            .map(|(t, project)| (self.urgency(t, project).total(), t, project))
+            .collect();
+        ranked.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.1.id().cmp(&b.1.id())));
+        ranked.truncate(count);
+
+        if ranked.is_empty() {
+            println!("Nothing to do");
+            return Ok(());
+        }
+
+        let r = self.renderer(cmd)?;
+        let id_w = ranked
+            .iter()
+            .map(|(_, t, _)| r.id(t).len())
+            .max()
+            .unwrap_or(0)
+            .max("ID".len());
+        let project_w = ranked
+            .iter()
+            .map(|(_, _, p)| p.chars().count())
+            .max()
+            .unwrap_or(0)
+            .max("PROJECT".len());
+        println!(
+            "{}",
+            r.dim(&format!(
+                "{:<id_w$}  {:>5}  {:<10}  {:<project_w$}  NAME",
+                "ID", "URG", "DUE", "PROJECT"
+            ))
+        );
+        for (score, task, project) in ranked {
+            let due_visible = task.due_time().map_or(0, |_| "YYYY-MM-DD".len());
+            println!(
+                "{:<id_w$}  {:>5.1}  {}{:pad$}  {:<project_w$}  {}",
+                r.id(task),
+                score,
+                r.due(task),
+                "",
+                project,
+                r.paint(task.name(), Renderer::priority_style(task.priority())),
+                pad = 10 - due_visible,
+            );
+        }
+        Ok(())
+    }
+
+    pub fn handle_create_project(&mut self, cmd: &Command) -> Result<()> {
         let (project_name, _, _) = cmd.parameters().fields();
 
         if let Some(name) = project_name {
             if self.find_project_id(name).is_some() {
-                return Err("project already exists");
+                return Err(TodoError::conflict("project already exists"));
             }
-            self.create_project(name.clone());
+            self.create_project(name.clone())?;
             println!("Project '{}' created", name);
             Ok(())
         } else if !cmd.parameters().tasks().is_empty() {
             let name = cmd.parameters().tasks()[0].clone();
             if self.find_project_id(&name).is_some() {
-                return Err("project already exists");
+                return Err(TodoError::conflict("project already exists"));
             }
-            self.create_project(name.clone());
+            self.create_project(name.clone())?;
             println!("Project '{}' created", name);
             Ok(())
         } else {
-            Err("project name required")
+            Err(TodoError::invalid("project name required"))
         }
     }
 
-    pub fn handle_remove_project(&mut self, cmd: &Command) -> Result<(), &'static str> {
+    pub fn handle_remove_project(&mut self, cmd: &Command) -> Result<()> {
         let (project_name, _, _) = cmd.parameters().fields();
         let force = cmd.parameters().force();
 
@@ -235,78 +1337,838 @@ impl AppState {
         } else if !cmd.parameters().tasks().is_empty() {
             cmd.parameters().tasks()[0].clone()
         } else {
-            return Err("project name required");
+            return Err(TodoError::invalid("project name required"));
         };
 
-        let project_id = self.find_project_id(&name).ok_or("project not found")?;
+        let project_id = self
+            .find_project_id(&name)
+            .ok_or_else(|| TodoError::not_found("project not found"))?;
 
         // Don't allow removing Home project
         if project_id == 0 {
-            return Err("cannot remove Home project");
+            return Err(TodoError::invalid("cannot remove Home project"));
         }
 
         // Check if project has tasks
         if let Some(project) = self.projects.iter().find(|p| p.id == project_id) {
             if !project.tasks.is_empty() && !force {
-                return Err("project has tasks, use --force to remove anyway");
+                return Err(TodoError::conflict(
+                    "project has tasks, use --force to remove anyway",
+                ));
             }
         }
 
+        self.storage.trash_project(project_id, Utc::now())?;
         self.projects.retain(|p| p.id != project_id);
-        self.save();
-        println!("Project '{}' removed", name);
+        println!("Project '{}' moved to the trash", name);
+        Ok(())
+    }
+
+    /// Ids and names by uuid of tasks wherever they are now: active, archived or
+    /// trashed.
+    fn known_tasks(&self) -> Result<BTreeMap<String, (usize, String)>> {
+        let mut known = BTreeMap::new();
+        let trash = self.storage.load_trash()?;
+        let archive = self.storage.load_archive()?;
+        let tasks = self
+            .projects
+            .iter()
+            .chain(trash.projects.iter().map(|p| &p.project))
+            .flat_map(|p| p.tasks.iter())
+            .chain(trash.tasks.iter().map(|t| &t.task))
+            .chain(archive.iter());
+        for task in tasks {
+            known.insert(task.uuid().to_string(), (task.id(), task.name().clone()));
+        }
+        Ok(known)
+    }
+
+    fn project_name(&self, project_id: usize) -> Option<String> {
+        self.projects
+            .iter()
+            .find(|p| p.id == project_id)
+            .map(|p| p.name.clone())
+    }
+
+    /// `history <id>` shows every recorded change to one task, oldest first.
+    pub fn handle_history(&self, cmd: &Command) -> Result<()> {
+        let task_id = cmd
+            .parameters()
+            .task_id()
+            .ok_or_else(|| TodoError::invalid("task ID required"))?;
+        let no_history = || {
+            TodoError::not_found(format!(
+                "no history for task {}",
+                self.working_set.label(task_id)
+            ))
+        };
+        let (uuid, (_, name)) = self
+            .known_tasks()?
+            .into_iter()
+            .find(|(_, (id, _))| *id == task_id)
+            .ok_or_else(no_history)?;
+        let changes = self.storage.load_history(Some(&uuid), None)?;
+        if changes.is_empty() {
+            return Err(no_history());
+        }
+
+        let r = self.renderer(cmd)?;
+        println!(
+            "{}",
+            r.header(&format!(
+                "Task {}: {}",
+                self.working_set.label(task_id),
+                name
+            ))
+        );
+        for change in &changes {
+            let when = change
+                .at
+                .with_timezone(&Local)
+                .format(&self.config.date_format);
+            println!(
+                "{}  {}",
+                r.dim(&when.to_string()),
+                change.describe(|id| self.project_name(id))
+            );
+        }
+        Ok(())
+    }
+
+    /// `log [--since 1w]` shows changes to all tasks, oldest first.
+    pub fn handle_log(&self, cmd: &Command) -> Result<()> {
+        let since = match cmd.parameters().since() {
+            Some(span) => time_before(span, Utc::now())?,
+            None => Utc::now() - Duration::weeks(1),
+        };
+        let changes = self.storage.load_history(None, Some(since))?;
+        if changes.is_empty() {
+            println!("No changes");
+            return Ok(());
+        }
+
+        let r = self.renderer(cmd)?;
+        // Ids are looked up by uuid: the one recorded may since have gone to
+        // another task. Deleted tasks have none.
+        let known = self.known_tasks()?;
+        let label = |change: &Change| match known.get(&change.task_uuid) {
+            Some((id, _)) => self.working_set.label(*id),
+            None => String::from("-"),
+        };
+        let id_w = changes.iter().map(|c| label(c).len()).max().unwrap_or(0);
+        for change in &changes {
+            let when = change
+                .at
+                .with_timezone(&Local)
+                .format(&self.config.date_format);
+            let name = known
+                .get(&change.task_uuid)
+                .map(|(_, name)| name.as_str())
+                .unwrap_or("(deleted)");
+            println!(
+                "{}  {:>id_w$}  {}  {}",
+                r.dim(&when.to_string()),
+                label(change),
+                r.header(name),
+                change.describe(|id| self.project_name(id))
+            );
+        }
         Ok(())
     }
 
-    pub fn handle_show(&self, cmd: &Command) -> Result<(), &'static str> {
-        let task_id = cmd.parameters().task_id().ok_or("task ID required")?;
+    /// `sync [remote] [--ours|--theirs]` merges the local data with the sync
+    /// repository: it pulls, merges field by field against the last state both
+    /// sides shared, commits the result and pushes it.
+    pub fn handle_sync(&mut self, cmd: &Command) -> Result<()> {
+        let url = cmd
+            .parameters()
+            .tasks()
+            .first()
+            .cloned()
+            .or_else(|| self.config.sync.remote.clone());
+        let branch = self.config.sync.branch.clone();
+        let dir = self
+            .config
+            .sync
+            .dir
+            .clone()
+            .unwrap_or_else(sync::default_dir);
+        let repo = Repo::open(&dir, url.as_deref(), &branch)?;
 
+        let head = repo.head();
+        let theirs = match url {
+            Some(_) => repo.fetch(&branch)?,
+            None => None,
+        };
+        let base = match (&head, &theirs) {
+            (Some(head), Some(theirs)) => repo.merge_base(head, theirs),
+            (head, _) => head.clone(),
+        };
+        let base = match base {
+            Some(commit) => repo.read(&commit)?,
+            None => Snapshot::new(),
+        };
+        let local = sync::snapshot(
+            &self.projects,
+            &self.storage.load_archive()?,
+            &self.storage.load_trash()?,
+        )?;
+        let remote = match &theirs {
+            Some(commit) => repo.read(commit)?,
+            None => base.clone(),
+        };
+
+        let (merged, conflicts) = sync::merge(&base, &local, &remote, cmd.parameters().side());
+        if !conflicts.is_empty() {
+            for conflict in &conflicts {
+                eprintln!("conflict: {}", conflict.describe());
+            }
+            return Err(TodoError::conflict(format!(
+                "{} conflicting change(s); make both sides agree or run todo sync --ours or --theirs",
+                conflicts.len()
+            )));
+        }
+
+        // Take in the result before committing it, so that HEAD never records a
+        // state the local data doesn't have.
+        let received = changed_files(&local, &merged);
+        self.import_snapshot(&local, &merged)?;
+
+        repo.write(&merged)?;
+        let parents: Vec<String> = match (head, theirs.clone()) {
+            (Some(head), Some(theirs)) if repo.is_ancestor(&theirs, &head) => vec![head],
+            (Some(head), Some(theirs)) if repo.is_ancestor(&head, &theirs) => vec![theirs],
+            (Some(head), Some(theirs)) => vec![head, theirs],
+            (head, theirs) => head.or(theirs).into_iter().collect(),
+        };
+        let commit = repo.commit(&parents, "todo sync")?;
+        let mut sent = 0;
+        if url.is_some() && theirs.as_ref() != Some(&commit) {
+            repo.push(&branch)?;
+            sent = changed_files(&remote, &merged);
+        }
+        println!("Synced: {received} file(s) updated here, {sent} sent");
+        Ok(())
+    }
+
+    /// Brings the local data from the `local` snapshot to `merged`, in one
+    /// storage transaction so that a failure partway changes nothing.
+    fn import_snapshot(&mut self, local: &Snapshot, merged: &Snapshot) -> Result<()> {
+        let storage = &*self.storage;
+        let projects = &mut self.projects;
+        let result = storage.transaction(&mut || apply_snapshot(storage, projects, local, merged));
+        self.projects = self.storage.load_projects()?;
+        result
+    }
+
+    /// `__complete projects|tasks` prints project names, or the open tasks as
+    /// `id<TAB>name`, one per line, for the shell completion scripts.
+    pub fn handle_query(&self, cmd: &Command) -> Result<()> {
+        match cmd.parameters().tasks().first().map(String::as_str) {
+            Some("projects") => {
+                for project in &self.projects {
+                    println!("{}", project.name);
+                }
+            }
+            Some("tasks") => {
+                let open = self
+                    .projects
+                    .iter()
+                    .flat_map(|p| p.tasks.iter())
+                    .filter(|t| t.completed_at().is_none());
+                for task in open {
+                    println!("{}\t{}", self.working_set.label(task.id()), task.name());
+                }
+            }
+            _ => return Err(TodoError::invalid("expected projects or tasks")),
+        }
+        Ok(())
+    }
+
+    /// `gc` numbers the open tasks from 1 again, in the order they were added.
+    /// Completed, archived and removed tasks leave the working set.
+    pub fn handle_gc(&mut self, cmd: &Command) -> Result<()> {
+        if !self.config.working_set {
+            return Err(TodoError::invalid(
+                "working-set numbers are off; turn them on with todo config set working_set true",
+            ));
+        }
+        let mut open: Vec<&Task> = self
+            .projects
+            .iter()
+            .flat_map(|p| p.tasks.iter())
+            .filter(|t| t.completed_at().is_none())
+            .collect();
+        open.sort_by_key(|t| t.id());
+        let ids: Vec<usize> = open.iter().map(|t| t.id()).collect();
+        self.storage.save_working_set(&ids)?;
+
+        if cmd.parameters().json() {
+            let mapping: Vec<serde_json::Value> = open
+                .iter()
+                .enumerate()
+                .map(|(i, t)| serde_json::json!({"number": i + 1, "id": t.id(), "uuid": t.uuid()}))
+                .collect();
+            print_json(&mapping)?;
+        } else {
+            println!("Renumbered {} open task(s)", ids.len());
+        }
+        self.working_set = WorkingSet::new(ids);
+        Ok(())
+    }
+
+    /// `merge <file>` folds another copy of the database into this one, e.g. one
+    /// that was used offline on another machine.
+    pub fn handle_merge(&mut self, cmd: &Command) -> Result<()> {
+        let path = cmd
+            .parameters()
+            .tasks()
+            .first()
+            .ok_or_else(|| TodoError::invalid("database file required"))?;
+        let report = self.storage.merge_from(Path::new(path))?;
+        self.projects = self.storage.load_projects()?;
+        println!(
+            "Merged {path}: {} added, {} updated, {} removed",
+            report.added, report.updated, report.removed
+        );
+        Ok(())
+    }
+
+    /// `trash [list]`, `trash restore <id>`, `trash restore <project>` and `trash empty`.
+    pub fn handle_trash(&mut self, cmd: &Command) -> Result<()> {
+        let action = cmd.parameters().tasks().first().map(String::as_str);
+        match action {
+            None | Some("list") => self.list_trash(cmd),
+            Some("restore") => {
+                let trash = self.storage.load_trash()?;
+                if let Some(task_id) = cmd.parameters().task_id() {
+                    let trashed = trash
+                        .tasks
+                        .iter()
+                        .find(|t| t.task.id() == task_id)
+                        .ok_or_else(|| TodoError::not_found("task is not in the trash"))?;
+                    if let Some(project) = trash
+                        .projects
+                        .iter()
+                        .find(|p| p.project.id == trashed.task.project_id())
+                    {
+                        return Err(TodoError::conflict(format!(
+                            "project '{}' is in the trash, restore it first",
+                            project.project.name
+                        )));
+                    }
+                    self.storage.restore_task(task_id)?;
+                    println!("Task {} restored", self.working_set.label(task_id));
+                } else {
+                    let (project_name, _, _) = cmd.parameters().fields();
+                    let name = project_name
+                        .as_ref()
+                        .or(cmd.parameters().tasks().get(1))
+                        .ok_or_else(|| TodoError::invalid("task ID or project name required"))?;
+                    let trashed = trash
+                        .projects
+                        .iter()
+                        .rev()
+                        .find(|p| &p.project.name == name)
+                        .ok_or_else(|| TodoError::not_found("project is not in the trash"))?;
+                    if self.find_project_id(name).is_some() {
+                        return Err(TodoError::conflict(format!(
+                            "a project named '{name}' already exists"
+                        )));
+                    }
+                    self.storage.restore_project(trashed.project.id)?;
+                    println!("Project '{name}' restored");
+                }
+                self.projects = self.storage.load_projects()?;
+                Ok(())
+            }
+            Some("empty") => {
+                let trash = self.storage.load_trash()?;
+                let count = trash.projects.len() + trash.tasks.len();
+                if count == 0 {
+                    println!("The trash is empty");
+                    return Ok(());
+                }
+                if !self.confirm(cmd, &format!("Permanently delete {count} trashed item(s)?"))? {
+                    return Err(TodoError::cancelled("trash not emptied"));
+                }
+                self.empty_trash(Utc::now())?;
+                println!("Deleted {count} item(s)");
+                Ok(())
+            }
+            Some(other) => Err(TodoError::invalid(format!(
+                "unknown trash action '{other}', expected list, restore or empty"
+            ))),
+        }
+    }
+
+    fn list_trash(&self, cmd: &Command) -> Result<()> {
+        let Trash { projects, tasks } = self.storage.load_trash()?;
+        if projects.is_empty() && tasks.is_empty() {
+            println!("The trash is empty");
+            return Ok(());
+        }
+
+        let r = self.renderer(cmd)?;
+        let mut rows: Vec<(DateTime<Utc>, String, String)> = Vec::new();
+        for trashed in &projects {
+            rows.push((
+                trashed.trashed_at,
+                String::new(),
+                format!(
+                    "project {} ({} tasks)",
+                    trashed.project.name,
+                    trashed.project.tasks.len()
+                ),
+            ));
+        }
+        for trashed in &tasks {
+            rows.push((
+                trashed.trashed_at,
+                self.working_set.label(trashed.task.id()),
+                trashed.task.name().clone(),
+            ));
+        }
+        rows.sort_by_key(|(at, _, _)| *at);
+
+        let dates: Vec<String> = rows
+            .iter()
+            .map(|(at, _, _)| {
+                at.with_timezone(&Local)
+                    .format(&self.config.date_format)
+                    .to_string()
+            })
+            .collect();
+        let date_w = dates
+            .iter()
+            .map(|d| d.chars().count())
+            .max()
+            .unwrap_or(0)
+            .max("TRASHED".len());
+        let id_w = rows
+            .iter()
+            .map(|(_, id, _)| id.len())
+            .max()
+            .unwrap_or(0)
+            .max("ID".len());
+        println!(
+            "{}",
+            r.dim(&format!("{:<date_w$}  {:<id_w$}  NAME", "TRASHED", "ID"))
+        );
+        for ((_, id, name), date) in rows.iter().zip(&dates) {
+            println!("{date:<date_w$}  {id:<id_w$}  {name}");
+        }
+        Ok(())
+    }
+
+    /// Permanently deletes what was trashed before `before` and drops
+    /// dependencies on the deleted tasks.
+    fn empty_trash(&mut self, before: DateTime<Utc>) -> Result<()> {
+        let trash = self.storage.load_trash()?;
+        let deleted: Vec<usize> = trash
+            .tasks
+            .iter()
+            .filter(|t| t.trashed_at < before)
+            .map(|t| t.task.id())
+            .chain(
+                trash
+                    .projects
+                    .iter()
+                    .filter(|p| p.trashed_at < before)
+                    .flat_map(|p| p.project.tasks.iter().map(|t| t.id())),
+            )
+            .collect();
+        if deleted.is_empty() && trash.projects.iter().all(|p| p.trashed_at >= before) {
+            return Ok(());
+        }
+        self.storage.empty_trash(before)?;
+
+        for task in self.projects.iter_mut().flat_map(|p| p.tasks.iter_mut()) {
+            if task.depends_on().iter().any(|d| deleted.contains(d)) {
+                let remaining = task
+                    .depends_on()
+                    .iter()
+                    .copied()
+                    .filter(|d| !deleted.contains(d))
+                    .collect();
+                task.set_depends_on(remaining);
+                self.storage.update_task(task)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn handle_show(&self, cmd: &Command) -> Result<()> {
+        let task_id = cmd
+            .parameters()
+            .task_id()
+            .ok_or_else(|| TodoError::invalid("task ID required"))?;
+
+        let r = self.renderer(cmd)?;
         for project in &self.projects {
             for task in &project.tasks {
                 if task.id() == task_id {
-                    println!("Task ID: {}", task.id());
-                    println!("Name: {}", task.name());
+                    if cmd.parameters().json() {
+                        return print_json(task);
+                    }
+                    match self.working_set.number(task.id()) {
+                        Some(number) => println!("Task ID: {number} (#{})", task.id()),
+                        None => println!("Task ID: {}", self.working_set.label(task.id())),
+                    }
+                    println!("UUID: {}", task.uuid());
+                    println!("Name: {}", r.header(task.name()));
                     println!("Project: {}", project.name);
                     println!("Description: {}", task.description());
-                    println!("Priority: {:?}", task.priority());
-                    println!("Created: {}", task.created_at().format("%Y-%m-%d %H:%M:%S"));
-                    if let Some(due) = task.due_time() {
-                        println!("Due: {}", due);
+                    println!(
+                        "Priority: {}",
+                        r.paint(
+                            &format!("{:?}", task.priority()),
+                            Renderer::priority_style(task.priority())
+                        )
+                    );
+                    println!("Status: {}", r.status(task));
+                    println!(
+                        "Created: {} ({} ago)",
+                        task.created_at()
+                            .with_timezone(&Local)
+                            .format(&self.config.date_format),
+                        r.age(task)
+                    );
+                    if task.due_time().is_some() {
+                        println!("Due: {}", r.due(task));
+                    }
+                    let tracked = task.tracked_total(Utc::now());
+                    match task.estimate() {
+                        Some(estimate) => println!(
+                            "Estimate: {} (tracked {})",
+                            format_duration(estimate),
+                            format_duration(tracked)
+                        ),
+                        None if !tracked.is_zero() => {
+                            println!("Tracked: {}", format_duration(tracked))
+                        }
+                        None => {}
                     }
                     if let Some(completed) = task.completed_at() {
-                        println!("Completed: {}", completed.format("%Y-%m-%d %H:%M:%S"));
+                        let completed = completed.with_timezone(&Local);
+                        println!("Completed: {}", completed.format(&self.config.date_format));
+                    }
+                    if !task.tags().is_empty() {
+                        println!("Tags: {}", task.tags().join(", "));
+                    }
+                    if !task.depends_on().is_empty() {
+                        let ids: Vec<String> = task
+                            .depends_on()
+                            .iter()
+                            .map(|&id| self.working_set.label(id))
+                            .collect();
+                        let blocked = if self.is_blocked(task) {
+                            " (blocked)"
+                        } else {
+                            ""
+                        };
+                        println!("Depends on: {}{blocked}", ids.join(", "));
+                    }
+                    if task.completed_at().is_none() {
+                        let urgency = self.urgency(task, &project.name);
+                        println!("Urgency: {:.2}", urgency.total());
+                        for term in &urgency.terms {
+                            println!(
+                                "  {:<16} {:>5.2} * {:>5.2} = {:>6.2}",
+                                term.name,
+                                term.factor,
+                                term.coefficient,
+                                term.value()
+                            );
+                        }
+                    }
+                    if !task.annotations().is_empty() {
+                        println!("Annotations:");
+                        for (i, annotation) in task.annotations().iter().enumerate() {
+                            let when = annotation
+                                .created_at
+                                .with_timezone(&Local)
+                                .format(&self.config.date_format);
+                            println!(
+                                "  {}. {}  {}",
+                                i + 1,
+                                r.dim(&when.to_string()),
+                                annotation.text
+                            );
+                        }
                     }
                     return Ok(());
                 }
             }
         }
 
-        Err("task not found")
+        Err(TodoError::not_found("task not found"))
     }
 
-    pub fn handle_complete(&mut self, cmd: &Command) -> Result<(), &'static str> {
-        let task_id = cmd.parameters().task_id().ok_or("task ID required")?;
+    pub fn handle_complete(&mut self, cmd: &Command) -> Result<()> {
+        let task_id = cmd
+            .parameters()
+            .task_id()
+            .ok_or_else(|| TodoError::invalid("task ID required"))?;
 
-        let mut found = false;
-        for project in self.projects.iter_mut() {
-            for task in project.tasks.iter_mut() {
-                if task.id() == task_id {
-                    task.mark_complete();
-                    found = true;
-                    println!("Task {} marked as complete", task_id);
-                    break;
-                }
+        let task = task_mut(&mut self.projects, task_id)?;
+        task.mark_complete();
+        // A running timer stops with the task, in the same write.
+        let stopped = task.active_time_entry().map(|entry| {
+            let now = Utc::now();
+            entry.ended_at = Some(now);
+            now - entry.started_at
+        });
+        self.storage.update_task(task)?;
+        println!(
+            "Task {} marked as complete",
+            self.working_set.label(task_id)
+        );
+        if let Some(spent) = stopped {
+            println!(
+                "Stopped tracking task {} after {}",
+                self.working_set.label(task_id),
+                format_duration(spent)
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Does the work of `import_snapshot`. Tasks and projects that went away
+/// elsewhere are moved to the trash, and ones that come back are restored from
+/// it. Ones new here get the next free ids.
+fn apply_snapshot(
+    storage: &dyn Storage,
+    projects: &mut Vec<Project>,
+    local: &Snapshot,
+    merged: &Snapshot,
+) -> Result<()> {
+    let trash = storage.load_trash()?;
+    let archive = storage.load_archive()?;
+    let now = Utc::now();
+
+    // Local ids by uuid, of trashed projects too so that they can be restored.
+    let mut project_ids: BTreeMap<String, usize> = projects
+        .iter()
+        .chain(trash.projects.iter().map(|p| &p.project))
+        .map(|p| (p.uuid.clone(), p.id))
+        .collect();
+    // A project that `merge` joined with one of the same name takes on the
+    // uuid of that one's file.
+    let mut adopted = BTreeSet::new();
+    for path in merged.keys() {
+        let Some(uuid) = sync::path_uuid(path, "projects") else {
+            continue;
+        };
+        if project_ids.contains_key(uuid) {
+            continue;
+        }
+        let name = merged[path].get("name").and_then(serde_json::Value::as_str);
+        let same_name = projects.iter().find(|p| {
+            Some(p.name.as_str()) == name && !merged.contains_key(&sync::project_path(&p.uuid))
+        });
+        let id = match same_name {
+            Some(project) => {
+                adopted.insert(project.id);
+                project.id
             }
-            if found {
-                break;
+            None => storage.allocate_project_id()?,
+        };
+        project_ids.insert(uuid.to_string(), id);
+    }
+
+    for (path, value) in merged {
+        let Some(uuid) = sync::path_uuid(path, "projects") else {
+            continue;
+        };
+        if local.get(path) == Some(value) {
+            continue;
+        }
+        let file = sync::project_from_value(path, value)?;
+        let id = project_ids[uuid];
+        let project = Project {
+            id,
+            uuid: uuid.to_string(),
+            name: file.name,
+            parent_id: file
+                .parent
+                .and_then(|parent| project_ids.get(&parent).copied())
+                .unwrap_or(0),
+            tasks: Vec::new(),
+        };
+        if local.contains_key(path) || adopted.contains(&id) {
+            storage.update_project(&project)?;
+        } else if trash.projects.iter().any(|p| p.project.id == id) {
+            storage.restore_project(id)?;
+            storage.update_project(&project)?;
+        } else {
+            storage.insert_project(&project)?;
+        }
+    }
+    *projects = storage.load_projects()?;
+
+    let mut task_ids: BTreeMap<String, usize> = projects
+        .iter()
+        .flat_map(|p| p.tasks.iter())
+        .chain(&archive)
+        .chain(trash.tasks.iter().map(|t| &t.task))
+        .chain(trash.projects.iter().flat_map(|p| p.project.tasks.iter()))
+        .map(|t| (t.uuid().to_string(), t.id()))
+        .collect();
+    for path in merged.keys() {
+        if let Some(uuid) = sync::path_uuid(path, "tasks") {
+            if !task_ids.contains_key(uuid) {
+                task_ids.insert(uuid.to_string(), storage.allocate_task_id()?);
             }
         }
+    }
 
-        if !found {
-            return Err("task not found");
+    for (path, value) in merged {
+        let Some(uuid) = sync::path_uuid(path, "tasks") else {
+            continue;
+        };
+        let old = local.get(path);
+        if old == Some(value) {
+            continue;
         }
+        if old.is_some_and(|old| old.get("archived").is_some()) {
+            // The archive is read-only.
+            continue;
+        }
+        let file = sync::task_from_value(path, value)?;
+        let id = task_ids[uuid];
+        let mut task = file.task;
+        task.set_id(id);
+        task.set_project_id(*project_ids.get(&file.project).ok_or_else(|| {
+            TodoError::format(format!("{path}: project {} not found", file.project))
+        })?);
+        task.set_depends_on(
+            file.depends_on
+                .iter()
+                .filter_map(|uuid| task_ids.get(uuid).copied())
+                .collect(),
+        );
 
-        self.save();
-        Ok(())
+        if trash.tasks.iter().any(|t| t.task.id() == id) {
+            storage.restore_task(id)?;
+            *projects = storage.load_projects()?;
+        }
+        // Also true of a task that came back with its project above.
+        match task_mut(projects, id) {
+            Ok(stored) => {
+                task.set_version(stored.version());
+                storage.update_task(&mut task)?;
+            }
+            Err(_) => storage.insert_task(&task)?,
+        }
+        if file.archived {
+            storage.archive_tasks(&[id])?;
+        }
+        *projects = storage.load_projects()?;
+    }
+
+    for path in local.keys().filter(|p| !merged.contains_key(*p)) {
+        if let Some(uuid) = sync::path_uuid(path, "tasks") {
+            let id = task_ids[uuid];
+            if local[path].get("archived").is_some() {
+                storage.purge_archived(&[id])?;
+            } else {
+                storage.trash_task(id, now)?;
+            }
+        }
+    }
+    for path in local.keys().filter(|p| !merged.contains_key(*p)) {
+        if let Some(uuid) = sync::path_uuid(path, "projects") {
+            let id = project_ids[uuid];
+            if !adopted.contains(&id) {
+                storage.trash_project(id, now)?;
+            }
+        }
+    }
+    *projects = storage.load_projects()?;
+    Ok(())
+}
+
+fn local_midnight(date: NaiveDate) -> DateTime<Utc> {
+    date.and_hms_opt(0, 0, 0)
+        .expect("midnight exists")
+        .and_local_timezone(Local)
+        .earliest()
+        .map(|dt| dt.with_timezone(&Utc))
+        .unwrap_or_else(|| {
+            date.and_hms_opt(0, 0, 0)
+                .expect("midnight exists")
+                .and_utc()
+        })
+}
+
+/// How many files differ between two snapshots, counting additions and deletions.
+fn changed_files(from: &Snapshot, to: &Snapshot) -> usize {
+    let changed = to
+        .iter()
+        .filter(|(path, value)| from.get(*path) != Some(value));
+    changed.count() + from.keys().filter(|path| !to.contains_key(*path)).count()
+}
+
+fn print_json<T: serde::Serialize + ?Sized>(value: &T) -> Result<()> {
+    let json = serde_json::to_string_pretty(value).map_err(|e| TodoError::format(e.to_string()))?;
+    println!("{json}");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hierarchy::Priority;
+
+    fn state_with_task() -> (AppState, Task) {
+        let mut state =
+            AppState::with_storage(Box::new(Database::open_in_memory().unwrap())).unwrap();
+        let mut task = Task::new(
+            7,
+            0,
+            String::from("Task"),
+            String::new(),
+            Priority::None,
+            Utc::now(),
+            None,
+            None,
+        );
+        task.set_uuid(new_uuid());
+        state.storage.insert_task(&task).unwrap();
+        state.projects[0].tasks.push(task.clone());
+        (state, task)
+    }
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn uuids_resolve_to_ids_and_project_names() {
+        let (state, task) = state_with_task();
+        let project = state.projects[0].uuid.clone();
+        let line = format!(
+            "todo modify {} --depends -{} -p {project}",
+            task.uuid(),
+            task.uuid()
+        );
+        let resolved = state.resolve_uuids(args(&line)).unwrap();
+        assert_eq!(resolved, args("todo modify #7 --depends -#7 -p Home"));
+        assert_eq!(
+            state.resolve_uuids(args("todo show 7")).unwrap(),
+            args("todo show 7")
+        );
+    }
+
+    #[test]
+    fn unknown_uuids_are_not_found() {
+        let (state, _) = state_with_task();
+        let line = format!("todo show {}", new_uuid());
+        assert!(matches!(
+            state.resolve_uuids(args(&line)),
+            Err(TodoError::NotFound(_))
+        ));
     }
 }
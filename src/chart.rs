@@ -0,0 +1,302 @@
+use std::{collections::BTreeMap, fmt::Write};
+
+use chrono::{Datelike, Duration, NaiveDate};
+
+use crate::render::Renderer;
+
+const BLOCKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+const HEAT: [char; 5] = ['·', '░', '▒', '▓', '█'];
+const SVG_GREENS: [&str; 5] = ["#ebedf0", "#9be9a8", "#40c463", "#30a14e", "#216e39"];
+
+/// Vertical bar chart of one value per day, `height` rows tall, with the
+/// maximum on the y axis and the first and last dates under the x axis.
+pub fn bars(series: &[(NaiveDate, usize)], height: usize) -> String {
+    let max = series.iter().map(|(_, v)| *v).max().unwrap_or(0).max(1);
+    let label_w = max.to_string().len();
+    // Each row covers 8 sub-steps so partial blocks give finer resolution.
+    let steps = height * 8;
+
+    let mut out = String::new();
+    for row in (0..height).rev() {
+        let label = match row {
+            r if r == height - 1 => max.to_string(),
+            0 => String::from("0"),
+            _ => String::new(),
+        };
+        let _ = write!(out, "{label:>label_w$} │");
+        for (_, value) in series {
+            let filled = value * steps / max;
+            let cell = match filled.saturating_sub(row * 8) {
+                0 => ' ',
+                n if n >= 8 => BLOCKS[7],
+                n => BLOCKS[n - 1],
+            };
+            out.push(cell);
+            out.push(cell);
+        }
+        out.push('\n');
+    }
+
+    let _ = writeln!(out, "{:label_w$} └{}", "", "─".repeat(series.len() * 2));
+    if let (Some((first, _)), Some((last, _))) = (series.first(), series.last()) {
+        let first = first.format("%m-%d").to_string();
+        let last = last.format("%m-%d").to_string();
+        let gap = (series.len() * 2).saturating_sub(first.len() + last.len());
+        let _ = writeln!(out, "{:label_w$}  {first}{}{last}", "", " ".repeat(gap));
+    }
+    out
+}
+
+/// Intensity level 0-4 of `count` relative to the busiest day.
+fn level(count: usize, max: usize) -> usize {
+    match count {
+        0 => 0,
+        c => (c * 4).div_ceil(max.max(1)).clamp(1, 4),
+    }
+}
+
+/// Monday on or before `from`, so heatmap columns are whole weeks.
+fn week_start(date: NaiveDate) -> NaiveDate {
+    date - Duration::days(date.weekday().num_days_from_monday() as i64)
+}
+
+/// GitHub-style heatmap: one row per weekday, one column per week.
+pub fn heatmap(
+    counts: &BTreeMap<NaiveDate, usize>,
+    from: NaiveDate,
+    to: NaiveDate,
+    r: &Renderer,
+) -> String {
+    let start = week_start(from);
+    let weeks = ((to - start).num_days() / 7 + 1) as usize;
+    let max = counts.values().copied().max().unwrap_or(0);
+
+    let mut out = String::new();
+    // Month names above the first week of each month, skipped where they would collide.
+    let mut months = String::from("    ");
+    let mut previous_month = None;
+    for week in 0..weeks {
+        let monday = start + Duration::weeks(week as i64);
+        let column = 4 + week * 2;
+        if previous_month != Some(monday.month()) && months.len() < column {
+            months.push_str(&" ".repeat(column - months.len()));
+            months.push_str(&monday.format("%b").to_string());
+        }
+        previous_month = Some(monday.month());
+    }
+    let _ = writeln!(out, "{}", months.trim_end());
+
+    for weekday in 0..7 {
+        let label = match weekday {
+            0 => "Mon",
+            2 => "Wed",
+            4 => "Fri",
+            _ => "",
+        };
+        let _ = write!(out, "{label:<3} ");
+        for week in 0..weeks {
+            let day = start + Duration::days(week as i64 * 7 + weekday);
+            if day < from || day > to {
+                out.push_str("  ");
+                continue;
+            }
+            let count = counts.get(&day).copied().unwrap_or(0);
+            let lvl = level(count, max);
+            out.push_str(&r.paint(&HEAT[lvl].to_string(), Renderer::heat_style(lvl)));
+            out.push(' ');
+        }
+        out.push('\n');
+    }
+
+    let _ = write!(out, "    Less ");
+    for (lvl, cell) in HEAT.iter().enumerate() {
+        out.push_str(&r.paint(&cell.to_string(), Renderer::heat_style(lvl)));
+        out.push(' ');
+    }
+    let _ = writeln!(out, "More   (max {max} per day)");
+    out
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Standalone SVG line chart of one value per day.
+pub fn bars_svg(series: &[(NaiveDate, usize)], title: &str) -> String {
+    let (w, h, pad) = (640.0, 240.0, 40.0);
+    let max = series.iter().map(|(_, v)| *v).max().unwrap_or(0).max(1) as f64;
+    let step = if series.len() > 1 {
+        (w - 2.0 * pad) / (series.len() - 1) as f64
+    } else {
+        0.0
+    };
+    let point = |i: usize, v: usize| {
+        (
+            pad + i as f64 * step,
+            h - pad - v as f64 / max * (h - 2.0 * pad),
+        )
+    };
+
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}" font-family="sans-serif" font-size="12">"#
+    );
+    let _ = writeln!(svg, r#"  <rect width="100%" height="100%" fill="white"/>"#);
+    let _ = writeln!(
+        svg,
+        r#"  <text x="{pad}" y="24" font-size="14">{}</text>"#,
+        escape(title)
+    );
+    let _ = writeln!(
+        svg,
+        r##"  <line x1="{pad}" y1="{}" x2="{}" y2="{}" stroke="#999"/>"##,
+        h - pad,
+        w - pad,
+        h - pad
+    );
+    let _ = writeln!(
+        svg,
+        r##"  <line x1="{pad}" y1="{pad}" x2="{pad}" y2="{}" stroke="#999"/>"##,
+        h - pad
+    );
+    let _ = writeln!(
+        svg,
+        r#"  <text x="{}" y="{}" text-anchor="end">{max}</text>"#,
+        pad - 6.0,
+        pad + 4.0
+    );
+    let _ = writeln!(
+        svg,
+        r#"  <text x="{}" y="{}" text-anchor="end">0</text>"#,
+        pad - 6.0,
+        h - pad + 4.0
+    );
+
+    let points: Vec<String> = series
+        .iter()
+        .enumerate()
+        .map(|(i, (_, v))| {
+            let (x, y) = point(i, *v);
+            format!("{x:.1},{y:.1}")
+        })
+        .collect();
+    let _ = writeln!(
+        svg,
+        r##"  <polyline fill="none" stroke="#0969da" stroke-width="2" points="{}"/>"##,
+        points.join(" ")
+    );
+    for (i, (_, v)) in series.iter().enumerate() {
+        let (x, y) = point(i, *v);
+        let _ = writeln!(
+            svg,
+            r##"  <circle cx="{x:.1}" cy="{y:.1}" r="3" fill="#0969da"/>"##
+        );
+    }
+    if let (Some((first, _)), Some((last, _))) = (series.first(), series.last()) {
+        let _ = writeln!(
+            svg,
+            r#"  <text x="{pad}" y="{}">{first}</text>"#,
+            h - pad + 18.0
+        );
+        let _ = writeln!(
+            svg,
+            r#"  <text x="{}" y="{}" text-anchor="end">{last}</text>"#,
+            w - pad,
+            h - pad + 18.0
+        );
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// Standalone SVG version of [`heatmap`].
+pub fn heatmap_svg(
+    counts: &BTreeMap<NaiveDate, usize>,
+    from: NaiveDate,
+    to: NaiveDate,
+    title: &str,
+) -> String {
+    let start = week_start(from);
+    let weeks = ((to - start).num_days() / 7 + 1) as usize;
+    let max = counts.values().copied().max().unwrap_or(0);
+    let (cell, gap, left, top) = (11, 3, 32, 40);
+    let w = left + weeks * (cell + gap) + 10;
+    let h = top + 7 * (cell + gap) + 10;
+
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}" font-family="sans-serif" font-size="10">"#
+    );
+    let _ = writeln!(svg, r#"  <rect width="100%" height="100%" fill="white"/>"#);
+    let _ = writeln!(
+        svg,
+        r#"  <text x="{left}" y="16" font-size="13">{}</text>"#,
+        escape(title)
+    );
+    for (weekday, label) in [(0, "Mon"), (2, "Wed"), (4, "Fri")] {
+        let _ = writeln!(
+            svg,
+            r#"  <text x="2" y="{}">{label}</text>"#,
+            top + weekday * (cell + gap) + cell - 1
+        );
+    }
+    for week in 0..weeks {
+        for weekday in 0..7 {
+            let day = start + Duration::days((week * 7 + weekday) as i64);
+            if day < from || day > to {
+                continue;
+            }
+            let count = counts.get(&day).copied().unwrap_or(0);
+            let _ = writeln!(
+                svg,
+                r#"  <rect x="{}" y="{}" width="{cell}" height="{cell}" rx="2" fill="{}"><title>{day}: {count}</title></rect>"#,
+                left + week * (cell + gap),
+                top + weekday * (cell + gap),
+                SVG_GREENS[level(count, max)]
+            );
+        }
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn day(d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2026, 10, d).unwrap()
+    }
+
+    #[test]
+    fn bars_scale_to_the_maximum() {
+        let series = [(day(1), 0), (day(2), 2), (day(3), 4)];
+        let chart = bars(&series, 2);
+        let lines: Vec<&str> = chart.lines().collect();
+        assert_eq!(lines[0], "4 │    ██");
+        assert_eq!(lines[1], "0 │  ████");
+        assert_eq!(lines[2], "  └──────");
+        assert!(lines[3].contains("10-01") && lines[3].ends_with("10-03"));
+    }
+
+    #[test]
+    fn levels_round_up_and_keep_zero_empty() {
+        assert_eq!(level(0, 5), 0);
+        assert_eq!(level(1, 100), 1);
+        assert_eq!(level(3, 4), 3);
+        assert_eq!(level(5, 5), 4);
+    }
+
+    #[test]
+    fn heatmap_svg_has_one_cell_per_day_and_escapes_the_title() {
+        let counts = BTreeMap::from([(day(7), 2)]);
+        let svg = heatmap_svg(&counts, day(5), day(18), "Done <& more>");
+        assert_eq!(svg.matches("<title>").count(), 14);
+        assert!(svg.contains("Done &lt;&amp; more&gt;"));
+        assert!(svg.contains(&format!("fill=\"{}\"><title>2026-10-07: 2", SVG_GREENS[4])));
+    }
+}
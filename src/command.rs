@@ -2,25 +2,684 @@
 #![allow(unused_variables)]
 #![allow(unused_mut)]
 
-use crate::hierarchy::Priority;
+use crate::{
+    error::{Result, TodoError},
+    hierarchy::Priority,
+};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 enum Flag {
     Project,
     Description,
     Priority,
     Force,
-    Other,
+    All,
+    Color,
+    Edit,
+    Delete,
+    Date,
+    Estimate,
+    Since,
+    Due,
+    Tag,
+    Depends,
+    CompletedBefore,
+    Archived,
+    Ours,
+    Theirs,
+    Json,
+    Svg,
+    Week,
+    Month,
+    Yes,
+    No,
+    Help,
 }
 
-impl Flag {
-    fn classify_flag(flag: &str) -> Self {
-        match flag {
-            "-p" | "--project" => Flag::Project,
-            "-d" | "--description" => Flag::Description,
-            "--priority" => Flag::Priority,
-            "-f" | "--force" => Flag::Force,
-            _ => Flag::Other,
+/// A flag as written on the command line.
+pub struct FlagSpec {
+    pub long: &'static str,
+    pub short: Option<&'static str>,
+    /// What the flag's value is called in help, if it takes one.
+    pub value: Option<&'static str>,
+    pub about: &'static str,
+    flag: Flag,
+}
+
+impl FlagSpec {
+    const fn new(
+        long: &'static str,
+        short: Option<&'static str>,
+        value: Option<&'static str>,
+        about: &'static str,
+        flag: Flag,
+    ) -> Self {
+        FlagSpec {
+            long,
+            short,
+            value,
+            about,
+            flag,
+        }
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        self.long == name || self.short == Some(name)
+    }
+}
+
+/// Every flag the parser knows, also used for help and shell completions.
+pub const FLAGS: &[FlagSpec] = &[
+    FlagSpec::new(
+        "--project",
+        Some("-p"),
+        Some("name"),
+        "Project name",
+        Flag::Project,
+    ),
+    FlagSpec::new(
+        "--description",
+        Some("-d"),
+        Some("text"),
+        "Task description",
+        Flag::Description,
+    ),
+    FlagSpec::new(
+        "--priority",
+        None,
+        Some("level"),
+        "Priority: none, low, medium, high, 0-3 or P3-P0",
+        Flag::Priority,
+    ),
+    FlagSpec::new(
+        "--force",
+        Some("-f"),
+        None,
+        "Also remove a project that still has tasks",
+        Flag::Force,
+    ),
+    FlagSpec::new(
+        "--all",
+        Some("-a"),
+        None,
+        "Include completed tasks even if list.show_completed is off",
+        Flag::All,
+    ),
+    FlagSpec::new(
+        "--color",
+        None,
+        Some("when"),
+        "Colour output: auto, always or never",
+        Flag::Color,
+    ),
+    FlagSpec::new(
+        "--edit",
+        None,
+        Some("n"),
+        "Replace the text of annotation n",
+        Flag::Edit,
+    ),
+    FlagSpec::new(
+        "--delete",
+        None,
+        Some("n"),
+        "Remove annotation n",
+        Flag::Delete,
+    ),
+    FlagSpec::new(
+        "--date",
+        None,
+        Some("day"),
+        "Day to use instead of today, e.g. YYYY-MM-DD or mon",
+        Flag::Date,
+    ),
+    FlagSpec::new(
+        "--estimate",
+        Some("-e"),
+        Some("time"),
+        "Effort estimate, e.g. 2h or 1h30m (none clears)",
+        Flag::Estimate,
+    ),
+    FlagSpec::new(
+        "--since",
+        None,
+        Some("span"),
+        "Start of the report window, e.g. 30d or 2w",
+        Flag::Since,
+    ),
+    FlagSpec::new(
+        "--due",
+        None,
+        Some("day"),
+        "Due date: YYYY-MM-DD, tomorrow, fri, +3d (none clears)",
+        Flag::Due,
+    ),
+    FlagSpec::new(
+        "--tag",
+        Some("-t"),
+        Some("tag"),
+        "Add a tag; -tag removes it (repeatable)",
+        Flag::Tag,
+    ),
+    FlagSpec::new(
+        "--depends",
+        None,
+        Some("id"),
+        "Depend on a task; -id removes, none clears (repeatable)",
+        Flag::Depends,
+    ),
+    FlagSpec::new(
+        "--completed-before",
+        None,
+        Some("span"),
+        "Only tasks completed at least this long ago, e.g. 30d",
+        Flag::CompletedBefore,
+    ),
+    FlagSpec::new(
+        "--archived",
+        None,
+        None,
+        "Show archived tasks instead",
+        Flag::Archived,
+    ),
+    FlagSpec::new(
+        "--ours",
+        None,
+        None,
+        "Settle conflicts with the local side",
+        Flag::Ours,
+    ),
+    FlagSpec::new(
+        "--theirs",
+        None,
+        None,
+        "Settle conflicts with the remote side",
+        Flag::Theirs,
+    ),
+    FlagSpec::new("--json", None, None, "Machine-readable output", Flag::Json),
+    FlagSpec::new(
+        "--svg",
+        None,
+        Some("file"),
+        "Write the chart to an SVG file",
+        Flag::Svg,
+    ),
+    FlagSpec::new("--week", None, None, "Weekly period", Flag::Week),
+    FlagSpec::new("--month", None, None, "Monthly period", Flag::Month),
+    FlagSpec::new(
+        "--yes",
+        Some("-y"),
+        None,
+        "Answer yes to confirmation prompts",
+        Flag::Yes,
+    ),
+    FlagSpec::new(
+        "--no",
+        None,
+        None,
+        "Answer no to confirmation prompts",
+        Flag::No,
+    ),
+    FlagSpec::new(
+        "--help",
+        Some("-h"),
+        None,
+        "Show help for the command",
+        Flag::Help,
+    ),
+];
+
+/// Flags every command takes.
+pub const GLOBAL_FLAGS: &[&str] = &["--color", "--yes", "--no", "--help"];
+
+/// A command `main` dispatches, with what its help shows.
+pub struct CommandSpec {
+    pub name: &'static str,
+    pub aliases: &'static [&'static str],
+    /// Arguments after the command name, for the usage line.
+    pub usage: &'static str,
+    pub about: &'static str,
+    /// The first number among the arguments is a task id (a count, for `next`).
+    pub id: bool,
+    /// Words such as `due:fri`, `+tag` and `@Project` set fields of the task.
+    pub modifiers: bool,
+    /// The command may change the data, so retention policies run after it.
+    pub writes: bool,
+    /// Long names of the flags the command takes besides `GLOBAL_FLAGS`.
+    pub flags: &'static [&'static str],
+}
+
+pub const COMMANDS: &[CommandSpec] = &[
+    CommandSpec {
+        name: "add",
+        aliases: &[],
+        usage: "<name...>",
+        about: "Add a new task",
+        id: false,
+        modifiers: true,
+        writes: true,
+        flags: &[
+            "--project",
+            "--description",
+            "--priority",
+            "--estimate",
+            "--due",
+            "--tag",
+            "--depends",
+        ],
+    },
+    CommandSpec {
+        name: "list",
+        aliases: &["ls"],
+        usage: "",
+        about: "List tasks by project",
+        id: false,
+        modifiers: false,
+        writes: false,
+        flags: &["--project", "--all", "--archived", "--json"],
+    },
+    CommandSpec {
+        name: "remove",
+        aliases: &["rm"],
+        usage: "<id>",
+        about: "Move a task to the trash",
+        id: true,
+        modifiers: false,
+        writes: true,
+        flags: &[],
+    },
+    CommandSpec {
+        name: "modify",
+        aliases: &["mod"],
+        usage: "<id> [name...]",
+        about: "Change the name or other fields of a task",
+        id: true,
+        modifiers: true,
+        writes: true,
+        flags: &[
+            "--project",
+            "--description",
+            "--priority",
+            "--estimate",
+            "--due",
+            "--tag",
+            "--depends",
+        ],
+    },
+    CommandSpec {
+        name: "show",
+        aliases: &[],
+        usage: "<id>",
+        about: "Show details of a task",
+        id: true,
+        modifiers: false,
+        writes: false,
+        flags: &["--json"],
+    },
+    CommandSpec {
+        name: "complete",
+        aliases: &["done"],
+        usage: "<id>",
+        about: "Mark a task as complete",
+        id: true,
+        modifiers: false,
+        writes: true,
+        flags: &[],
+    },
+    CommandSpec {
+        name: "note",
+        aliases: &[],
+        usage: "<id> [text]",
+        about: "Append an annotation to a task, or edit or remove one",
+        id: true,
+        modifiers: false,
+        writes: true,
+        flags: &["--edit", "--delete"],
+    },
+    CommandSpec {
+        name: "search",
+        aliases: &[],
+        usage: "<text>",
+        about: "Find tasks by name, description, tag or annotation",
+        id: false,
+        modifiers: false,
+        writes: false,
+        flags: &[],
+    },
+    CommandSpec {
+        name: "track",
+        aliases: &[],
+        usage: "[start <id> | stop | add <id> <time> | status]",
+        about: "Track time spent on tasks, one timer at a time",
+        id: true,
+        modifiers: false,
+        writes: true,
+        flags: &["--date"],
+    },
+    CommandSpec {
+        name: "timesheet",
+        aliases: &[],
+        usage: "",
+        about: "Tracked time per project",
+        id: false,
+        modifiers: false,
+        writes: false,
+        flags: &["--project", "--date", "--week", "--month"],
+    },
+    CommandSpec {
+        name: "estimates",
+        aliases: &[],
+        usage: "",
+        about: "Open estimates per project and estimate vs actual",
+        id: false,
+        modifiers: false,
+        writes: false,
+        flags: &["--project"],
+    },
+    CommandSpec {
+        name: "stats",
+        aliases: &[],
+        usage: "",
+        about: "Productivity statistics",
+        id: false,
+        modifiers: false,
+        writes: false,
+        flags: &["--week", "--month", "--since", "--json"],
+    },
+    CommandSpec {
+        name: "chart",
+        aliases: &[],
+        usage: "burndown|activity",
+        about: "Open tasks per day, or a heatmap of completions",
+        id: false,
+        modifiers: false,
+        writes: false,
+        flags: &["--project", "--since", "--svg"],
+    },
+    CommandSpec {
+        name: "agenda",
+        aliases: &[],
+        usage: "",
+        about: "Open tasks grouped by due date",
+        id: false,
+        modifiers: false,
+        writes: false,
+        flags: &["--project"],
+    },
+    CommandSpec {
+        name: "next",
+        aliases: &[],
+        usage: "[n]",
+        about: "The n most urgent unblocked tasks",
+        id: true,
+        modifiers: false,
+        writes: false,
+        flags: &["--project"],
+    },
+    CommandSpec {
+        name: "archive",
+        aliases: &[],
+        usage: "",
+        about: "Move completed tasks out of list",
+        id: false,
+        modifiers: false,
+        writes: true,
+        flags: &["--project", "--completed-before"],
+    },
+    CommandSpec {
+        name: "purge",
+        aliases: &[],
+        usage: "",
+        about: "Permanently delete archived tasks",
+        id: false,
+        modifiers: false,
+        writes: true,
+        flags: &["--project", "--completed-before"],
+    },
+    CommandSpec {
+        name: "calendar",
+        aliases: &["cal"],
+        usage: "",
+        about: "Month grid of tasks due per day",
+        id: false,
+        modifiers: false,
+        writes: false,
+        flags: &["--project", "--date", "--month"],
+    },
+    CommandSpec {
+        name: "project",
+        aliases: &["cr"],
+        usage: "<name>",
+        about: "Create a new project",
+        id: false,
+        modifiers: false,
+        writes: true,
+        flags: &["--project"],
+    },
+    CommandSpec {
+        name: "remove-project",
+        aliases: &["rmp"],
+        usage: "<name>",
+        about: "Move a project and its tasks to the trash",
+        id: false,
+        modifiers: false,
+        writes: true,
+        flags: &["--project", "--force"],
+    },
+    CommandSpec {
+        name: "history",
+        aliases: &[],
+        usage: "<id>",
+        about: "Every recorded change to a task",
+        id: true,
+        modifiers: false,
+        writes: false,
+        flags: &[],
+    },
+    CommandSpec {
+        name: "log",
+        aliases: &[],
+        usage: "",
+        about: "Changes to all tasks",
+        id: false,
+        modifiers: false,
+        writes: false,
+        flags: &["--since"],
+    },
+    CommandSpec {
+        name: "trash",
+        aliases: &[],
+        usage: "[list | restore <id|name> | empty]",
+        about: "Show, restore or empty removed tasks and projects",
+        id: true,
+        modifiers: false,
+        writes: true,
+        flags: &["--project"],
+    },
+    CommandSpec {
+        name: "sync",
+        aliases: &[],
+        usage: "[remote]",
+        about: "Merge with a git repository and push",
+        id: false,
+        modifiers: false,
+        writes: true,
+        flags: &["--ours", "--theirs"],
+    },
+    CommandSpec {
+        name: "merge",
+        aliases: &[],
+        usage: "<file>",
+        about: "Fold another copy of the database into this one",
+        id: false,
+        modifiers: false,
+        writes: true,
+        flags: &[],
+    },
+    CommandSpec {
+        name: "gc",
+        aliases: &[],
+        usage: "",
+        about: "Number the open tasks from 1 again",
+        id: false,
+        modifiers: false,
+        writes: true,
+        flags: &["--json"],
+    },
+    CommandSpec {
+        name: "config",
+        aliases: &[],
+        usage: "[get [key] | set <key> <value> | path]",
+        about: "Show or change the configuration",
+        id: false,
+        modifiers: false,
+        writes: false,
+        flags: &[],
+    },
+    CommandSpec {
+        name: "completions",
+        aliases: &[],
+        usage: "bash|zsh|fish",
+        about: "Print a shell completion script",
+        id: false,
+        modifiers: false,
+        writes: false,
+        flags: &[],
+    },
+    CommandSpec {
+        name: "help",
+        aliases: &[],
+        usage: "[command]",
+        about: "Show help",
+        id: false,
+        modifiers: false,
+        writes: false,
+        flags: &[],
+    },
+];
+
+/// Commands used by the completion scripts and kept out of help.
+pub const HIDDEN_COMMANDS: &[&str] = &["__complete"];
+
+impl CommandSpec {
+    /// The spec of the command called `name` or aliased to it.
+    pub fn find(name: &str) -> Option<&'static CommandSpec> {
+        COMMANDS
+            .iter()
+            .find(|c| c.name == name || c.aliases.contains(&name))
+    }
+
+    pub fn takes(&self, flag: &FlagSpec) -> bool {
+        GLOBAL_FLAGS.contains(&flag.long) || self.flags.contains(&flag.long)
+    }
+
+    /// Text for `todo <command> --help`.
+    pub fn help(&self) -> String {
+        let mut names = vec![self.name];
+        names.extend_from_slice(self.aliases);
+        let mut out = format!(
+            "{}\n\nUSAGE:\n    todo {} {}[OPTIONS]\n",
+            self.about,
+            names.join("|"),
+            if self.usage.is_empty() {
+                String::new()
+            } else {
+                format!("{} ", self.usage)
+            }
+        );
+        out.push_str("\nOPTIONS:\n");
+        for flag in FLAGS.iter().filter(|f| self.takes(f)) {
+            let mut left = match flag.short {
+                Some(short) => format!("{short}, {}", flag.long),
+                None => flag.long.to_string(),
+            };
+            if let Some(value) = flag.value {
+                left.push_str(&format!(" <{value}>"));
+            }
+            out.push_str(&format!("    {left:<26}{}\n", flag.about));
+        }
+        out
+    }
+}
+
+/// The candidate closest to `word`, if it is close enough to be a likely typo.
+pub fn suggest<'a>(word: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    candidates
+        .into_iter()
+        .map(|c| (edit_distance(word, c), c))
+        .filter(|&(d, c)| d <= 2.min(c.len() / 2))
+        .min_by_key(|&(d, _)| d)
+        .map(|(_, c)| c)
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let substitute = previous + usize::from(ca != cb);
+            previous = row[j + 1];
+            row[j + 1] = substitute.min(row[j] + 1).min(previous + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// The flag called `name`, if `op` takes it.
+fn find_flag(spec: Option<&CommandSpec>, op: &str, name: &str) -> Result<&'static FlagSpec> {
+    let taken = |flag: &FlagSpec| spec.is_none_or(|spec| spec.takes(flag));
+    match FLAGS.iter().find(|flag| flag.matches(name)) {
+        Some(flag) if taken(flag) => Ok(flag),
+        Some(flag) => Err(TodoError::invalid(format!(
+            "todo {op} does not take {}; see todo {op} --help",
+            flag.long
+        ))),
+        None => {
+            let names = FLAGS
+                .iter()
+                .filter(|flag| taken(flag))
+                .flat_map(|flag| flag.short.into_iter().chain([flag.long]));
+            let hint = match suggest(name, names) {
+                Some(similar) => format!("; did you mean {similar}?"),
+                None => format!("; see todo {op} --help"),
+            };
+            Err(TodoError::invalid(format!("unknown flag {name}{hint}")))
+        }
+    }
+}
+
+/// Reporting window selected with `--week` or `--month`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Period {
+    Week,
+    Month,
+}
+
+/// Side whose change wins a `sync` conflict, from `--ours` or `--theirs`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Ours,
+    Theirs,
+}
+
+/// Answer given to confirmation prompts, from `--yes`/`--no` or `TODO_ASSUME`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Assume {
+    #[default]
+    Ask,
+    Yes,
+    No,
+}
+
+impl Assume {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_lowercase().as_str() {
+            "yes" | "y" | "true" | "1" => Some(Assume::Yes),
+            "no" | "n" | "false" | "0" => Some(Assume::No),
+            "ask" | "" => Some(Assume::Ask),
+            _ => None,
         }
     }
 }
@@ -32,7 +691,28 @@ pub struct Parameters {
     description: Option<String>,
     priority: Option<Priority>,
     task_id: Option<usize>,
+    /// The task id was written `#id`, so it is a stored id and not a
+    /// working-set number.
+    stored_id: bool,
     force: bool,
+    all: bool,
+    color: Option<String>,
+    edit: Option<String>,
+    delete: Option<String>,
+    date: Option<String>,
+    estimate: Option<String>,
+    since: Option<String>,
+    due: Option<String>,
+    tags: Vec<String>,
+    depends: Vec<String>,
+    completed_before: Option<String>,
+    archived: bool,
+    side: Option<Side>,
+    json: bool,
+    svg: Option<String>,
+    period: Option<Period>,
+    assume: Assume,
+    help: bool,
 }
 #[derive(Debug)]
 pub struct Command {
@@ -56,6 +736,89 @@ impl Parameters {
         self.force
     }
 
+    pub fn all(&self) -> bool {
+        self.all
+    }
+
+    pub fn color(&self) -> Option<&str> {
+        self.color.as_deref()
+    }
+
+    /// Value of `--edit`, e.g. the annotation index for `note`.
+    pub fn edit(&self) -> Option<&str> {
+        self.edit.as_deref()
+    }
+
+    /// Value of `--delete`, e.g. the annotation index for `note`.
+    pub fn delete(&self) -> Option<&str> {
+        self.delete.as_deref()
+    }
+
+    /// Value of `--date`, unparsed.
+    pub fn date(&self) -> Option<&str> {
+        self.date.as_deref()
+    }
+
+    /// Value of `--estimate`, unparsed.
+    pub fn estimate(&self) -> Option<&str> {
+        self.estimate.as_deref()
+    }
+
+    /// Value of `--since`, e.g. `2w`, unparsed.
+    pub fn since(&self) -> Option<&str> {
+        self.since.as_deref()
+    }
+
+    /// Value of `--due`, unparsed.
+    pub fn due(&self) -> Option<&str> {
+        self.due.as_deref()
+    }
+
+    /// Values of `--tag`, in order; a leading `-` asks for removal.
+    pub fn tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    /// Values of `--depends`, unparsed; a leading `-` asks for removal.
+    pub fn depends(&self) -> &[String] {
+        &self.depends
+    }
+
+    /// Value of `--completed-before`, e.g. `30d`, unparsed.
+    pub fn completed_before(&self) -> Option<&str> {
+        self.completed_before.as_deref()
+    }
+
+    pub fn archived(&self) -> bool {
+        self.archived
+    }
+
+    pub fn side(&self) -> Option<Side> {
+        self.side
+    }
+
+    pub fn json(&self) -> bool {
+        self.json
+    }
+
+    /// Output path given with `--svg`.
+    pub fn svg(&self) -> Option<&str> {
+        self.svg.as_deref()
+    }
+
+    pub fn period(&self) -> Option<Period> {
+        self.period
+    }
+
+    pub fn assume(&self) -> Assume {
+        self.assume
+    }
+
+    /// `--help` or `-h` was given.
+    pub fn help(&self) -> bool {
+        self.help
+    }
+
     fn new() -> Self {
         Parameters {
             tasks: Vec::new(),
@@ -63,8 +826,113 @@ impl Parameters {
             description: None,
             priority: None,
             task_id: None,
+            stored_id: false,
             force: false,
+            all: false,
+            color: None,
+            edit: None,
+            delete: None,
+            date: None,
+            estimate: None,
+            since: None,
+            due: None,
+            tags: Vec::new(),
+            depends: Vec::new(),
+            completed_before: None,
+            archived: false,
+            side: None,
+            json: false,
+            svg: None,
+            period: None,
+            assume: Assume::Ask,
+            help: false,
+        }
+    }
+
+    /// Records `flag`, whose value the parser has checked is there if needed.
+    fn set(&mut self, flag: Flag, value: Option<String>) -> Result<()> {
+        match flag {
+            Flag::Project => self.project = value,
+            Flag::Description => self.description = value,
+            Flag::Priority => self.priority = value.as_deref().map(Priority::parse).transpose()?,
+            Flag::Force => self.force = true,
+            Flag::All => self.all = true,
+            Flag::Color => self.color = value,
+            Flag::Edit => self.edit = value,
+            Flag::Delete => self.delete = value,
+            Flag::Date => self.date = value,
+            Flag::Estimate => self.estimate = value,
+            Flag::Since => self.since = value,
+            Flag::Due => self.due = value,
+            Flag::Tag => self.tags.extend(value),
+            Flag::Depends => self.depends.extend(value),
+            Flag::CompletedBefore => self.completed_before = value,
+            Flag::Archived => self.archived = true,
+            Flag::Ours => self.side = Some(Side::Ours),
+            Flag::Theirs => self.side = Some(Side::Theirs),
+            Flag::Json => self.json = true,
+            Flag::Svg => self.svg = value,
+            Flag::Week => self.period = Some(Period::Week),
+            Flag::Month => self.period = Some(Period::Month),
+            Flag::Yes => self.assume = Assume::Yes,
+            Flag::No => self.assume = Assume::No,
+            Flag::Help => self.help = true,
+        }
+        Ok(())
+    }
+
+    /// With `id`, the first number, or `#id`, is the task id (for commands like
+    /// 'modify 1'); other arguments, numbers included, are kept as words (for
+    /// task names like 'Task 2') unless `modifiers` picks them up.
+    fn push_positional(&mut self, arg: &str, id: bool, modifiers: bool) -> Result<()> {
+        if id && self.task_id.is_none() {
+            if let Some(id) = arg.strip_prefix('#').and_then(|id| id.parse().ok()) {
+                self.task_id = Some(id);
+                self.stored_id = true;
+                return Ok(());
+            }
+            if let Ok(id) = arg.parse() {
+                self.task_id = Some(id);
+                return Ok(());
+            }
+        }
+        if modifiers && self.modifier(arg)? {
+            return Ok(());
+        }
+        self.tasks.push(arg.to_string());
+        Ok(())
+    }
+
+    /// Applies `arg` if it is an inline modifier: `+tag`, `@project`, or
+    /// `key:value` for due, pri(ority), est(imate), dep(ends) and project, which
+    /// work like the flags of the same name. Returns whether it was one.
+    fn modifier(&mut self, arg: &str) -> Result<bool> {
+        if let Some(tag) = arg.strip_prefix('+') {
+            if tag.starts_with(char::is_alphabetic) {
+                self.tags.push(tag.to_string());
+                return Ok(true);
+            }
         }
+        if let Some(project) = arg.strip_prefix('@') {
+            if !project.is_empty() {
+                self.project = Some(project.to_string());
+                return Ok(true);
+            }
+        }
+        let Some((key, value)) = arg.split_once(':') else {
+            return Ok(false);
+        };
+        let flag = match key.to_lowercase().as_str() {
+            _ if value.is_empty() => return Ok(false),
+            "due" => Flag::Due,
+            "pri" | "priority" => Flag::Priority,
+            "est" | "estimate" => Flag::Estimate,
+            "dep" | "depends" => Flag::Depends,
+            "project" => Flag::Project,
+            _ => return Ok(false),
+        };
+        self.set(flag, Some(value.to_string()))?;
+        Ok(true)
     }
 }
 
@@ -72,53 +940,234 @@ impl Command {
     pub fn op(&self) -> &str {
         &self.op
     }
+    /// Whether this command may change the data. `track` and `trash` only do
+    /// with some of their actions.
+    pub fn writes(&self) -> bool {
+        let action = self.parameters.tasks().first().map(String::as_str);
+        match self.op.as_str() {
+            "track" => matches!(action, Some("start" | "stop" | "add")),
+            "trash" => matches!(action, Some("restore" | "empty")),
+            op => CommandSpec::find(op).is_some_and(|spec| spec.writes),
+        }
+    }
     pub fn parameters(&self) -> &Parameters {
         &self.parameters
     }
-    pub fn new(args: Vec<String>) -> Self {
-        let op: String = args[1].clone();
-        let mut parameters: Parameters = Parameters::new();
-        let mut it = args.into_iter().skip(2).peekable();
+    /// Parses the program arguments, program name first. Unknown flags, flags
+    /// the command doesn't take and flags without their value are errors.
+    /// `--flag=value` works like `--flag value`, and after `--` every argument
+    /// is positional.
+    pub fn parse(args: &[String]) -> Result<Self> {
+        let op = args
+            .get(1)
+            .ok_or_else(|| TodoError::invalid("command required"))?
+            .clone();
+        let spec = CommandSpec::find(&op);
+        let (id, modifiers) = spec.map_or((true, false), |spec| (spec.id, spec.modifiers));
+        let mut parameters = Parameters::new();
+        let mut options = true;
+        let mut it = args.iter().skip(2);
 
         while let Some(arg) = it.next() {
-            match Flag::classify_flag(&arg) {
-                Flag::Project => {
-                    if let Some(value) = it.next() {
-                        parameters.project = Some(value);
-                    }
-                }
-                Flag::Priority => {
-                    if let Some(value) = it.next() {
-                        parameters.priority = Some(Priority::translate_priority(&value));
-                    }
-                }
-                Flag::Description => {
-                    if let Some(value) = it.next() {
-                        parameters.description = Some(value);
-                    }
-                }
-                Flag::Force => {
-                    parameters.force = true;
-                }
-                Flag::Other => {
-                    // Try to parse as task ID if it's a number
-                    // The first numeric argument becomes the task ID (for commands like 'modify 1')
-                    // Subsequent numeric arguments are treated as strings (for task names like 'Task 2')
-                    if let Ok(id) = arg.parse::<usize>() {
-                        if parameters.task_id.is_none() {
-                            parameters.task_id = Some(id);
-                            continue;
-                        }
+            if !options || arg == "-" || !arg.starts_with('-') {
+                parameters.push_positional(arg, id, modifiers && options)?;
+                continue;
+            }
+            if arg == "--" {
+                options = false;
+                continue;
+            }
+            let (name, inline) = match arg.split_once('=') {
+                Some((name, value)) if name.starts_with("--") => (name, Some(value)),
+                _ => (arg.as_str(), None),
+            };
+            let flag = find_flag(spec, &op, name)?;
+            let value = match (flag.value, inline) {
+                (Some(_), Some(value)) => Some(value.to_string()),
+                // A value starting with `--` is more likely the next flag; it
+                // can still be given as `--flag=--value`.
+                (Some(what), None) => match it.next() {
+                    Some(value) if !value.starts_with("--") => Some(value.clone()),
+                    _ => {
+                        return Err(TodoError::invalid(format!("{name} needs a <{what}>")));
                     }
-                    parameters.tasks.push(arg);
+                },
+                (None, Some(_)) => {
+                    return Err(TodoError::invalid(format!("{name} does not take a value")));
                 }
-            }
+                (None, None) => None,
+            };
+            parameters.set(flag.flag, value)?;
         }
 
-        Command { op, parameters }
+        Ok(Command { op, parameters })
+    }
+
+    /// Maps the task id and the `--depends` ids written as plain numbers
+    /// through `resolve`, leaving `#id` forms alone. Resolved dependencies are
+    /// rewritten as `#id`.
+    pub fn resolve_ids(&mut self, resolve: impl Fn(usize) -> Result<usize>) -> Result<()> {
+        let params = &mut self.parameters;
+        if let Some(id) = params.task_id.filter(|_| !params.stored_id) {
+            params.task_id = Some(resolve(id)?);
+            params.stored_id = true;
+        }
+        for value in params.depends.iter_mut() {
+            let (sign, id) = match value.strip_prefix('-') {
+                Some(id) => ("-", id),
+                None => ("", value.as_str()),
+            };
+            if let Ok(id) = id.parse() {
+                *value = format!("{sign}#{}", resolve(id)?);
+            }
+        }
+        Ok(())
     }
 
     pub fn get_tasks(&self) -> Vec<String> {
         self.parameters.tasks.clone()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::working_set::WorkingSet;
+
+    fn parse(line: &str) -> Result<Command> {
+        let args: Vec<String> = ["todo"]
+            .into_iter()
+            .chain(line.split_whitespace())
+            .map(String::from)
+            .collect();
+        Command::parse(&args)
+    }
+
+    fn error(line: &str) -> String {
+        parse(line).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn flag_values_follow_the_flag_or_an_equals_sign() {
+        let command = parse("add Task --due=fri -p Work --description=a=b").unwrap();
+        let params = command.parameters();
+        assert_eq!(params.due(), Some("fri"));
+        assert_eq!(params.fields().0.as_deref(), Some("Work"));
+        assert_eq!(params.fields().1.as_deref(), Some("a=b"));
+        assert_eq!(params.tasks(), &["Task"]);
+    }
+
+    #[test]
+    fn flags_without_their_value_are_errors() {
+        assert!(error("add Task --due").contains("--due needs a <"));
+        assert!(error("add Task --due --tag x").contains("--due needs a <"));
+        assert!(error("list --all=yes").contains("does not take a value"));
+        assert!(parse("add Task --description=--odd").is_ok());
+    }
+
+    #[test]
+    fn double_dash_ends_the_options() {
+        let command = parse("add -- --not-a-flag +tag pri:h").unwrap();
+        let params = command.parameters();
+        assert_eq!(params.tasks(), &["--not-a-flag", "+tag", "pri:h"]);
+        assert!(params.tags().is_empty());
+        assert!(params.fields().2.is_none());
+    }
+
+    #[test]
+    fn flags_are_checked_against_the_command() {
+        assert!(error("remove 3 --due fri").contains("todo remove does not take --due"));
+        assert!(error("list --bogus").contains("unknown flag --bogus"));
+        assert!(parse("list --json").is_ok());
+        assert!(parse("remove 3 --yes").is_ok());
+        assert!(parse("remove 3 -h").unwrap().parameters().help());
+    }
+
+    #[test]
+    fn typos_get_a_suggestion() {
+        assert!(error("add Task --decsription x").contains("did you mean --description?"));
+        assert!(error("list --jsno").contains("did you mean --json?"));
+        assert!(error("list --zzzzzz").contains("see todo list --help"));
+        assert_eq!(suggest("lsit", ["list", "show", "next"]), Some("list"));
+        assert_eq!(suggest("xyz", ["list", "show"]), None);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn inline_modifiers_set_fields() {
+        let command = parse("add Fix login bug due:fri pri:h +backend @Work est:2h").unwrap();
+        let params = command.parameters();
+        assert_eq!(params.tasks(), &["Fix", "login", "bug"]);
+        assert_eq!(params.due(), Some("fri"));
+        assert!(matches!(params.fields().2, Some(Priority::High)));
+        assert_eq!(params.tags(), &["backend"]);
+        assert_eq!(params.fields().0.as_deref(), Some("Work"));
+        assert_eq!(params.estimate(), Some("2h"));
+    }
+
+    #[test]
+    fn modifier_lookalikes_stay_in_the_name() {
+        let command = parse("add Buy 2 apples +1 @ note: http://x").unwrap();
+        let params = command.parameters();
+        assert_eq!(params.task_id(), None);
+        assert_eq!(
+            params.tasks(),
+            &["Buy", "2", "apples", "+1", "@", "note:", "http://x"]
+        );
+    }
+
+    #[test]
+    fn modifiers_only_apply_to_commands_that_take_them() {
+        let command = parse("modify 4 pri:l dep:2").unwrap();
+        assert_eq!(command.parameters().task_id(), Some(4));
+        assert!(matches!(
+            command.parameters().fields().2,
+            Some(Priority::Low)
+        ));
+        assert_eq!(command.parameters().depends(), &["2"]);
+
+        let command = parse("search +backend").unwrap();
+        assert_eq!(command.parameters().tasks(), &["+backend"]);
+        assert!(command.parameters().tags().is_empty());
+    }
+
+    #[test]
+    fn bad_modifier_values_are_errors() {
+        assert!(error("add Task pri:bad").contains("invalid priority 'bad'"));
+    }
+
+    #[test]
+    fn only_commands_that_change_data_write() {
+        for line in [
+            "add Task",
+            "archive",
+            "purge",
+            "track start 1",
+            "trash empty",
+        ] {
+            assert!(parse(line).unwrap().writes(), "{line}");
+        }
+        for line in ["list", "list --archived", "track report", "trash", "stats"] {
+            assert!(!parse(line).unwrap().writes(), "{line}");
+        }
+    }
+
+    #[test]
+    fn resolve_ids_maps_plain_numbers_only() {
+        let set = WorkingSet::new(vec![40, 12]);
+        let mut command = parse("modify 2 --depends 1 --depends -2 --depends #7").unwrap();
+        command.resolve_ids(|n| set.resolve(n)).unwrap();
+        assert_eq!(command.parameters().task_id(), Some(12));
+        assert_eq!(command.parameters().depends(), ["#40", "-#12", "#7"]);
+
+        let mut command = parse("show #2").unwrap();
+        command.resolve_ids(|n| set.resolve(n)).unwrap();
+        assert_eq!(command.parameters().task_id(), Some(2));
+        assert!(
+            parse("show 3")
+                .unwrap()
+                .resolve_ids(|n| set.resolve(n))
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,288 @@
+use crate::{
+    command::{COMMANDS, CommandSpec, FLAGS, FlagSpec, GLOBAL_FLAGS},
+    error::{Result, TodoError},
+};
+
+/// Words taken by commands that have actions of their own.
+const ACTIONS: &[(&str, &[&str])] = &[
+    ("track", &["start", "stop", "add", "status"]),
+    ("chart", &["burndown", "activity"]),
+    ("trash", &["list", "restore", "empty"]),
+    ("config", &["get", "set", "path"]),
+    ("completions", &["bash", "zsh", "fish"]),
+];
+
+/// Commands whose argument is an open task.
+const TASK_COMMANDS: &[&str] = &[
+    "complete", "done", "show", "remove", "rm", "modify", "mod", "note", "history",
+];
+
+const PRIORITIES: &[&str] = &["low", "medium", "high"];
+const COLORS: &[&str] = &["auto", "always", "never"];
+
+/// The completion script for `shell`. Project names and open tasks are
+/// looked up when completing, with the hidden `todo __complete` command.
+pub fn script(shell: &str) -> Result<String> {
+    match shell {
+        "bash" => Ok(bash()),
+        "zsh" => Ok(zsh()),
+        "fish" => Ok(fish()),
+        _ => Err(TodoError::invalid(format!(
+            "unknown shell '{shell}', expected bash, zsh or fish"
+        ))),
+    }
+}
+
+/// A command's name and aliases, as a shell `case` pattern.
+fn pattern(command: &CommandSpec) -> String {
+    let mut names = vec![command.name];
+    names.extend_from_slice(command.aliases);
+    names.join("|")
+}
+
+fn names(flag: &FlagSpec) -> impl Iterator<Item = &'static str> {
+    flag.short.into_iter().chain([flag.long])
+}
+
+/// `case` arms setting `flags` to what each command takes.
+fn flag_arms(indent: &str) -> String {
+    COMMANDS
+        .iter()
+        .map(|command| {
+            let flags: Vec<&str> = FLAGS
+                .iter()
+                .filter(|f| command.takes(f))
+                .flat_map(names)
+                .collect();
+            format!(
+                "{indent}{}) flags=\"{}\" ;;\n",
+                pattern(command),
+                flags.join(" ")
+            )
+        })
+        .collect()
+}
+
+/// Flags whose value isn't completed, as a shell `case` pattern.
+fn free_value_flags() -> String {
+    FLAGS
+        .iter()
+        .filter(|f| f.value.is_some() && !["--project", "--priority", "--color"].contains(&f.long))
+        .flat_map(names)
+        .collect::<Vec<_>>()
+        .join("|")
+}
+
+fn bash() -> String {
+    let commands: Vec<&str> = COMMANDS
+        .iter()
+        .flat_map(|c| [c.name].into_iter().chain(c.aliases.iter().copied()))
+        .collect();
+    let actions: String = ACTIONS
+        .iter()
+        .map(|(command, words)| format!("        {command}) words=\"{}\" ;;\n", words.join(" ")))
+        .collect();
+    format!(
+        r#"# bash completion for todo; load with: source <(todo completions bash)
+# Offers the lines of stdin that start with the current word.
+_todo_reply() {{
+    local word
+    COMPREPLY=()
+    while IFS= read -r word; do
+        [[ $word == "$cur"* ]] && COMPREPLY+=("$(printf '%q' "$word")")
+    done
+}}
+
+_todo() {{
+    local cur=${{COMP_WORDS[COMP_CWORD]}} prev=${{COMP_WORDS[COMP_CWORD-1]}} words= flags=
+    case $prev in
+        -p|--project) _todo_reply < <(todo __complete projects 2>/dev/null); return ;;
+        --priority) _todo_reply < <(printf '%s\n' {priorities}); return ;;
+        --color) _todo_reply < <(printf '%s\n' {colors}); return ;;
+        {values}) return ;;
+    esac
+    if (( COMP_CWORD == 1 )); then
+        _todo_reply < <(printf '%s\n' {commands})
+        return
+    fi
+    if [[ $cur == -* ]]; then
+        case ${{COMP_WORDS[1]}} in
+{flags}        esac
+        _todo_reply < <(printf '%s\n' $flags)
+        return
+    fi
+    case ${{COMP_WORDS[1]}} in
+{actions}    esac
+    if [[ -n $words ]] && (( COMP_CWORD == 2 )); then
+        _todo_reply < <(printf '%s\n' $words)
+        return
+    fi
+    case ${{COMP_WORDS[1]}} in
+        {tasks}) _todo_reply < <(todo __complete tasks 2>/dev/null | cut -f1) ;;
+    esac
+}}
+
+complete -F _todo todo
+"#,
+        priorities = PRIORITIES.join(" "),
+        colors = COLORS.join(" "),
+        values = free_value_flags(),
+        commands = commands.join(" "),
+        flags = flag_arms("            "),
+        actions = actions,
+        tasks = TASK_COMMANDS.join("|"),
+    )
+}
+
+fn zsh() -> String {
+    let commands: String = COMMANDS
+        .iter()
+        .flat_map(|c| {
+            [c.name]
+                .into_iter()
+                .chain(c.aliases.iter().copied())
+                .map(|name| (name, c.about))
+        })
+        .map(|(name, about)| format!("        '{name}:{about}'\n"))
+        .collect();
+    let actions: String = ACTIONS
+        .iter()
+        .map(|(command, words)| format!("        {command}) compadd {} ;;\n", words.join(" ")))
+        .collect();
+    format!(
+        r#"#compdef todo
+# zsh completion for todo; save as _todo in a directory on $fpath
+
+_todo_tasks() {{
+    local -a tasks
+    tasks=(${{(f)"$(todo __complete tasks 2>/dev/null | tr '\t' ':')"}})
+    _describe 'task' tasks
+}}
+
+_todo() {{
+    local -a commands projects
+    local flags
+    commands=(
+{commands}    )
+    if (( CURRENT == 2 )); then
+        _describe 'command' commands
+        return
+    fi
+    case $words[CURRENT-1] in
+        -p|--project)
+            projects=(${{(f)"$(todo __complete projects 2>/dev/null)"}})
+            compadd -a projects
+            return ;;
+        --priority) compadd {priorities}; return ;;
+        --color) compadd {colors}; return ;;
+        {values}) return ;;
+    esac
+    if [[ $PREFIX == -* ]]; then
+        case $words[2] in
+{flags}        esac
+        compadd -- ${{=flags}}
+        return
+    fi
+    if (( CURRENT == 3 )); then
+        case $words[2] in
+{actions}        esac
+    fi
+    case $words[2] in
+        {tasks}) _todo_tasks ;;
+    esac
+}}
+
+_todo "$@"
+"#,
+        commands = commands,
+        priorities = PRIORITIES.join(" "),
+        colors = COLORS.join(" "),
+        values = free_value_flags(),
+        flags = flag_arms("            "),
+        actions = actions,
+        tasks = TASK_COMMANDS.join("|"),
+    )
+}
+
+fn fish() -> String {
+    let mut out = String::from(
+        "# fish completion for todo; load with: todo completions fish | source\n\
+         complete -c todo -f\n",
+    );
+    for command in COMMANDS {
+        for name in [command.name].iter().chain(command.aliases) {
+            out.push_str(&format!(
+                "complete -c todo -n __fish_use_subcommand -a {name} -d '{}'\n",
+                command.about
+            ));
+        }
+    }
+    for (command, words) in ACTIONS {
+        out.push_str(&format!(
+            "complete -c todo -n '__fish_seen_subcommand_from {command}; and test (count (commandline -opc)) -eq 2' -a '{}'\n",
+            words.join(" ")
+        ));
+    }
+    for flag in FLAGS {
+        let mut line = String::from("complete -c todo");
+        let commands: Vec<String> = COMMANDS
+            .iter()
+            .filter(|c| c.flags.contains(&flag.long))
+            .map(pattern)
+            .collect();
+        if !GLOBAL_FLAGS.contains(&flag.long) {
+            line.push_str(&format!(
+                " -n '__fish_seen_subcommand_from {}'",
+                commands.join(" ").replace('|', " ")
+            ));
+        }
+        line.push_str(&format!(" -l {}", &flag.long[2..]));
+        if let Some(short) = flag.short {
+            line.push_str(&format!(" -s {}", &short[1..]));
+        }
+        match flag.long {
+            "--project" => line.push_str(" -x -a '(todo __complete projects 2>/dev/null)'"),
+            "--priority" => line.push_str(&format!(" -x -a '{}'", PRIORITIES.join(" "))),
+            "--color" => line.push_str(&format!(" -x -a '{}'", COLORS.join(" "))),
+            _ if flag.value.is_some() => line.push_str(" -x"),
+            _ => {}
+        }
+        out.push_str(&line);
+        out.push('\n');
+    }
+    out.push_str(&format!(
+        "complete -c todo -n '__fish_seen_subcommand_from {}' -a '(todo __complete tasks 2>/dev/null)'\n",
+        TASK_COMMANDS.join(" ")
+    ));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scripts_cover_every_command_and_look_up_live_data() {
+        for shell in ["bash", "zsh", "fish"] {
+            let script = script(shell).unwrap();
+            for command in COMMANDS {
+                assert!(script.contains(command.name), "{shell}: {}", command.name);
+            }
+            assert!(script.contains("todo __complete projects"), "{shell}");
+            assert!(script.contains("todo __complete tasks"), "{shell}");
+            assert!(PRIORITIES.iter().all(|p| script.contains(p)), "{shell}");
+        }
+    }
+
+    #[test]
+    fn completed_commands_exist() {
+        let known = |name: &str| CommandSpec::find(name).is_some();
+        assert!(TASK_COMMANDS.iter().all(|name| known(name)));
+        assert!(ACTIONS.iter().all(|(name, _)| known(name)));
+    }
+
+    #[test]
+    fn unknown_shells_are_rejected() {
+        assert!(matches!(script("tcsh"), Err(TodoError::InvalidInput(_))));
+    }
+}
@@ -0,0 +1,364 @@
+use std::{
+    collections::BTreeMap,
+    env, fs, io,
+    path::{Path, PathBuf},
+};
+
+use chrono::{
+    Utc,
+    format::{Item, StrftimeItems},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    command::{CommandSpec, HIDDEN_COMMANDS},
+    error::{Result, TodoError},
+    hierarchy::Priority,
+    render::ColorChoice,
+    timeutil::time_before,
+};
+
+/// User settings read from `$XDG_CONFIG_HOME/todo/config.toml`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Project that receives tasks added without `-p`.
+    pub default_project: String,
+    /// Priority given to new tasks added without `--priority`.
+    pub default_priority: String,
+    /// strftime-style format used when printing timestamps.
+    pub date_format: String,
+    /// `auto`, `always` or `never`.
+    pub color: String,
+    /// Create unknown projects named with `-p` without asking.
+    pub auto_create_projects: bool,
+    /// Number open tasks from 1 with `todo gc` instead of showing their stored ids.
+    pub working_set: bool,
+    /// Data file to use instead of `~/.todo.db`; `TODO_DB` takes precedence.
+    pub database: Option<PathBuf>,
+    /// Extra command names, expanded to the given words before parsing.
+    pub aliases: BTreeMap<String, String>,
+    pub list: ListConfig,
+    pub urgency: UrgencyConfig,
+    pub archive: ArchiveConfig,
+    pub trash: TrashConfig,
+    pub sync: SyncConfig,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ArchiveConfig {
+    /// Archive tasks automatically once they have been completed this long,
+    /// e.g. `30d`. Off when unset.
+    pub after: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ListConfig {
+    /// Project whose subtree `list` shows when no `-p` is given.
+    pub project: Option<String>,
+    /// Whether `list` includes completed tasks unless `--all` is given.
+    pub show_completed: bool,
+}
+
+/// Coefficients of the urgency score used by `next` and `show`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UrgencyConfig {
+    pub priority_high: f64,
+    pub priority_medium: f64,
+    pub priority_low: f64,
+    /// Weight of due-date proximity, reached in full a week after the due date.
+    pub due: f64,
+    /// Weight of age, reached in full at `age_max_days`.
+    pub age: f64,
+    pub age_max_days: f64,
+    /// Added when an open dependency holds the task up; usually negative.
+    pub blocked: f64,
+    /// Added when other open tasks depend on the task.
+    pub blocking: f64,
+    /// Added while a timer runs on the task.
+    pub active: f64,
+    /// Weight of having tags at all.
+    pub tagged: f64,
+    /// Extra weight per tag name.
+    pub tag: BTreeMap<String, f64>,
+    /// Extra weight per project name.
+    pub project: BTreeMap<String, f64>,
+    /// How many tasks `next` shows when no count is given.
+    pub next_count: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TrashConfig {
+    /// How long removed tasks and projects can be restored, e.g. `30d`, or
+    /// `never` to keep them until `trash empty`.
+    pub expire_after: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SyncConfig {
+    /// Working copy used by `sync`; `~/.todo-sync` when unset.
+    pub dir: Option<PathBuf>,
+    /// Repository to pull from and push to, as a path or URL. Without one,
+    /// `sync` only records the state in the working copy.
+    pub remote: Option<String>,
+    pub branch: String,
+}
+
+impl Default for SyncConfig {
+    fn default() -> Self {
+        SyncConfig {
+            dir: None,
+            remote: None,
+            branch: String::from("main"),
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            default_project: String::from("Home"),
+            default_priority: String::from("none"),
+            date_format: String::from("%Y-%m-%d %H:%M:%S"),
+            color: String::from("auto"),
+            auto_create_projects: false,
+            working_set: false,
+            database: None,
+            aliases: BTreeMap::new(),
+            list: ListConfig::default(),
+            urgency: UrgencyConfig::default(),
+            archive: ArchiveConfig::default(),
+            trash: TrashConfig::default(),
+            sync: SyncConfig::default(),
+        }
+    }
+}
+
+impl Default for TrashConfig {
+    fn default() -> Self {
+        TrashConfig {
+            expire_after: String::from("30d"),
+        }
+    }
+}
+
+impl Default for UrgencyConfig {
+    fn default() -> Self {
+        UrgencyConfig {
+            priority_high: 6.0,
+            priority_medium: 3.9,
+            priority_low: 1.8,
+            due: 12.0,
+            age: 2.0,
+            age_max_days: 365.0,
+            blocked: -5.0,
+            blocking: 8.0,
+            active: 4.0,
+            tagged: 1.0,
+            tag: BTreeMap::new(),
+            project: BTreeMap::new(),
+            next_count: 5,
+        }
+    }
+}
+
+impl Default for ListConfig {
+    fn default() -> Self {
+        ListConfig {
+            project: None,
+            show_completed: true,
+        }
+    }
+}
+
+impl Config {
+    /// Location of the config file, honouring `TODO_CONFIG` and `XDG_CONFIG_HOME`.
+    pub fn path() -> PathBuf {
+        if let Some(path) = env::var_os("TODO_CONFIG") {
+            return PathBuf::from(path);
+        }
+        let mut path = dirs::config_dir().unwrap_or_else(|| PathBuf::from("."));
+        path.push("todo");
+        path.push("config.toml");
+        path
+    }
+
+    /// Reads the config file, falling back to the defaults if it doesn't exist.
+    pub fn load() -> Result<Self> {
+        Self::load_from(&Self::path())
+    }
+
+    pub fn load_from(path: &Path) -> Result<Self> {
+        Self::from_table(read_table(path)?, path)
+    }
+
+    /// Reads the config file without `validate`, so that `todo config` can show
+    /// a file with bad values and be used to fix them.
+    pub fn load_unchecked() -> Result<Self> {
+        let path = Self::path();
+        Self::parse_table(read_table(&path)?, &path)
+    }
+
+    fn from_table(table: toml::Table, path: &Path) -> Result<Self> {
+        let config = Self::parse_table(table, path)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn parse_table(table: toml::Table, path: &Path) -> Result<Self> {
+        table.try_into().map_err(|e: toml::de::Error| {
+            TodoError::format(format!("{}: {}", path.display(), e.message()))
+        })
+    }
+
+    /// Checks the values that deserializing alone doesn't.
+    pub fn validate(&self) -> Result<()> {
+        if StrftimeItems::new(&self.date_format).any(|item| item == Item::Error) {
+            return Err(TodoError::invalid(format!(
+                "invalid date_format '{}'",
+                self.date_format
+            )));
+        }
+        Priority::parse(&self.default_priority)
+            .map_err(|e| TodoError::invalid(format!("default_priority: {e}")))?;
+        ColorChoice::parse(&self.color)?;
+        if let Some(after) = &self.archive.after {
+            time_before(after, Utc::now())?;
+        }
+        if self.trash.expire_after != "never" {
+            time_before(&self.trash.expire_after, Utc::now())?;
+        }
+        if self.urgency.age_max_days <= 0.0 {
+            return Err(TodoError::invalid(
+                "urgency.age_max_days must be greater than 0",
+            ));
+        }
+        // `expand_alias` would otherwise quietly replace the command.
+        for name in self.aliases.keys() {
+            if CommandSpec::find(name).is_some() || HIDDEN_COMMANDS.contains(&name.as_str()) {
+                return Err(TodoError::invalid(format!(
+                    "alias '{name}' has the name of a built-in command"
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn default_priority(&self) -> Priority {
+        Priority::parse(&self.default_priority).unwrap_or_default()
+    }
+
+    pub fn color_choice(&self) -> ColorChoice {
+        ColorChoice::parse(&self.color).unwrap_or(ColorChoice::Auto)
+    }
+
+    /// Replaces an aliased command name in `args` with its expansion.
+    pub fn expand_alias(&self, args: Vec<String>) -> Vec<String> {
+        let Some(expansion) = args.get(1).and_then(|op| self.aliases.get(op)) else {
+            return args;
+        };
+        let mut expanded = vec![args[0].clone()];
+        expanded.extend(expansion.split_whitespace().map(String::from));
+        expanded.extend(args.into_iter().skip(2));
+        expanded
+    }
+
+    /// Returns the effective value of a dotted `key`, or the whole config without one.
+    pub fn get(&self, key: Option<&str>) -> Result<String> {
+        let table = toml::Table::try_from(self).map_err(|e| TodoError::format(e.to_string()))?;
+        let Some(key) = key else {
+            return toml::to_string(&table).map_err(|e| TodoError::format(e.to_string()));
+        };
+
+        let mut value = toml::Value::Table(table);
+        for part in key.split('.') {
+            value = match value {
+                toml::Value::Table(mut inner) => inner.remove(part),
+                _ => None,
+            }
+            .ok_or_else(|| TodoError::not_found(format!("config key '{key}' is not set")))?;
+        }
+
+        match value {
+            toml::Value::String(s) => Ok(s),
+            toml::Value::Table(t) => {
+                toml::to_string(&t).map_err(|e| TodoError::format(e.to_string()))
+            }
+            other => Ok(other.to_string()),
+        }
+    }
+
+    /// Sets a dotted `key` in the config file at `path`, keeping the rest of the file.
+    pub fn set(path: &Path, key: &str, value: &str) -> Result<()> {
+        let mut table = read_table(path)?;
+
+        let parts: Vec<&str> = key.split('.').collect();
+        let (last, parents) = parts.split_last().expect("split yields at least one part");
+        let mut current = &mut table;
+        for part in parents {
+            current = match current
+                .entry(part.to_string())
+                .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+            {
+                toml::Value::Table(inner) => inner,
+                _ => return Err(TodoError::invalid(format!("'{part}' is not a table"))),
+            };
+        }
+        current.insert(last.to_string(), parse_value(value));
+
+        // Refuse to write a file that would fail to load on the next run.
+        Self::from_table(table.clone(), path)?;
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let text = toml::to_string(&table).map_err(|e| TodoError::format(e.to_string()))?;
+        fs::write(path, text)?;
+        Ok(())
+    }
+}
+
+fn read_table(path: &Path) -> Result<toml::Table> {
+    match fs::read_to_string(path) {
+        Ok(text) => text
+            .parse()
+            .map_err(|e: toml::de::Error| TodoError::format(format!("{}: {e}", path.display()))),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(toml::Table::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Interprets a command-line value as TOML (`true`, `3`, `"x"`), or else as a plain string.
+fn parse_value(value: &str) -> toml::Value {
+    format!("v = {value}")
+        .parse::<toml::Table>()
+        .ok()
+        .and_then(|mut t| t.remove("v"))
+        .unwrap_or_else(|| toml::Value::String(value.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aliases_cannot_replace_commands() {
+        let mut config = Config::default();
+        config
+            .aliases
+            .insert(String::from("today"), String::from("agenda --days 1"));
+        assert!(config.validate().is_ok());
+        for name in ["list", "ls", "__complete"] {
+            let mut config = Config::default();
+            config
+                .aliases
+                .insert(name.to_string(), String::from("next"));
+            assert!(config.validate().is_err(), "{name}");
+        }
+    }
+}
@@ -1,31 +1,142 @@
-use rusqlite::{Connection, Result as SqlResult};
-use std::path::PathBuf;
+use rusqlite::{Connection, OpenFlags, Transaction, TransactionBehavior};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    ops::Deref,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
-use crate::hierarchy::{Priority, Project, Task};
+use crate::{
+    command::Period,
+    error::{Result, TodoError},
+    hierarchy::{Annotation, Priority, Project, Task, TimeEntry},
+    history::{Change, diff},
+    replica::{self, FIELDS, MergeReport, Stamp, TRASHED, new_uuid},
+    stats::{ProjectCount, Stats, StatsQuery, streaks},
+    storage::{Storage, Trash, TrashedProject, TrashedTask, stale_task},
+};
 use chrono::{DateTime, NaiveDate, Utc};
 
+/// Condition on `tasks` rows that are neither trashed nor in a trashed project.
+const LIVE_TASKS: &str = "trashed_at IS NULL
+    AND project_id IN (SELECT id FROM projects WHERE trashed_at IS NULL)";
+
 #[derive(Debug)]
 pub struct Database {
     conn: Connection,
 }
 
+/// A transaction, or a part of the one `Storage::transaction` has open: that
+/// one then commits, or rolls everything back on an error.
+struct Tx<'a> {
+    conn: &'a Connection,
+    own: Option<Transaction<'a>>,
+}
+
+impl Tx<'_> {
+    fn commit(self) -> Result<()> {
+        if let Some(tx) = self.own {
+            tx.commit()?;
+        }
+        Ok(())
+    }
+}
+
+impl Deref for Tx<'_> {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.conn
+    }
+}
+
 impl Database {
-    pub fn new() -> SqlResult<Self> {
-        let db_path = Self::get_db_path();
-        let conn = Connection::open(db_path)?;
+    /// Opens the default database at `~/.todo.db`.
+    pub fn new() -> Result<Self> {
+        Self::open(Self::get_db_path())
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::from_connection(Connection::open(path)?)
+    }
+
+    /// Opens a private database that lives only as long as the returned value.
+    pub fn open_in_memory() -> Result<Self> {
+        Self::from_connection(Connection::open_in_memory()?)
+    }
+
+    /// Opens another replica for `merge_replica` without changing it: read-only,
+    /// and without the schema upgrade `open` does. A replica from before uuids
+    /// is refused, since its tasks can't be matched with these.
+    fn open_source(path: &Path) -> Result<Self> {
+        let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        conn.busy_timeout(Duration::from_secs(5))?;
+        let db = Database { conn };
+        let has_uuids = db.has_column("tasks", "uuid")?
+            && db.has_column("projects", "uuid")?
+            && !db.conn.query_row(
+                "SELECT EXISTS (SELECT 1 FROM tasks WHERE uuid IS NULL)
+                     OR EXISTS (SELECT 1 FROM projects WHERE uuid IS NULL)",
+                [],
+                |row| row.get::<_, bool>(0),
+            )?;
+        if !has_uuids {
+            return Err(TodoError::invalid(format!(
+                "cannot merge {}: it was last written by a version of todo without task \
+                 uuids, so its tasks can't be matched with these",
+                path.display()
+            )));
+        }
+        Ok(db)
+    }
+
+    fn from_connection(conn: Connection) -> Result<Self> {
         conn.execute("PRAGMA foreign_keys = ON", [])?;
+        // Another process may be writing: wait for it instead of failing, and let
+        // readers keep a consistent snapshot while it does. In-memory databases
+        // stay in their own journal mode.
+        conn.busy_timeout(Duration::from_secs(5))?;
+        conn.query_row("PRAGMA journal_mode = WAL", [], |row| {
+            row.get::<_, String>(0)
+        })?;
         let db = Database { conn };
         db.init_schema()?;
         Ok(db)
     }
 
+    /// Starts a transaction that takes the write lock up front, so two processes
+    /// never both read and then race to write.
+    fn write_transaction(&self) -> Result<Tx<'_>> {
+        self.begin(TransactionBehavior::Immediate)
+    }
+
+    /// Starts a transaction for reads that must see a single snapshot.
+    fn read_transaction(&self) -> Result<Tx<'_>> {
+        self.begin(TransactionBehavior::Deferred)
+    }
+
+    fn begin(&self, behavior: TransactionBehavior) -> Result<Tx<'_>> {
+        // SQLite doesn't nest transactions: inside one, join it.
+        let own = if self.conn.is_autocommit() {
+            Some(Transaction::new_unchecked(&self.conn, behavior)?)
+        } else {
+            None
+        };
+        Ok(Tx {
+            conn: &self.conn,
+            own,
+        })
+    }
+
     fn get_db_path() -> PathBuf {
         let mut path = dirs::home_dir().unwrap_or_else(|| PathBuf::from("."));
         path.push(".todo.db");
         path
     }
 
-    fn init_schema(&self) -> SqlResult<()> {
+    fn init_schema(&self) -> Result<()> {
+        // Two processes opening a new database at once must not both add columns.
+        let tx = self.write_transaction()?;
         self.conn.execute(
             "CREATE TABLE IF NOT EXISTS projects (
                 id INTEGER PRIMARY KEY,
@@ -50,6 +161,66 @@ impl Database {
             [],
         )?;
 
+        self.add_column_if_missing("tasks", "estimate_minutes", "INTEGER")?;
+        self.add_column_if_missing("tasks", "trashed_at", "TEXT")?;
+        self.add_column_if_missing("projects", "trashed_at", "TEXT")?;
+        self.add_column_if_missing("tasks", "version", "INTEGER NOT NULL DEFAULT 0")?;
+        self.add_column_if_missing("tasks", "uuid", "TEXT")?;
+        self.add_column_if_missing("projects", "uuid", "TEXT")?;
+
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS annotations (
+                id INTEGER PRIMARY KEY,
+                task_id INTEGER NOT NULL,
+                created_at TEXT NOT NULL,
+                text TEXT NOT NULL,
+                FOREIGN KEY (task_id) REFERENCES tasks(id) ON DELETE CASCADE
+            )",
+            [],
+        )?;
+
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS time_entries (
+                id INTEGER PRIMARY KEY,
+                task_id INTEGER NOT NULL,
+                started_at TEXT NOT NULL,
+                ended_at TEXT,
+                FOREIGN KEY (task_id) REFERENCES tasks(id) ON DELETE CASCADE
+            )",
+            [],
+        )?;
+
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS task_tags (
+                task_id INTEGER NOT NULL,
+                tag TEXT NOT NULL,
+                PRIMARY KEY (task_id, tag),
+                FOREIGN KEY (task_id) REFERENCES tasks(id) ON DELETE CASCADE
+            )",
+            [],
+        )?;
+
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS task_dependencies (
+                task_id INTEGER NOT NULL,
+                depends_on INTEGER NOT NULL,
+                PRIMARY KEY (task_id, depends_on),
+                FOREIGN KEY (task_id) REFERENCES tasks(id) ON DELETE CASCADE
+            )",
+            [],
+        )?;
+
+        // Archived tasks stay in `tasks` with their details; a row here hides
+        // them from the projects.
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS archive (
+                task_id INTEGER PRIMARY KEY,
+                archived_at TEXT NOT NULL,
+                FOREIGN KEY (task_id) REFERENCES tasks(id) ON DELETE CASCADE
+            )",
+            [],
+        )?;
+
         self.conn.execute(
             "CREATE TABLE IF NOT EXISTS app_state (
                 id INTEGER PRIMARY KEY CHECK (id = 1),
@@ -57,104 +228,456 @@ impl Database {
             )",
             [],
         )?;
+        self.add_column_if_missing("app_state", "replica_id", "TEXT")?;
+        self.add_column_if_missing("app_state", "clock", "INTEGER NOT NULL DEFAULT 0")?;
+        self.add_column_if_missing("app_state", "next_project_id", "INTEGER NOT NULL DEFAULT 0")?;
+        self.conn.execute(
+            "INSERT OR IGNORE INTO app_state (id, next_task_id) VALUES (1, 0)",
+            [],
+        )?;
+        self.conn.execute(
+            "UPDATE app_state SET replica_id = ?1 WHERE replica_id IS NULL",
+            [new_uuid()],
+        )?;
+
+        // Tasks and projects from before uuids existed get one here.
+        for table in ["tasks", "projects"] {
+            let mut stmt = self
+                .conn
+                .prepare(&format!("SELECT id FROM {table} WHERE uuid IS NULL"))?;
+            let ids = stmt
+                .query_map([], |row| row.get::<_, usize>(0))?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            for id in ids {
+                self.conn.execute(
+                    &format!("UPDATE {table} SET uuid = ?2 WHERE id = ?1"),
+                    rusqlite::params![id, new_uuid()],
+                )?;
+            }
+            self.conn.execute(
+                &format!("CREATE UNIQUE INDEX IF NOT EXISTS {table}_uuid ON {table} (uuid)"),
+                [],
+            )?;
+        }
+
+        // No foreign key: the history of a task outlives it. `task_id` is the
+        // short id the task had at the time; the uuid is what ties rows to it.
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS history (
+                id INTEGER PRIMARY KEY,
+                task_id INTEGER NOT NULL,
+                task_uuid TEXT,
+                at TEXT NOT NULL,
+                field TEXT NOT NULL,
+                old_value TEXT,
+                new_value TEXT
+            )",
+            [],
+        )?;
+        if !self.has_column("history", "task_uuid")? {
+            // Older changes go to the task that has their id now; those of tasks
+            // since deleted stay unattributed.
+            self.conn
+                .execute("DROP TRIGGER IF EXISTS history_no_update", [])?;
+            self.add_column_if_missing("history", "task_uuid", "TEXT")?;
+            self.conn.execute(
+                "UPDATE history SET task_uuid = (SELECT uuid FROM tasks WHERE tasks.id = history.task_id)",
+                [],
+            )?;
+        }
+        self.conn.execute("DROP INDEX IF EXISTS history_task", [])?;
+        self.conn.execute(
+            "CREATE INDEX IF NOT EXISTS history_task_uuid ON history (task_uuid)",
+            [],
+        )?;
+        for statement in ["UPDATE", "DELETE"] {
+            self.conn.execute(
+                &format!(
+                    "CREATE TRIGGER IF NOT EXISTS history_no_{} BEFORE {statement} ON history
+                     BEGIN SELECT RAISE(ABORT, 'history is append-only'); END",
+                    statement.to_lowercase()
+                ),
+                [],
+            )?;
+        }
+
+        // When each field of a task was last written, for `merge`.
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS task_clocks (
+                task_uuid TEXT NOT NULL,
+                field TEXT NOT NULL,
+                clock INTEGER NOT NULL,
+                replica TEXT NOT NULL,
+                PRIMARY KEY (task_uuid, field)
+            )",
+            [],
+        )?;
+        // Tasks deleted for good, so that a merge doesn't bring them back.
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS tombstones (task_uuid TEXT PRIMARY KEY)",
+            [],
+        )?;
+        // Working-set numbers given out by `gc`; local, like the ids they map to.
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS working_set (
+                number INTEGER PRIMARY KEY,
+                task_id INTEGER NOT NULL
+            )",
+            [],
+        )?;
 
+        tx.commit()?;
         Ok(())
     }
 
-    pub fn save_projects(&self, projects: &[Project]) -> SqlResult<()> {
-        let tx = self.conn.unchecked_transaction()?;
-        
-        tx.execute("DELETE FROM tasks", [])?;
-        tx.execute("DELETE FROM projects", [])?;
-
-        for project in projects {
-            tx.execute(
-                "INSERT INTO projects (id, name, parent_id) VALUES (?1, ?2, ?3)",
-                [&project.id.to_string(), &project.name, &project.parent_id.to_string()],
-            )?;
+    /// SQL expression mapping a stored UTC timestamp column to the local start of its stats bucket.
+    fn bucket_expr(column: &str, query: &StatsQuery) -> String {
+        match query.period {
+            None => format!("date({column}, 'localtime')"),
+            Some(Period::Week) => format!("date({column}, 'localtime', 'weekday 0', '-6 days')"),
+            Some(Period::Month) => format!("date({column}, 'localtime', 'start of month')"),
+        }
+    }
 
-            for task in &project.tasks {
-                let priority = task.priority() as i32;
-                let created_at = task.created_at().to_rfc3339();
-                let due_time = task.due_time().map(|d| d.to_string());
-                let completed_at = task.completed_at().map(|d| d.to_rfc3339());
+    /// Counts rows per bucket of `column`, from the bucket holding `query.since` on.
+    fn count_by_bucket(
+        &self,
+        column: &str,
+        query: &StatsQuery,
+    ) -> Result<BTreeMap<NaiveDate, usize>> {
+        let bucket = Self::bucket_expr(column, query);
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {bucket} AS bucket, COUNT(*) FROM tasks
+             WHERE {column} IS NOT NULL AND date({column}, 'localtime') >= ?1 AND {LIVE_TASKS}
+             GROUP BY bucket"
+        ))?;
+        let rows = stmt.query_map([query.since.to_string()], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, usize>(1)?))
+        })?;
 
-                tx.execute(
-                    "INSERT INTO tasks (id, project_id, name, description, priority, created_at, due_time, completed_at) 
-                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
-                    rusqlite::params![
-                        task.id(),
-                        task.project_id(),
-                        task.name(),
-                        task.description(),
-                        priority,
-                        created_at,
-                        due_time,
-                        completed_at,
-                    ],
-                )?;
+        let mut counts = BTreeMap::new();
+        for row in rows {
+            let (bucket, count) = row?;
+            if let Ok(day) = NaiveDate::parse_from_str(&bucket, "%Y-%m-%d") {
+                counts.insert(day, count);
             }
         }
+        Ok(counts)
+    }
 
-        tx.commit()?;
+    /// Upgrades tables created by older versions, which `CREATE TABLE IF NOT EXISTS` leaves alone.
+    fn add_column_if_missing(&self, table: &str, column: &str, decl: &str) -> Result<()> {
+        if !self.has_column(table, column)? {
+            self.conn.execute(
+                &format!("ALTER TABLE {table} ADD COLUMN {column} {decl}"),
+                [],
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Whether `table` exists and has `column`.
+    fn has_column(&self, table: &str, column: &str) -> Result<bool> {
+        let mut stmt = self.conn.prepare(&format!("PRAGMA table_info({table})"))?;
+        let exists = stmt
+            .query_map([], |row| row.get::<_, String>(1))?
+            .filter_map(|name| name.ok())
+            .any(|name| name == column);
+        Ok(exists)
+    }
+
+    fn insert_task_row(conn: &Connection, task: &Task) -> Result<()> {
+        let uuid = match task.uuid() {
+            "" => new_uuid(),
+            uuid => uuid.to_string(),
+        };
+        let priority = task.priority() as i32;
+        let created_at = task.created_at().to_rfc3339();
+        let due_time = task.due_time().map(|d| d.to_string());
+        let completed_at = task.completed_at().map(|d| d.to_rfc3339());
+
+        conn.execute(
+            "INSERT INTO tasks (id, project_id, name, description, priority, created_at, due_time, completed_at, estimate_minutes, version, uuid)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+            rusqlite::params![
+                task.id(),
+                task.project_id(),
+                task.name(),
+                task.description(),
+                priority,
+                created_at,
+                due_time,
+                completed_at,
+                task.estimate().map(|d| d.num_minutes()),
+                task.version(),
+                uuid,
+            ],
+        )?;
+        Self::insert_task_details(conn, task)
+    }
+
+    /// Overwrites a stored task and its details with `task`, at `version`.
+    fn update_task_row(conn: &Connection, task: &Task, version: u64) -> Result<()> {
+        conn.execute(
+            "UPDATE tasks SET project_id = ?2, name = ?3, description = ?4, priority = ?5,
+                              created_at = ?6, due_time = ?7, completed_at = ?8, estimate_minutes = ?9,
+                              version = ?10
+             WHERE id = ?1",
+            rusqlite::params![
+                task.id(),
+                task.project_id(),
+                task.name(),
+                task.description(),
+                task.priority() as i32,
+                task.created_at().to_rfc3339(),
+                task.due_time().map(|d| d.to_string()),
+                task.completed_at().map(|d| d.to_rfc3339()),
+                task.estimate().map(|d| d.num_minutes()),
+                version,
+            ],
+        )?;
+        conn.execute("DELETE FROM annotations WHERE task_id = ?1", [task.id()])?;
+        conn.execute("DELETE FROM time_entries WHERE task_id = ?1", [task.id()])?;
+        conn.execute("DELETE FROM task_tags WHERE task_id = ?1", [task.id()])?;
+        conn.execute(
+            "DELETE FROM task_dependencies WHERE task_id = ?1",
+            [task.id()],
+        )?;
+        Self::insert_task_details(conn, task)
+    }
+
+    /// Writes the rows that hang off a task: annotations, time entries, tags and
+    /// dependencies.
+    fn insert_task_details(conn: &Connection, task: &Task) -> Result<()> {
+        for tag in task.tags() {
+            conn.execute(
+                "INSERT INTO task_tags (task_id, tag) VALUES (?1, ?2)",
+                rusqlite::params![task.id(), tag],
+            )?;
+        }
+        for depends_on in task.depends_on() {
+            conn.execute(
+                "INSERT INTO task_dependencies (task_id, depends_on) VALUES (?1, ?2)",
+                rusqlite::params![task.id(), depends_on],
+            )?;
+        }
+        for annotation in task.annotations() {
+            conn.execute(
+                "INSERT INTO annotations (task_id, created_at, text) VALUES (?1, ?2, ?3)",
+                rusqlite::params![
+                    task.id(),
+                    annotation.created_at.to_rfc3339(),
+                    annotation.text,
+                ],
+            )?;
+        }
+        for entry in task.time_entries() {
+            conn.execute(
+                "INSERT INTO time_entries (task_id, started_at, ended_at) VALUES (?1, ?2, ?3)",
+                rusqlite::params![
+                    task.id(),
+                    entry.started_at.to_rfc3339(),
+                    entry.ended_at.map(|d| d.to_rfc3339()),
+                ],
+            )?;
+        }
         Ok(())
     }
 
-    pub fn load_projects(&self) -> SqlResult<Vec<Project>> {
+    fn load_annotations(&self, task_id: usize) -> Result<Vec<Annotation>> {
         let mut stmt = self
             .conn
-            .prepare("SELECT id, name, parent_id FROM projects ORDER BY id")?;
-        let project_iter = stmt.query_map([], |row| {
-            Ok((
-                row.get::<_, usize>(0)?,
-                row.get::<_, String>(1)?,
-                row.get::<_, usize>(2)?,
-            ))
+            .prepare("SELECT created_at, text FROM annotations WHERE task_id = ?1 ORDER BY id")?;
+        let rows = stmt.query_map([task_id], |row| {
+            let created_at = row.get::<_, String>(0)?;
+            Ok(Annotation {
+                created_at: DateTime::parse_from_rfc3339(&created_at)
+                    .map(|dt| dt.with_timezone(&Utc))
+                    .unwrap_or_else(|_| Utc::now()),
+                text: row.get(1)?,
+            })
         })?;
 
-        let mut projects = Vec::new();
-        for project_result in project_iter {
-            let (id, name, parent_id) = project_result?;
-            let tasks = self.load_tasks_for_project(id)?;
-            projects.push(Project {
-                id,
-                name,
-                parent_id,
-                tasks,
-            });
+        let mut annotations = Vec::new();
+        for annotation in rows {
+            annotations.push(annotation?);
+        }
+        Ok(annotations)
+    }
+
+    fn insert_changes(conn: &Connection, changes: &[Change]) -> Result<()> {
+        for change in changes {
+            conn.execute(
+                "INSERT INTO history (task_id, task_uuid, at, field, old_value, new_value)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                rusqlite::params![
+                    change.task_id,
+                    change.task_uuid,
+                    change.at.to_rfc3339(),
+                    change.field,
+                    change.old,
+                    change.new,
+                ],
+            )?;
         }
+        Ok(())
+    }
 
-        Ok(projects)
+    /// Records that the tasks matching the SQL `condition` entered or left `state`.
+    fn record_state<P: rusqlite::Params>(
+        conn: &Connection,
+        condition: &str,
+        params: P,
+        state: &str,
+        entered: bool,
+    ) -> Result<()> {
+        let mut stmt = conn.prepare(&format!("SELECT id, uuid FROM tasks WHERE {condition}"))?;
+        let tasks = stmt
+            .query_map(params, |row| {
+                Ok((row.get::<_, usize>(0)?, row.get::<_, String>(1)?))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        let now = Utc::now();
+        let changes: Vec<Change> = tasks
+            .iter()
+            .map(|(id, uuid)| Change::entered(*id, uuid, now, state, entered))
+            .collect();
+        Self::insert_changes(conn, &changes)
+    }
+
+    /// Uuids of the tasks matching the SQL `condition`.
+    fn task_uuids<P: rusqlite::Params>(
+        conn: &Connection,
+        condition: &str,
+        params: P,
+    ) -> Result<Vec<String>> {
+        let mut stmt = conn.prepare(&format!("SELECT uuid FROM tasks WHERE {condition}"))?;
+        let uuids = stmt
+            .query_map(params, |row| row.get::<_, String>(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(uuids)
     }
 
-    fn load_tasks_for_project(&self, project_id: usize) -> SqlResult<Vec<Task>> {
+    /// Advances the Lamport clock and stamps `fields` of the given tasks with it.
+    fn stamp(conn: &Connection, uuids: &[String], fields: &[&str]) -> Result<()> {
+        if uuids.is_empty() || fields.is_empty() {
+            return Ok(());
+        }
+        let (clock, replica) = conn.query_row(
+            "UPDATE app_state SET clock = clock + 1 WHERE id = 1 RETURNING clock, replica_id",
+            [],
+            |row| Ok((row.get::<_, u64>(0)?, row.get::<_, String>(1)?)),
+        )?;
+        let stamp = Stamp { clock, replica };
+        for uuid in uuids {
+            for field in fields {
+                Self::set_stamp(conn, uuid, field, &stamp)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn set_stamp(conn: &Connection, uuid: &str, field: &str, stamp: &Stamp) -> Result<()> {
+        conn.execute(
+            "INSERT OR REPLACE INTO task_clocks (task_uuid, field, clock, replica)
+             VALUES (?1, ?2, ?3, ?4)",
+            rusqlite::params![uuid, field, stamp.clock, stamp.replica],
+        )?;
+        Ok(())
+    }
+
+    /// Leaves tombstones for the tasks matching `condition`, which are about to be
+    /// deleted.
+    fn bury<P: rusqlite::Params>(conn: &Connection, condition: &str, params: P) -> Result<()> {
+        conn.execute(
+            &format!(
+                "INSERT OR IGNORE INTO tombstones (task_uuid)
+                 SELECT uuid FROM tasks WHERE {condition}"
+            ),
+            params,
+        )?;
+        Ok(())
+    }
+
+    /// Values of `column` in the detail `table` for one task, in insertion order.
+    fn load_task_values<T: rusqlite::types::FromSql>(
+        &self,
+        table: &str,
+        column: &str,
+        task_id: usize,
+    ) -> Result<Vec<T>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {column} FROM {table} WHERE task_id = ?1 ORDER BY rowid"
+        ))?;
+        let rows = stmt.query_map([task_id], |row| row.get(0))?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn load_time_entries(&self, task_id: usize) -> Result<Vec<TimeEntry>> {
         let mut stmt = self.conn.prepare(
-            "SELECT id, project_id, name, description, priority, created_at, due_time, completed_at 
-             FROM tasks WHERE project_id = ?1 ORDER BY id",
+            "SELECT started_at, ended_at FROM time_entries WHERE task_id = ?1 ORDER BY started_at",
         )?;
+        let rows = stmt.query_map([task_id], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, Option<String>>(1)?))
+        })?;
+
+        // A bad end can't be read as a running timer, nor a bad start skipped:
+        // either would change the tracked time without saying so.
+        let parse = |s: &str| {
+            DateTime::parse_from_rfc3339(s)
+                .map(|dt| dt.with_timezone(&Utc))
+                .map_err(|e| {
+                    TodoError::format(format!(
+                        "task {task_id}: invalid time entry timestamp '{s}': {e}"
+                    ))
+                })
+        };
+        let mut entries = Vec::new();
+        for row in rows {
+            let (started_at, ended_at) = row?;
+            entries.push(TimeEntry {
+                started_at: parse(&started_at)?,
+                ended_at: ended_at.as_deref().map(parse).transpose()?,
+            });
+        }
+        Ok(entries)
+    }
 
-        let task_iter = stmt.query_map([project_id], |row| {
+    fn load_tasks_for_project(&self, project_id: usize) -> Result<Vec<Task>> {
+        self.load_tasks(
+            "project_id = ?1 AND trashed_at IS NULL AND id NOT IN (SELECT task_id FROM archive)",
+            [project_id],
+        )
+    }
+
+    /// Loads the tasks matching the SQL `condition`, with their details.
+    fn load_tasks<P: rusqlite::Params>(&self, condition: &str, params: P) -> Result<Vec<Task>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT id, project_id, name, description, priority, created_at, due_time, completed_at, estimate_minutes, version, uuid
+             FROM tasks WHERE {condition} ORDER BY id"
+        ))?;
+
+        let task_iter = stmt.query_map(params, |row| {
             let id = row.get::<_, usize>(0)?;
             let project_id = row.get::<_, usize>(1)?;
             let name = row.get::<_, String>(2)?;
             let description = row.get::<_, String>(3)?;
-            let priority_int = row.get::<_, i32>(4)?;
+            let priority_int = row.get::<_, i64>(4)?;
             let created_at_str = row.get::<_, String>(5)?;
             let due_time_str = row.get::<_, Option<String>>(6)?;
             let completed_at_str = row.get::<_, Option<String>>(7)?;
+            let estimate_minutes = row.get::<_, Option<i64>>(8)?;
+            let version = row.get::<_, u64>(9)?;
+            let uuid = row.get::<_, String>(10)?;
 
-            let priority = match priority_int {
-                1 => Priority::Low,
-                2 => Priority::Medium,
-                3 => Priority::High,
-                _ => Priority::None,
-            };
+            let priority = Priority::from_level(priority_int);
 
             let created_at = DateTime::parse_from_rfc3339(&created_at_str)
                 .map(|dt| dt.with_timezone(&Utc))
                 .unwrap_or_else(|_| Utc::now());
 
-            let due_time = due_time_str.and_then(|s| NaiveDate::parse_from_str(&s, "%Y-%m-%d").ok());
+            let due_time =
+                due_time_str.and_then(|s| NaiveDate::parse_from_str(&s, "%Y-%m-%d").ok());
 
             let completed_at = completed_at_str.and_then(|s| {
                 DateTime::parse_from_rfc3339(&s)
@@ -162,7 +685,7 @@ impl Database {
                     .ok()
             });
 
-            Ok(Task::new(
+            let mut task = Task::new(
                 id,
                 project_id,
                 name,
@@ -171,36 +694,1299 @@ impl Database {
                 created_at,
                 due_time,
                 completed_at,
-            ))
+            );
+            task.set_estimate(estimate_minutes.map(chrono::Duration::minutes));
+            task.set_version(version);
+            task.set_uuid(uuid);
+            Ok(task)
         })?;
 
         let mut tasks = Vec::new();
         for task_result in task_iter {
-            tasks.push(task_result?);
+            let mut task = task_result?;
+            task.set_annotations(self.load_annotations(task.id())?);
+            task.set_time_entries(self.load_time_entries(task.id())?);
+            task.set_tags(self.load_task_values("task_tags", "tag", task.id())?);
+            task.set_depends_on(self.load_task_values(
+                "task_dependencies",
+                "depends_on",
+                task.id(),
+            )?);
+            tasks.push(task);
         }
 
         Ok(tasks)
     }
 
-    pub fn save_next_task_id(&self, next_task_id: usize) -> SqlResult<()> {
-        self.conn.execute(
-            "INSERT OR REPLACE INTO app_state (id, next_task_id) VALUES (1, ?1)",
-            [next_task_id],
+    /// Everything `merge_replica` needs to know about one replica.
+    fn replica_state(&self) -> Result<ReplicaState> {
+        let (replica_id, clock) = self.conn.query_row(
+            "SELECT replica_id, clock FROM app_state WHERE id = 1",
+            [],
+            |row| Ok((row.get::<_, String>(0)?, row.get::<_, u64>(1)?)),
         )?;
-        Ok(())
+
+        let mut state = ReplicaState {
+            replica_id,
+            clock,
+            ..ReplicaState::default()
+        };
+        for task in self.load_tasks("1 = 1", [])? {
+            state.tasks.insert(task.uuid().to_string(), task);
+        }
+
+        let mut stmt = self
+            .conn
+            .prepare("SELECT uuid, trashed_at, id IN (SELECT task_id FROM archive) FROM tasks")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, Option<String>>(1)?,
+                row.get::<_, bool>(2)?,
+            ))
+        })?;
+        for row in rows {
+            let (uuid, trashed_at, archived) = row?;
+            if archived {
+                state.archived.insert(uuid.clone());
+            }
+            state.trashed.insert(uuid, trashed_at);
+        }
+
+        let mut stmt = self
+            .conn
+            .prepare("SELECT task_uuid, field, clock, replica FROM task_clocks")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                (row.get::<_, String>(0)?, row.get::<_, String>(1)?),
+                Stamp {
+                    clock: row.get(2)?,
+                    replica: row.get(3)?,
+                },
+            ))
+        })?;
+        for row in rows {
+            let (key, stamp) = row?;
+            state.stamps.insert(key, stamp);
+        }
+
+        let mut stmt = self.conn.prepare("SELECT task_uuid FROM tombstones")?;
+        for uuid in stmt.query_map([], |row| row.get::<_, String>(0))? {
+            state.tombstones.insert(uuid?);
+        }
+
+        let mut stmt = self
+            .conn
+            .prepare("SELECT id, name, parent_id, trashed_at IS NOT NULL, uuid FROM projects")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, usize>(0)?,
+                ProjectRow {
+                    name: row.get(1)?,
+                    parent_id: row.get(2)?,
+                    trashed: row.get(3)?,
+                    uuid: row.get(4)?,
+                },
+            ))
+        })?;
+        for row in rows {
+            let (id, project) = row?;
+            state.projects.insert(id, project);
+        }
+        Ok(state)
     }
 
-    pub fn load_next_task_id(&self) -> SqlResult<usize> {
-        let result: Result<usize, _> = self.conn.query_row(
-            "SELECT next_task_id FROM app_state WHERE id = 1",
-            [],
-            |row| row.get(0),
-        );
+    /// Merges the database at `path` into this one. Tasks are matched by uuid and
+    /// each field keeps the value with the later stamp, so merging in either
+    /// direction ends with the same data. Tasks new to this side get fresh short
+    /// ids. Projects are matched by uuid, or else by name.
+    fn merge_replica(&self, path: &Path) -> Result<MergeReport> {
+        if let Some(own) = self.conn.path() {
+            if Path::new(own).canonicalize().ok() == path.canonicalize().ok() {
+                return Err(TodoError::invalid("cannot merge a database with itself"));
+            }
+        }
+        if !path.exists() {
+            return Err(TodoError::not_found(format!(
+                "{} not found",
+                path.display()
+            )));
+        }
+        let theirs = Database::open_source(path)?.replica_state()?;
+        let tx = self.write_transaction()?;
+        let ours = self.replica_state()?;
+        let mut report = MergeReport::default();
+        let now = Utc::now();
 
-        match result {
-            Ok(id) => Ok(id),
-            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(0),
-            Err(e) => Err(e),
+        // A task deleted on either side stays deleted.
+        for uuid in &theirs.tombstones {
+            if let Some(task) = ours.tasks.get(uuid) {
+                Self::record_state(&tx, "id = ?1", [task.id()], "deleted", true)?;
+                tx.execute("DELETE FROM tasks WHERE id = ?1", [task.id()])?;
+                report.removed += 1;
+            }
+            tx.execute(
+                "INSERT OR IGNORE INTO tombstones (task_uuid) VALUES (?1)",
+                [uuid],
+            )?;
+        }
+
+        // Missing projects are created, under the same parent when that one is
+        // known here.
+        let mut project_ids: BTreeMap<usize, usize> = BTreeMap::new();
+        let mut next_project_id = ours.projects.keys().max().map_or(0, |id| id + 1);
+        let mut created = Vec::new();
+        for (&their_id, project) in &theirs.projects {
+            let by_uuid = ours.projects.iter().find(|(_, p)| p.uuid == project.uuid);
+            let by_name = ours
+                .projects
+                .iter()
+                .filter(|(_, p)| p.name == project.name)
+                .min_by_key(|(_, p)| p.trashed);
+            let id = match by_uuid.or(by_name) {
+                Some((&id, _)) => id,
+                None => {
+                    tx.execute(
+                        "INSERT INTO projects (id, name, parent_id, uuid) VALUES (?1, ?2, 0, ?3)",
+                        rusqlite::params![next_project_id, project.name, project.uuid],
+                    )?;
+                    created.push(their_id);
+                    next_project_id += 1;
+                    next_project_id - 1
+                }
+            };
+            project_ids.insert(their_id, id);
+        }
+        for their_id in created {
+            let parent = theirs.projects[&their_id].parent_id;
+            if let (Some(id), Some(parent)) = (project_ids.get(&their_id), project_ids.get(&parent))
+            {
+                tx.execute(
+                    "UPDATE projects SET parent_id = ?2 WHERE id = ?1",
+                    [id, parent],
+                )?;
+            }
+        }
+
+        // Short ids are local: tasks new to this side get the next free ones.
+        let mut incoming: Vec<&Task> = theirs
+            .tasks
+            .values()
+            .filter(|t| !ours.tombstones.contains(t.uuid()))
+            .collect();
+        incoming.sort_by_key(|t| t.id());
+        let mut ids: BTreeMap<&str, usize> =
+            ours.tasks.values().map(|t| (t.uuid(), t.id())).collect();
+        let mut next_id = self
+            .load_next_task_id()?
+            .max(ours.tasks.values().map(|t| t.id() + 1).max().unwrap_or(0));
+        for task in &incoming {
+            if !ids.contains_key(task.uuid()) {
+                ids.insert(task.uuid(), next_id);
+                next_id += 1;
+            }
+        }
+        let their_uuids: BTreeMap<usize, &str> =
+            theirs.tasks.values().map(|t| (t.id(), t.uuid())).collect();
+
+        for their_task in incoming {
+            let uuid = their_task.uuid();
+            // Their task in our terms.
+            let mut task = their_task.clone();
+            task.set_id(ids[uuid]);
+            task.set_depends_on(
+                their_task
+                    .depends_on()
+                    .iter()
+                    .filter_map(|d| their_uuids.get(d).and_then(|u| ids.get(u)).copied())
+                    .collect(),
+            );
+            let mut project = serde_json::Map::new();
+            project.insert(
+                String::from("project_id"),
+                project_ids
+                    .get(&their_task.project_id())
+                    .copied()
+                    .unwrap_or(0)
+                    .into(),
+            );
+            let mut task = replica::with_fields(&task, project)?;
+            let their_trashed = theirs.trashed.get(uuid).cloned().flatten();
+
+            let Some(local) = ours.tasks.get(uuid) else {
+                task.set_version(0);
+                Self::insert_task_row(&tx, &task)?;
+                tx.execute(
+                    "UPDATE tasks SET trashed_at = ?2 WHERE id = ?1",
+                    rusqlite::params![task.id(), their_trashed],
+                )?;
+                if theirs.archived.contains(uuid) {
+                    tx.execute(
+                        "INSERT INTO archive (task_id, archived_at) VALUES (?1, ?2)",
+                        rusqlite::params![task.id(), now.to_rfc3339()],
+                    )?;
+                }
+                for field in FIELDS.iter().chain([&TRASHED]) {
+                    if let Some(stamp) = theirs.stamp(uuid, field) {
+                        Self::set_stamp(&tx, uuid, field, stamp)?;
+                    }
+                }
+                Self::insert_changes(&tx, &[Change::created(&task, now)])?;
+                report.added += 1;
+                continue;
+            };
+
+            let (mine, their_fields) = (replica::fields(local)?, replica::fields(&task)?);
+            let mut taken = serde_json::Map::new();
+            for field in FIELDS {
+                let (value, theirs_value) = (mine.get(*field), their_fields.get(*field));
+                if theirs.wins(&ours, uuid, field, value, theirs_value) {
+                    if let Some(value) = theirs_value {
+                        taken.insert(field.to_string(), value.clone());
+                    }
+                    if let Some(stamp) = theirs.stamp(uuid, field) {
+                        Self::set_stamp(&tx, uuid, field, stamp)?;
+                    }
+                }
+            }
+            let mut changed = false;
+            if !taken.is_empty() {
+                let merged = replica::with_fields(local, taken)?;
+                Self::update_task_row(&tx, &merged, local.version() + 1)?;
+                Self::insert_changes(&tx, &diff(local, &merged, now))?;
+                changed = true;
+            }
+
+            let our_trashed = ours.trashed.get(uuid).cloned().flatten();
+            let (ours_json, theirs_json) = (
+                our_trashed.clone().map(serde_json::Value::from),
+                their_trashed.clone().map(serde_json::Value::from),
+            );
+            if theirs.wins(
+                &ours,
+                uuid,
+                TRASHED,
+                ours_json.as_ref(),
+                theirs_json.as_ref(),
+            ) {
+                tx.execute(
+                    "UPDATE tasks SET trashed_at = ?2 WHERE id = ?1",
+                    rusqlite::params![local.id(), their_trashed],
+                )?;
+                let change = Change::state(local, now, "trashed", their_trashed.is_some());
+                Self::insert_changes(&tx, &[change])?;
+                if let Some(stamp) = theirs.stamp(uuid, TRASHED) {
+                    Self::set_stamp(&tx, uuid, TRASHED, stamp)?;
+                }
+                changed = true;
+            }
+
+            // Archiving can't be undone, so it only spreads.
+            if theirs.archived.contains(uuid) && !ours.archived.contains(uuid) {
+                tx.execute(
+                    "INSERT INTO archive (task_id, archived_at) VALUES (?1, ?2)",
+                    rusqlite::params![local.id(), now.to_rfc3339()],
+                )?;
+                Self::record_state(&tx, "id = ?1", [local.id()], "archived", true)?;
+                changed = true;
+            }
+            if changed {
+                report.updated += 1;
+            }
+        }
+
+        // Lamport: later local writes must stamp above anything seen.
+        tx.execute(
+            "UPDATE app_state SET clock = MAX(clock, ?1), next_task_id = MAX(next_task_id, ?2)
+             WHERE id = 1",
+            rusqlite::params![theirs.clock, next_id],
+        )?;
+        tx.commit()?;
+        Ok(report)
+    }
+}
+
+impl Storage for Database {
+    fn load_projects(&self) -> Result<Vec<Project>> {
+        // Read everything from one snapshot, even if a writer commits halfway.
+        let _snapshot = self.read_transaction()?;
+        let mut stmt = self.conn.prepare(
+            "SELECT id, name, parent_id, uuid FROM projects WHERE trashed_at IS NULL ORDER BY id",
+        )?;
+        let project_iter = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, usize>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, usize>(2)?,
+                row.get::<_, String>(3)?,
+            ))
+        })?;
+
+        let mut projects = Vec::new();
+        for project_result in project_iter {
+            let (id, name, parent_id, uuid) = project_result?;
+            let tasks = self.load_tasks_for_project(id)?;
+            projects.push(Project {
+                id,
+                uuid,
+                name,
+                parent_id,
+                tasks,
+            });
+        }
+
+        Ok(projects)
+    }
+
+    fn save_projects(&self, projects: &[Project]) -> Result<()> {
+        let tx = self.write_transaction()?;
+
+        // Archived tasks aren't part of the projects, so they and the projects
+        // they belong to are kept.
+        tx.execute(
+            "DELETE FROM tasks
+             WHERE trashed_at IS NULL AND id NOT IN (SELECT task_id FROM archive)",
+            [],
+        )?;
+        tx.execute(
+            "DELETE FROM projects
+             WHERE trashed_at IS NULL AND id NOT IN (SELECT project_id FROM tasks)",
+            [],
+        )?;
+
+        for project in projects {
+            tx.execute(
+                "INSERT OR REPLACE INTO projects (id, name, parent_id, uuid) VALUES (?1, ?2, ?3, ?4)",
+                rusqlite::params![
+                    project.id,
+                    project.name,
+                    project.parent_id,
+                    project_uuid(project)
+                ],
+            )?;
+
+            for task in &project.tasks {
+                Self::insert_task_row(&tx, task)?;
+            }
+        }
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn save_next_task_id(&self, next_task_id: usize) -> Result<()> {
+        self.conn.execute(
+            "UPDATE app_state SET next_task_id = ?1 WHERE id = 1",
+            [next_task_id],
+        )?;
+        Ok(())
+    }
+
+    fn load_next_task_id(&self) -> Result<usize> {
+        let result: rusqlite::Result<usize> = self.conn.query_row(
+            "SELECT next_task_id FROM app_state WHERE id = 1",
+            [],
+            |row| row.get(0),
+        );
+
+        match result {
+            Ok(id) => Ok(id),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(0),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn load_archive(&self) -> Result<Vec<Task>> {
+        self.load_tasks(
+            &format!("id IN (SELECT task_id FROM archive) AND {LIVE_TASKS}"),
+            [],
+        )
+    }
+
+    fn save_archive(&self, tasks: &[Task]) -> Result<()> {
+        let tx = self.write_transaction()?;
+        tx.execute(
+            "DELETE FROM tasks WHERE id IN (SELECT task_id FROM archive)",
+            [],
+        )?;
+        let now = Utc::now().to_rfc3339();
+        for task in tasks {
+            Self::insert_task_row(&tx, task)?;
+            tx.execute(
+                "INSERT INTO archive (task_id, archived_at) VALUES (?1, ?2)",
+                rusqlite::params![task.id(), now],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn archive_tasks(&self, task_ids: &[usize]) -> Result<()> {
+        let tx = self.write_transaction()?;
+        let now = Utc::now().to_rfc3339();
+        for id in task_ids {
+            tx.execute(
+                "INSERT OR IGNORE INTO archive (task_id, archived_at) VALUES (?1, ?2)",
+                rusqlite::params![id, now],
+            )?;
+            Self::record_state(&tx, "id = ?1", [id], "archived", true)?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn purge_archived(&self, task_ids: &[usize]) -> Result<()> {
+        let tx = self.write_transaction()?;
+        for id in task_ids {
+            let archived = "id = ?1 AND id IN (SELECT task_id FROM archive)";
+            Self::record_state(&tx, archived, [id], "deleted", true)?;
+            Self::bury(&tx, archived, [id])?;
+            tx.execute(&format!("DELETE FROM tasks WHERE {archived}"), [id])?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn load_history(
+        &self,
+        task: Option<&str>,
+        since: Option<DateTime<Utc>>,
+    ) -> Result<Vec<Change>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT task_id, task_uuid, at, field, old_value, new_value FROM history
+             WHERE (?1 IS NULL OR task_uuid = ?1) AND (?2 IS NULL OR at >= ?2)
+             ORDER BY id",
+        )?;
+        let rows = stmt.query_map(
+            rusqlite::params![task, since.map(|s| s.to_rfc3339())],
+            |row| {
+                Ok((
+                    row.get::<_, usize>(0)?,
+                    row.get::<_, Option<String>>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, String>(3)?,
+                    row.get::<_, Option<String>>(4)?,
+                    row.get::<_, Option<String>>(5)?,
+                ))
+            },
+        )?;
+
+        let mut changes = Vec::new();
+        for row in rows {
+            let (task_id, task_uuid, at, field, old, new) = row?;
+            let at = DateTime::parse_from_rfc3339(&at)
+                .map(|dt| dt.with_timezone(&Utc))
+                .unwrap_or_else(|_| Utc::now());
+            changes.push(Change {
+                at,
+                task_id,
+                task_uuid: task_uuid.unwrap_or_default(),
+                field,
+                old,
+                new,
+            });
+        }
+        Ok(changes)
+    }
+
+    fn append_history(&self, changes: &[Change]) -> Result<()> {
+        let tx = self.write_transaction()?;
+        Self::insert_changes(&tx, changes)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn load_working_set(&self) -> Result<Vec<usize>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT task_id FROM working_set ORDER BY number")?;
+        let task_ids = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<usize>>>()?;
+        Ok(task_ids)
+    }
+
+    fn save_working_set(&self, task_ids: &[usize]) -> Result<()> {
+        let tx = self.write_transaction()?;
+        tx.execute("DELETE FROM working_set", [])?;
+        for (i, task_id) in task_ids.iter().enumerate() {
+            tx.execute(
+                "INSERT INTO working_set (number, task_id) VALUES (?1, ?2)",
+                [i + 1, *task_id],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Numbers the task in a single statement, so concurrent adds each get their own.
+    fn extend_working_set(&self, task_id: usize) -> Result<usize> {
+        let number = self.conn.query_row(
+            "INSERT INTO working_set (task_id) VALUES (?1) RETURNING number",
+            [task_id],
+            |row| row.get(0),
+        )?;
+        Ok(number)
+    }
+
+    fn load_trash(&self) -> Result<Trash> {
+        let _snapshot = self.read_transaction()?;
+        let parse = |s: String| {
+            DateTime::parse_from_rfc3339(&s)
+                .map(|dt| dt.with_timezone(&Utc))
+                .unwrap_or_else(|_| Utc::now())
+        };
+
+        let mut stmt = self.conn.prepare(
+            "SELECT id, name, parent_id, trashed_at, uuid FROM projects
+             WHERE trashed_at IS NOT NULL ORDER BY trashed_at",
+        )?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, usize>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, usize>(2)?,
+                    row.get::<_, String>(3)?,
+                    row.get::<_, String>(4)?,
+                ))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        let mut projects = Vec::new();
+        for (id, name, parent_id, trashed_at, uuid) in rows {
+            let tasks = self.load_tasks(
+                "project_id = ?1 AND trashed_at = ?2",
+                rusqlite::params![id, trashed_at],
+            )?;
+            projects.push(TrashedProject {
+                trashed_at: parse(trashed_at),
+                project: Project {
+                    id,
+                    uuid,
+                    name,
+                    parent_id,
+                    tasks,
+                },
+            });
+        }
+
+        // Tasks trashed on their own, rather than along with their project.
+        let own = "trashed_at IS NOT NULL
+                   AND trashed_at IS NOT (SELECT trashed_at FROM projects WHERE id = tasks.project_id)";
+        let mut stmt = self
+            .conn
+            .prepare(&format!("SELECT id, trashed_at FROM tasks WHERE {own}"))?;
+        let trashed_at: BTreeMap<usize, String> = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<_>>()?;
+        let mut tasks: Vec<TrashedTask> = self
+            .load_tasks(own, [])?
+            .into_iter()
+            .map(|task| TrashedTask {
+                trashed_at: parse(trashed_at[&task.id()].clone()),
+                task,
+            })
+            .collect();
+        tasks.sort_by_key(|t| t.trashed_at);
+
+        Ok(Trash { projects, tasks })
+    }
+
+    fn save_trash(&self, trash: &Trash) -> Result<()> {
+        let tx = self.write_transaction()?;
+        tx.execute("DELETE FROM tasks WHERE trashed_at IS NOT NULL", [])?;
+        tx.execute(
+            "DELETE FROM tasks WHERE project_id IN (SELECT id FROM projects WHERE trashed_at IS NOT NULL)",
+            [],
+        )?;
+        tx.execute("DELETE FROM projects WHERE trashed_at IS NOT NULL", [])?;
+        for trashed in &trash.projects {
+            let at = trashed.trashed_at.to_rfc3339();
+            let project = &trashed.project;
+            tx.execute(
+                "INSERT INTO projects (id, name, parent_id, uuid, trashed_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                rusqlite::params![
+                    project.id,
+                    project.name,
+                    project.parent_id,
+                    project_uuid(project),
+                    at
+                ],
+            )?;
+            for task in &project.tasks {
+                Self::insert_task_row(&tx, task)?;
+                tx.execute(
+                    "UPDATE tasks SET trashed_at = ?2 WHERE id = ?1",
+                    rusqlite::params![task.id(), at],
+                )?;
+            }
+        }
+        for trashed in &trash.tasks {
+            Self::insert_task_row(&tx, &trashed.task)?;
+            tx.execute(
+                "UPDATE tasks SET trashed_at = ?2 WHERE id = ?1",
+                rusqlite::params![trashed.task.id(), trashed.trashed_at.to_rfc3339()],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn trash_task(&self, task_id: usize, at: DateTime<Utc>) -> Result<()> {
+        let tx = self.write_transaction()?;
+        let updated = tx.execute(
+            "UPDATE tasks SET trashed_at = ?2 WHERE id = ?1 AND trashed_at IS NULL",
+            rusqlite::params![task_id, at.to_rfc3339()],
+        )?;
+        if updated == 0 {
+            return Err(TodoError::not_found("task not found"));
+        }
+        Self::record_state(&tx, "id = ?1", [task_id], "trashed", true)?;
+        let uuids = Self::task_uuids(&tx, "id = ?1", [task_id])?;
+        Self::stamp(&tx, &uuids, &[TRASHED])?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Marks the project and its active tasks with the same timestamp, which is
+    /// how `restore_project` tells them from tasks trashed earlier on their own.
+    fn trash_project(&self, project_id: usize, at: DateTime<Utc>) -> Result<()> {
+        let tx = self.write_transaction()?;
+        let at = at.to_rfc3339();
+        let updated = tx.execute(
+            "UPDATE projects SET trashed_at = ?2 WHERE id = ?1 AND trashed_at IS NULL",
+            rusqlite::params![project_id, at],
+        )?;
+        if updated == 0 {
+            return Err(TodoError::not_found("project not found"));
+        }
+        tx.execute(
+            "UPDATE tasks SET trashed_at = ?2
+             WHERE project_id = ?1 AND trashed_at IS NULL
+               AND id NOT IN (SELECT task_id FROM archive)",
+            rusqlite::params![project_id, at],
+        )?;
+        let trashed = "project_id = ?1 AND trashed_at = ?2";
+        Self::record_state(
+            &tx,
+            trashed,
+            rusqlite::params![project_id, at],
+            "trashed",
+            true,
+        )?;
+        let uuids = Self::task_uuids(&tx, trashed, rusqlite::params![project_id, at])?;
+        Self::stamp(&tx, &uuids, &[TRASHED])?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn restore_task(&self, task_id: usize) -> Result<()> {
+        let tx = self.write_transaction()?;
+        let updated = tx.execute(
+            "UPDATE tasks SET trashed_at = NULL WHERE id = ?1 AND trashed_at IS NOT NULL",
+            [task_id],
+        )?;
+        if updated == 0 {
+            return Err(TodoError::not_found("task is not in the trash"));
+        }
+        Self::record_state(&tx, "id = ?1", [task_id], "trashed", false)?;
+        let uuids = Self::task_uuids(&tx, "id = ?1", [task_id])?;
+        Self::stamp(&tx, &uuids, &[TRASHED])?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn restore_project(&self, project_id: usize) -> Result<()> {
+        let tx = self.write_transaction()?;
+        let trashed =
+            "project_id = ?1 AND trashed_at = (SELECT trashed_at FROM projects WHERE id = ?1)";
+        Self::record_state(&tx, trashed, [project_id], "trashed", false)?;
+        let uuids = Self::task_uuids(&tx, trashed, [project_id])?;
+        Self::stamp(&tx, &uuids, &[TRASHED])?;
+        tx.execute(
+            "UPDATE tasks SET trashed_at = NULL
+             WHERE project_id = ?1
+               AND trashed_at = (SELECT trashed_at FROM projects WHERE id = ?1)",
+            [project_id],
+        )?;
+        let updated = tx.execute(
+            "UPDATE projects SET trashed_at = NULL WHERE id = ?1 AND trashed_at IS NOT NULL",
+            [project_id],
+        )?;
+        if updated == 0 {
+            return Err(TodoError::not_found("project is not in the trash"));
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn empty_trash(&self, before: DateTime<Utc>) -> Result<()> {
+        let tx = self.write_transaction()?;
+        let before = before.to_rfc3339();
+        Self::record_state(
+            &tx,
+            "trashed_at < ?1
+             OR project_id IN (SELECT id FROM projects WHERE trashed_at < ?1)",
+            [&before],
+            "deleted",
+            true,
+        )?;
+        Self::bury(
+            &tx,
+            "trashed_at < ?1
+             OR project_id IN (SELECT id FROM projects WHERE trashed_at < ?1)",
+            [&before],
+        )?;
+        tx.execute("DELETE FROM tasks WHERE trashed_at < ?1", [&before])?;
+        tx.execute(
+            "DELETE FROM tasks WHERE project_id IN (SELECT id FROM projects WHERE trashed_at < ?1)",
+            [&before],
+        )?;
+        tx.execute("DELETE FROM projects WHERE trashed_at < ?1", [&before])?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn insert_project(&self, project: &Project) -> Result<()> {
+        let tx = self.write_transaction()?;
+        tx.execute(
+            "INSERT INTO projects (id, name, parent_id, uuid) VALUES (?1, ?2, ?3, ?4)",
+            rusqlite::params![
+                project.id,
+                project.name,
+                project.parent_id,
+                project_uuid(project)
+            ],
+        )?;
+        for task in &project.tasks {
+            Self::insert_task_row(&tx, task)?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn update_project(&self, project: &Project) -> Result<()> {
+        let updated = self.conn.execute(
+            "UPDATE projects SET name = ?2, parent_id = ?3, uuid = ?4
+             WHERE id = ?1 AND trashed_at IS NULL",
+            rusqlite::params![
+                project.id,
+                project.name,
+                project.parent_id,
+                project_uuid(project)
+            ],
+        )?;
+        if updated == 0 {
+            return Err(TodoError::not_found("project not found"));
+        }
+        Ok(())
+    }
+
+    fn delete_project(&self, project_id: usize) -> Result<()> {
+        let tx = self.write_transaction()?;
+        Self::bury(&tx, "project_id = ?1", [project_id])?;
+        tx.execute("DELETE FROM tasks WHERE project_id = ?1", [project_id])?;
+        tx.execute("DELETE FROM projects WHERE id = ?1", [project_id])?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn insert_task(&self, task: &Task) -> Result<()> {
+        let tx = self.write_transaction()?;
+        Self::insert_task_row(&tx, task)?;
+        Self::insert_changes(&tx, &[Change::created(task, Utc::now())])?;
+        let uuids = Self::task_uuids(&tx, "id = ?1", [task.id()])?;
+        Self::stamp(&tx, &uuids, FIELDS)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn allocate_task_id(&self) -> Result<usize> {
+        let tx = self.write_transaction()?;
+        let id = self.load_next_task_id()?;
+        tx.execute(
+            "UPDATE app_state SET next_task_id = ?1 WHERE id = 1",
+            [id + 1],
+        )?;
+        tx.commit()?;
+        Ok(id)
+    }
+
+    /// Never below the counter, so that the ids of projects since deleted aren't
+    /// given out again, nor below an id in use.
+    fn allocate_project_id(&self) -> Result<usize> {
+        let tx = self.write_transaction()?;
+        let id = tx.query_row(
+            "SELECT MAX(next_project_id, (SELECT COALESCE(MAX(id), 0) + 1 FROM projects))
+             FROM app_state WHERE id = 1",
+            [],
+            |row| row.get::<_, usize>(0),
+        )?;
+        tx.execute(
+            "UPDATE app_state SET next_project_id = ?1 WHERE id = 1",
+            [id + 1],
+        )?;
+        tx.commit()?;
+        Ok(id)
+    }
+
+    fn update_task(&self, task: &mut Task) -> Result<()> {
+        let tx = self.write_transaction()?;
+        let old = self
+            .load_tasks("id = ?1", [task.id()])?
+            .pop()
+            .ok_or_else(|| TodoError::not_found("task not found"))?;
+        if old.version() != task.version() {
+            return Err(stale_task(task.id()));
+        }
+        let version = task.version() + 1;
+        Self::update_task_row(&tx, task, version)?;
+        Self::insert_changes(&tx, &diff(&old, task, Utc::now()))?;
+        Self::stamp(
+            &tx,
+            &[old.uuid().to_string()],
+            &replica::changed_fields(&old, task)?,
+        )?;
+        tx.commit()?;
+        task.set_version(version);
+        Ok(())
+    }
+
+    fn stats(&self, query: &StatsQuery) -> Result<Stats> {
+        let created = self.count_by_bucket("created_at", query)?;
+        let completed = self.count_by_bucket("completed_at", query)?;
+
+        let (completed_total, average_lead_time_hours) = self.conn.query_row(
+            &format!(
+                "SELECT COUNT(*), AVG((julianday(completed_at) - julianday(created_at)) * 24)
+                 FROM tasks WHERE completed_at IS NOT NULL AND {LIVE_TASKS}"
+            ),
+            [],
+            |row| Ok((row.get::<_, usize>(0)?, row.get::<_, Option<f64>>(1)?)),
+        )?;
+
+        let overdue = self.conn.query_row(
+            &format!(
+                "SELECT COUNT(*) FROM tasks
+                 WHERE completed_at IS NULL AND due_time < ?1 AND {LIVE_TASKS}"
+            ),
+            [query.today.to_string()],
+            |row| row.get::<_, usize>(0),
+        )?;
+
+        let mut stmt = self.conn.prepare(
+            "SELECT p.name, COUNT(t.id) FROM projects p
+             LEFT JOIN tasks t
+               ON t.project_id = p.id AND t.completed_at IS NULL AND t.trashed_at IS NULL
+             WHERE p.trashed_at IS NULL
+             GROUP BY p.id ORDER BY p.id",
+        )?;
+        let mut open_by_project = Vec::new();
+        for row in stmt.query_map([], |row| {
+            Ok(ProjectCount {
+                project: row.get(0)?,
+                open: row.get(1)?,
+            })
+        })? {
+            open_by_project.push(row?);
+        }
+
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT DISTINCT date(completed_at, 'localtime') AS day FROM tasks
+             WHERE completed_at IS NOT NULL AND {LIVE_TASKS} ORDER BY day"
+        ))?;
+        let mut days = Vec::new();
+        for row in stmt.query_map([], |row| row.get::<_, String>(0))? {
+            if let Ok(day) = NaiveDate::parse_from_str(&row?, "%Y-%m-%d") {
+                days.push(day);
+            }
+        }
+        let (current_streak, longest_streak) = streaks(&days, query.today);
+
+        Ok(Stats {
+            buckets: query.buckets(&created, &completed),
+            completed_total,
+            average_lead_time_hours,
+            open_by_project,
+            overdue,
+            current_streak,
+            longest_streak,
+        })
+    }
+
+    fn transaction(&self, apply: &mut dyn FnMut() -> Result<()>) -> Result<()> {
+        let tx = self.write_transaction()?;
+        apply()?;
+        tx.commit()
+    }
+
+    fn merge_from(&self, path: &Path) -> Result<MergeReport> {
+        self.merge_replica(path)
+    }
+
+    fn delete_task(&self, task_id: usize) -> Result<()> {
+        let tx = self.write_transaction()?;
+        Self::bury(&tx, "id = ?1", [task_id])?;
+        tx.execute("DELETE FROM tasks WHERE id = ?1", [task_id])?;
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+/// One replica's tasks, keyed by uuid, with what `merge_replica` compares them by.
+#[derive(Debug, Default)]
+struct ReplicaState {
+    replica_id: String,
+    clock: u64,
+    tasks: BTreeMap<String, Task>,
+    /// When each task went into the trash, if it's there.
+    trashed: BTreeMap<String, Option<String>>,
+    archived: BTreeSet<String>,
+    stamps: BTreeMap<(String, String), Stamp>,
+    tombstones: BTreeSet<String>,
+    projects: BTreeMap<usize, ProjectRow>,
+}
+
+#[derive(Debug)]
+struct ProjectRow {
+    name: String,
+    parent_id: usize,
+    trashed: bool,
+    uuid: String,
+}
+
+impl ReplicaState {
+    fn stamp(&self, uuid: &str, field: &str) -> Option<&Stamp> {
+        self.stamps.get(&(uuid.to_string(), field.to_string()))
+    }
+
+    /// Whether this side's value of a field beats `ours`. The later stamp wins;
+    /// equal stamps, e.g. from a copied database, fall back to comparing values so
+    /// that both directions agree.
+    fn wins(
+        &self,
+        ours: &ReplicaState,
+        uuid: &str,
+        field: &str,
+        our_value: Option<&serde_json::Value>,
+        their_value: Option<&serde_json::Value>,
+    ) -> bool {
+        if our_value == their_value {
+            return false;
+        }
+        let default = Stamp::default();
+        let theirs = self.stamp(uuid, field).unwrap_or(&default);
+        let mine = ours.stamp(uuid, field).unwrap_or(&default);
+        match theirs.cmp(mine) {
+            std::cmp::Ordering::Equal => {
+                their_value.map(|v| v.to_string()) > our_value.map(|v| v.to_string())
+            }
+            order => order.is_gt(),
+        }
+    }
+}
+
+/// The project's uuid, or a new one for projects made before uuids existed.
+fn project_uuid(project: &Project) -> String {
+    match project.uuid.as_str() {
+        "" => new_uuid(),
+        uuid => uuid.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("todo-{}-{name}.db", process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn remove(path: &Path) {
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = fs::remove_file(format!("{}{suffix}", path.display()));
+        }
+    }
+
+    /// A database as written before task uuids existed.
+    fn old_database(path: &Path) {
+        let conn = Connection::open(path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE projects (id INTEGER PRIMARY KEY, name TEXT NOT NULL,
+                 parent_id INTEGER NOT NULL);
+             CREATE TABLE tasks (id INTEGER PRIMARY KEY, project_id INTEGER NOT NULL,
+                 name TEXT NOT NULL, description TEXT NOT NULL, priority INTEGER NOT NULL,
+                 created_at TEXT NOT NULL, due_time TEXT, completed_at TEXT);
+             CREATE TABLE app_state (id INTEGER PRIMARY KEY CHECK (id = 1),
+                 next_task_id INTEGER NOT NULL);
+             INSERT INTO projects VALUES (0, 'Home', 0);
+             INSERT INTO tasks VALUES (0, 0, 'Buy milk', '', 0, '2026-01-01T00:00:00+00:00',
+                 NULL, NULL);
+             INSERT INTO tasks VALUES (1, 0, 'Call mum', '', 0, '2026-01-02T00:00:00+00:00',
+                 NULL, NULL);
+             INSERT INTO app_state VALUES (1, 2);",
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn merging_a_copy_of_an_old_database_leaves_both_intact() {
+        let (ours, theirs) = (temp_path("old-ours"), temp_path("old-theirs"));
+        old_database(&ours);
+        fs::copy(&ours, &theirs).unwrap();
+        let before = fs::read(&theirs).unwrap();
+
+        let db = Database::open(&ours).unwrap();
+        assert!(db.merge_from(&theirs).is_err());
+        assert_eq!(fs::read(&theirs).unwrap(), before);
+        let tasks = &db.load_projects().unwrap()[0].tasks;
+        assert_eq!(tasks.len(), 2);
+
+        drop(db);
+        remove(&ours);
+        remove(&theirs);
+    }
+
+    #[test]
+    fn merging_an_upgraded_copy_adds_no_duplicates() {
+        let (ours, theirs) = (temp_path("new-ours"), temp_path("new-theirs"));
+        old_database(&ours);
+        // Closing the last connection folds the WAL back into the file.
+        drop(Database::open(&ours).unwrap());
+        fs::copy(&ours, &theirs).unwrap();
+        let before = fs::read(&theirs).unwrap();
+
+        let db = Database::open(&ours).unwrap();
+        let report = db.merge_from(&theirs).unwrap();
+        assert_eq!(report.added, 0);
+        assert_eq!(fs::read(&theirs).unwrap(), before);
+        let tasks = &db.load_projects().unwrap()[0].tasks;
+        assert_eq!(tasks.len(), 2);
+
+        drop(db);
+        remove(&ours);
+        remove(&theirs);
+    }
+
+    #[test]
+    fn history_belongs_to_the_task_uuid_not_its_id() {
+        let path = temp_path("history");
+        old_database(&path);
+        Connection::open(&path)
+            .unwrap()
+            .execute_batch(
+                "CREATE TABLE history (id INTEGER PRIMARY KEY, task_id INTEGER NOT NULL,
+                     at TEXT NOT NULL, field TEXT NOT NULL, old_value TEXT, new_value TEXT);
+                 INSERT INTO history VALUES (1, 1, '2026-01-02T00:00:00+00:00', 'created',
+                     NULL, 'Call mum');",
+            )
+            .unwrap();
+
+        let db = Database::open(&path).unwrap();
+        let tasks = db.load_projects().unwrap().remove(0).tasks;
+        let find = |name: &str| tasks.iter().find(|t| t.name() == name).unwrap();
+        let (milk, mum) = (find("Buy milk"), find("Call mum"));
+        // The old row went to the task that had its id.
+        assert_eq!(db.load_history(Some(mum.uuid()), None).unwrap().len(), 1);
+
+        // A change made under the same short id by another task stays its own.
+        let at = Utc::now();
+        db.append_history(&[Change::of(1, milk.uuid(), at, "name", None, None)])
+            .unwrap();
+        let history = db.load_history(Some(mum.uuid()), None).unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].field, "created");
+        assert_eq!(db.load_history(Some(milk.uuid()), None).unwrap().len(), 1);
+
+        drop(db);
+        remove(&path);
+    }
+
+    #[test]
+    fn a_failed_transaction_is_rolled_back() {
+        let db = Database::open_in_memory().unwrap();
+        let project = |id: usize| Project {
+            id,
+            uuid: new_uuid(),
+            name: format!("Project {id}"),
+            parent_id: 0,
+            tasks: Vec::new(),
+        };
+        db.insert_project(&project(0)).unwrap();
+
+        let failed = db.transaction(&mut || {
+            db.insert_project(&project(1))?;
+            db.trash_project(0, Utc::now())?;
+            assert!(db.load_projects()?.iter().all(|p| p.id == 1));
+            Err(TodoError::conflict("changed meanwhile"))
+        });
+        assert!(failed.is_err());
+        let projects = db.load_projects().unwrap();
+        assert_eq!(projects.len(), 1);
+        assert_eq!(projects[0].id, 0);
+    }
+
+    #[test]
+    fn archived_tasks_leave_the_projects_until_purged() {
+        let db = Database::open_in_memory().unwrap();
+        db.insert_project(&Project {
+            id: 0,
+            uuid: new_uuid(),
+            name: String::from("Home"),
+            parent_id: 0,
+            tasks: Vec::new(),
+        })
+        .unwrap();
+        let mut task = Task::new(
+            0,
+            0,
+            String::from("Task"),
+            String::new(),
+            Priority::None,
+            Utc::now(),
+            None,
+            Some(Utc::now()),
+        );
+        task.set_uuid(new_uuid());
+        db.insert_task(&task).unwrap();
+
+        db.archive_tasks(&[0]).unwrap();
+        assert!(db.load_projects().unwrap()[0].tasks.is_empty());
+        assert_eq!(db.load_archive().unwrap()[0].uuid(), task.uuid());
+
+        db.purge_archived(&[0]).unwrap();
+        assert!(db.load_archive().unwrap().is_empty());
+        assert!(db.load_projects().unwrap()[0].tasks.is_empty());
+        let history = db.load_history(Some(task.uuid()), None).unwrap();
+        let fields: Vec<&str> = history.iter().map(|c| c.field.as_str()).collect();
+        assert!(fields.ends_with(&["archived", "deleted"]), "{fields:?}");
+    }
+
+    #[test]
+    fn annotations_are_stored_in_order() {
+        let db = Database::open_in_memory().unwrap();
+        db.insert_project(&Project {
+            id: 0,
+            uuid: new_uuid(),
+            name: String::from("Home"),
+            parent_id: 0,
+            tasks: Vec::new(),
+        })
+        .unwrap();
+        let mut task = Task::new(
+            0,
+            0,
+            String::from("Task"),
+            String::new(),
+            Priority::None,
+            Utc::now(),
+            None,
+            None,
+        );
+        task.set_uuid(new_uuid());
+        db.insert_task(&task).unwrap();
+
+        for text in ["first", "second"] {
+            task.annotate(Annotation {
+                created_at: Utc::now(),
+                text: text.to_string(),
+            });
+            db.update_task(&mut task).unwrap();
+        }
+        let stored = db.load_projects().unwrap().remove(0).tasks.remove(0);
+        let texts: Vec<&str> = stored
+            .annotations()
+            .iter()
+            .map(|a| a.text.as_str())
+            .collect();
+        assert_eq!(texts, ["first", "second"]);
+        let history = db.load_history(Some(task.uuid()), None).unwrap();
+        assert_eq!(history.last().unwrap().field, "annotation");
+    }
+
+    #[test]
+    fn saved_trash_loads_back() {
+        let db = Database::open_in_memory().unwrap();
+        let mut project = Project {
+            id: 1,
+            uuid: new_uuid(),
+            name: String::from("Old"),
+            parent_id: 0,
+            tasks: Vec::new(),
+        };
+        let mut task = Task::new(
+            0,
+            1,
+            String::from("Task"),
+            String::new(),
+            Priority::None,
+            Utc::now(),
+            None,
+            None,
+        );
+        task.set_uuid(new_uuid());
+        project.tasks.push(task);
+        let trash = Trash {
+            projects: vec![TrashedProject {
+                trashed_at: Utc::now(),
+                project: project.clone(),
+            }],
+            tasks: Vec::new(),
+        };
+
+        db.save_trash(&trash).unwrap();
+        let loaded = db.load_trash().unwrap();
+        assert_eq!(loaded.projects.len(), 1);
+        let restored = &loaded.projects[0].project;
+        assert_eq!(restored.uuid, project.uuid);
+        assert_eq!(restored.tasks.len(), 1);
+        assert_eq!(restored.tasks[0].uuid(), project.tasks[0].uuid());
+    }
+
+    #[test]
+    fn project_ids_are_given_out_once() {
+        let db = Database::open_in_memory().unwrap();
+        let (first, second) = (
+            db.allocate_project_id().unwrap(),
+            db.allocate_project_id().unwrap(),
+        );
+        assert_ne!(first, second);
+        db.insert_project(&Project {
+            id: second,
+            uuid: new_uuid(),
+            name: String::from("Work"),
+            parent_id: 0,
+            tasks: Vec::new(),
+        })
+        .unwrap();
+        db.delete_project(second).unwrap();
+        assert!(db.allocate_project_id().unwrap() > second);
+    }
+
+    #[test]
+    fn malformed_time_entries_are_errors() {
+        for (started, ended) in [
+            ("yesterday", None),
+            ("2026-01-01T09:00:00+00:00", Some("?")),
+        ] {
+            let path = temp_path("time-entries");
+            old_database(&path);
+            let db = Database::open(&path).unwrap();
+            db.conn
+                .execute(
+                    "INSERT INTO time_entries (task_id, started_at, ended_at) VALUES (0, ?1, ?2)",
+                    rusqlite::params![started, ended],
+                )
+                .unwrap();
+            assert!(matches!(db.load_projects(), Err(TodoError::Format(_))));
+
+            drop(db);
+            remove(&path);
         }
     }
 }
@@ -0,0 +1,85 @@
+use std::{error, fmt, io};
+
+pub type Result<T, E = TodoError> = std::result::Result<T, E>;
+
+#[derive(Debug)]
+pub enum TodoError {
+    NotFound(String),
+    InvalidInput(String),
+    Conflict(String),
+    Cancelled(String),
+    Format(String),
+    Storage(rusqlite::Error),
+    Io(io::Error),
+}
+
+impl TodoError {
+    pub fn not_found(msg: impl Into<String>) -> Self {
+        TodoError::NotFound(msg.into())
+    }
+
+    pub fn invalid(msg: impl Into<String>) -> Self {
+        TodoError::InvalidInput(msg.into())
+    }
+
+    pub fn conflict(msg: impl Into<String>) -> Self {
+        TodoError::Conflict(msg.into())
+    }
+
+    pub fn cancelled(msg: impl Into<String>) -> Self {
+        TodoError::Cancelled(msg.into())
+    }
+
+    pub fn format(msg: impl Into<String>) -> Self {
+        TodoError::Format(msg.into())
+    }
+
+    /// Process exit code used by the binary for this kind of failure.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            TodoError::InvalidInput(_) => 2,
+            TodoError::NotFound(_) => 3,
+            TodoError::Conflict(_) => 4,
+            TodoError::Cancelled(_) => 5,
+            TodoError::Storage(_) => 6,
+            TodoError::Io(_) => 7,
+            TodoError::Format(_) => 8,
+        }
+    }
+}
+
+impl fmt::Display for TodoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TodoError::NotFound(msg)
+            | TodoError::InvalidInput(msg)
+            | TodoError::Conflict(msg)
+            | TodoError::Cancelled(msg) => write!(f, "{msg}"),
+            TodoError::Storage(e) => write!(f, "database error: {e}"),
+            TodoError::Io(e) => write!(f, "io error: {e}"),
+            TodoError::Format(msg) => write!(f, "malformed data: {msg}"),
+        }
+    }
+}
+
+impl error::Error for TodoError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            TodoError::Storage(e) => Some(e),
+            TodoError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<rusqlite::Error> for TodoError {
+    fn from(e: rusqlite::Error) -> Self {
+        TodoError::Storage(e)
+    }
+}
+
+impl From<io::Error> for TodoError {
+    fn from(e: io::Error) -> Self {
+        TodoError::Io(e)
+    }
+}
@@ -0,0 +1,560 @@
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    fs::{self, File},
+    io, mem,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{Result, TodoError},
+    hierarchy::{Project, Task},
+    history::Change,
+    replica::new_uuid,
+    storage::{
+        Storage, Trash, expire_trash, move_project_to_trash, move_task_to_trash, next_project_id,
+        purge_from_archive, restore_project_from_trash, restore_task_from_trash, store_project,
+        store_task,
+    },
+};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Document {
+    next_task_id: usize,
+    /// Ids of projects since deleted aren't given out again.
+    #[serde(default)]
+    next_project_id: usize,
+    projects: Vec<Project>,
+    #[serde(default)]
+    archive: Vec<Task>,
+    #[serde(default)]
+    trash: Trash,
+    #[serde(default)]
+    history: Vec<Change>,
+    #[serde(default)]
+    working_set: Vec<usize>,
+}
+
+/// Keeps the whole todo list in a single pretty-printed JSON file, which makes it
+/// easy to track in a dotfiles repository.
+///
+/// Every change is a single read-modify-write of the whole file done under an
+/// exclusive lock on a `.lock` file next to it, so concurrent processes take
+/// turns and a change moving data between parts of the file is never half done.
+#[derive(Debug)]
+pub struct FileStorage {
+    path: PathBuf,
+    /// The document being changed inside `transaction`, written once at its end.
+    pending: RefCell<Option<Document>>,
+}
+
+impl FileStorage {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let storage = FileStorage {
+            path: path.as_ref().to_path_buf(),
+            pending: RefCell::new(None),
+        };
+        // Fail early on a file we can't parse instead of on the first command.
+        if assign_uuids(&mut storage.read()?) {
+            storage.modify(|doc| {
+                assign_uuids(doc);
+                Ok(())
+            })?;
+        }
+        Ok(storage)
+    }
+
+    fn read(&self) -> Result<Document> {
+        if let Some(doc) = &*self.pending.borrow() {
+            return Ok(doc.clone());
+        }
+        match fs::read_to_string(&self.path) {
+            Ok(text) if text.trim().is_empty() => Ok(Document::default()),
+            Ok(text) => serde_json::from_str(&text)
+                .map_err(|e| TodoError::format(format!("{}: {e}", self.path.display()))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Document::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn write(&self, doc: &Document) -> Result<()> {
+        let mut text =
+            serde_json::to_string_pretty(doc).map_err(|e| TodoError::format(e.to_string()))?;
+        text.push('\n');
+
+        // Write next to the target and rename so a crash never leaves a truncated file.
+        let tmp = self.path.with_extension("json.tmp");
+        fs::write(&tmp, text)?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+
+    /// Applies `change` to the current document and writes it back, holding the
+    /// lock throughout. Readers don't need the lock since the rename is atomic.
+    fn modify<T>(&self, change: impl FnOnce(&mut Document) -> Result<T>) -> Result<T> {
+        if let Some(doc) = self.pending.borrow_mut().as_mut() {
+            return change(doc);
+        }
+        let lock = File::create(self.path.with_extension("json.lock"))?;
+        lock.lock()?;
+        let mut doc = self.read()?;
+        let result = change(&mut doc)?;
+        self.write(&doc)?;
+        Ok(result)
+    }
+}
+
+impl Storage for FileStorage {
+    fn load_projects(&self) -> Result<Vec<Project>> {
+        Ok(self.read()?.projects)
+    }
+
+    fn save_projects(&self, projects: &[Project]) -> Result<()> {
+        self.modify(|doc| {
+            doc.projects = projects.to_vec();
+            Ok(())
+        })
+    }
+
+    fn load_next_task_id(&self) -> Result<usize> {
+        Ok(self.read()?.next_task_id)
+    }
+
+    fn save_next_task_id(&self, next_task_id: usize) -> Result<()> {
+        self.modify(|doc| {
+            doc.next_task_id = next_task_id;
+            Ok(())
+        })
+    }
+
+    fn load_archive(&self) -> Result<Vec<Task>> {
+        Ok(self.read()?.archive)
+    }
+
+    fn save_archive(&self, tasks: &[Task]) -> Result<()> {
+        self.modify(|doc| {
+            doc.archive = tasks.to_vec();
+            Ok(())
+        })
+    }
+
+    fn load_trash(&self) -> Result<Trash> {
+        Ok(self.read()?.trash)
+    }
+
+    fn save_trash(&self, trash: &Trash) -> Result<()> {
+        self.modify(|doc| {
+            doc.trash = trash.clone();
+            Ok(())
+        })
+    }
+
+    fn load_history(
+        &self,
+        task: Option<&str>,
+        since: Option<DateTime<Utc>>,
+    ) -> Result<Vec<Change>> {
+        let mut history = self.read()?.history;
+        history.retain(|c| task.is_none_or(|uuid| c.task_uuid == uuid));
+        history.retain(|c| since.is_none_or(|since| c.at >= since));
+        Ok(history)
+    }
+
+    fn append_history(&self, changes: &[Change]) -> Result<()> {
+        if changes.is_empty() {
+            return Ok(());
+        }
+        self.modify(|doc| {
+            doc.history.extend_from_slice(changes);
+            Ok(())
+        })
+    }
+
+    /// Holds the lock throughout and writes the file once, if `apply` succeeds.
+    fn transaction(&self, apply: &mut dyn FnMut() -> Result<()>) -> Result<()> {
+        if self.pending.borrow().is_some() {
+            return apply();
+        }
+        self.modify(|doc| {
+            self.pending.replace(Some(mem::take(doc)));
+            let result = apply();
+            *doc = self.pending.take().expect("set above");
+            result
+        })
+    }
+
+    fn load_working_set(&self) -> Result<Vec<usize>> {
+        Ok(self.read()?.working_set)
+    }
+
+    fn save_working_set(&self, task_ids: &[usize]) -> Result<()> {
+        self.modify(|doc| {
+            doc.working_set = task_ids.to_vec();
+            Ok(())
+        })
+    }
+
+    fn extend_working_set(&self, task_id: usize) -> Result<usize> {
+        self.modify(|doc| {
+            doc.working_set.push(task_id);
+            Ok(doc.working_set.len())
+        })
+    }
+
+    /// Moves the tasks in a single write, so they are never in both places or neither.
+    fn archive_tasks(&self, task_ids: &[usize]) -> Result<()> {
+        self.modify(|doc| {
+            let now = Utc::now();
+            for project in doc.projects.iter_mut() {
+                let (archived, kept): (Vec<Task>, _) = project
+                    .tasks
+                    .drain(..)
+                    .partition(|t| task_ids.contains(&t.id()));
+                project.tasks = kept;
+                doc.history.extend(
+                    archived
+                        .iter()
+                        .map(|t| Change::state(t, now, "archived", true)),
+                );
+                doc.archive.extend(archived);
+            }
+            Ok(())
+        })
+    }
+
+    fn purge_archived(&self, task_ids: &[usize]) -> Result<()> {
+        self.modify(|doc| {
+            let changes = purge_from_archive(&mut doc.archive, task_ids, Utc::now());
+            doc.history.extend(changes);
+            Ok(())
+        })
+    }
+
+    fn insert_project(&self, project: &Project) -> Result<()> {
+        self.modify(|doc| {
+            doc.projects.push(project.clone());
+            Ok(())
+        })
+    }
+
+    fn update_project(&self, project: &Project) -> Result<()> {
+        self.modify(|doc| store_project(&mut doc.projects, project))
+    }
+
+    fn delete_project(&self, project_id: usize) -> Result<()> {
+        self.modify(|doc| {
+            doc.projects.retain(|p| p.id != project_id);
+            doc.archive.retain(|t| t.project_id() != project_id);
+            Ok(())
+        })
+    }
+
+    fn allocate_task_id(&self) -> Result<usize> {
+        self.modify(|doc| {
+            doc.next_task_id += 1;
+            Ok(doc.next_task_id - 1)
+        })
+    }
+
+    fn allocate_project_id(&self) -> Result<usize> {
+        self.modify(|doc| {
+            let id = next_project_id(&doc.projects, &doc.trash).max(doc.next_project_id);
+            doc.next_project_id = id + 1;
+            Ok(id)
+        })
+    }
+
+    fn insert_task(&self, task: &Task) -> Result<()> {
+        self.modify(|doc| {
+            let project = doc
+                .projects
+                .iter_mut()
+                .find(|p| p.id == task.project_id())
+                .ok_or_else(|| TodoError::not_found("project not found"))?;
+            project.tasks.push(task.clone());
+            doc.history.push(Change::created(task, Utc::now()));
+            Ok(())
+        })
+    }
+
+    /// Checks the version and stores the task under one lock, so no other
+    /// process can slip a change in between.
+    fn update_task(&self, task: &mut Task) -> Result<()> {
+        let version = task.version() + 1;
+        self.modify(|doc| {
+            let changes = store_task(&mut doc.projects, task, version)?;
+            doc.history.extend(changes);
+            Ok(())
+        })?;
+        task.set_version(version);
+        Ok(())
+    }
+
+    fn delete_task(&self, task_id: usize) -> Result<()> {
+        self.modify(|doc| {
+            for project in doc.projects.iter_mut() {
+                project.tasks.retain(|t| t.id() != task_id);
+            }
+            Ok(())
+        })
+    }
+
+    fn trash_task(&self, task_id: usize, at: DateTime<Utc>) -> Result<()> {
+        self.modify(|doc| {
+            let changes = move_task_to_trash(&mut doc.projects, &mut doc.trash, task_id, at)?;
+            doc.history.extend(changes);
+            Ok(())
+        })
+    }
+
+    fn trash_project(&self, project_id: usize, at: DateTime<Utc>) -> Result<()> {
+        self.modify(|doc| {
+            let changes = move_project_to_trash(&mut doc.projects, &mut doc.trash, project_id, at)?;
+            doc.history.extend(changes);
+            Ok(())
+        })
+    }
+
+    fn restore_task(&self, task_id: usize) -> Result<()> {
+        self.modify(|doc| {
+            let changes =
+                restore_task_from_trash(&mut doc.projects, &mut doc.trash, task_id, Utc::now())?;
+            doc.history.extend(changes);
+            Ok(())
+        })
+    }
+
+    fn restore_project(&self, project_id: usize) -> Result<()> {
+        self.modify(|doc| {
+            let changes = restore_project_from_trash(
+                &mut doc.projects,
+                &mut doc.trash,
+                project_id,
+                Utc::now(),
+            )?;
+            doc.history.extend(changes);
+            Ok(())
+        })
+    }
+
+    fn empty_trash(&self, before: DateTime<Utc>) -> Result<()> {
+        self.modify(|doc| {
+            let changes = expire_trash(&mut doc.trash, &mut doc.archive, before, Utc::now());
+            doc.history.extend(changes);
+            Ok(())
+        })
+    }
+}
+
+/// Gives tasks and projects from before uuids existed one, and changes from
+/// before history was keyed by uuid that of their task. Returns whether any
+/// needed it.
+fn assign_uuids(doc: &mut Document) -> bool {
+    let mut assigned = false;
+    let projects = doc
+        .projects
+        .iter_mut()
+        .chain(doc.trash.projects.iter_mut().map(|p| &mut p.project));
+    let mut tasks = Vec::new();
+    for project in projects {
+        if project.uuid.is_empty() {
+            project.uuid = new_uuid();
+            assigned = true;
+        }
+        tasks.extend(project.tasks.iter_mut());
+    }
+    tasks.extend(doc.archive.iter_mut());
+    tasks.extend(doc.trash.tasks.iter_mut().map(|t| &mut t.task));
+    let mut uuids = BTreeMap::new();
+    for task in tasks {
+        if task.uuid().is_empty() {
+            task.set_uuid(new_uuid());
+            assigned = true;
+        }
+        uuids.insert(task.id(), task.uuid().to_string());
+    }
+    // History from before changes carried the task's uuid goes to the task that
+    // has its id now; that of tasks since deleted stays unattributed.
+    for change in doc.history.iter_mut().filter(|c| c.task_uuid.is_empty()) {
+        if let Some(uuid) = uuids.get(&change.task_id) {
+            change.task_uuid = uuid.clone();
+            assigned = true;
+        }
+    }
+    assigned
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process, thread};
+
+    use super::*;
+    use crate::hierarchy::Priority;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("todo-{}-{name}.json", process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn remove(path: &Path) {
+        fs::remove_file(path).unwrap();
+        fs::remove_file(path.with_extension("json.lock")).unwrap();
+    }
+
+    fn project(id: usize) -> Project {
+        Project {
+            name: format!("Project {id}"),
+            id,
+            uuid: new_uuid(),
+            parent_id: 0,
+            tasks: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn concurrent_writers_keep_each_others_changes() {
+        let path = temp_path("writers");
+        let storage = FileStorage::open(&path).unwrap();
+        storage.insert_project(&project(0)).unwrap();
+
+        let tasks = thread::spawn({
+            let path = path.clone();
+            move || {
+                let storage = FileStorage::open(&path).unwrap();
+                for _ in 0..50 {
+                    let id = storage.allocate_task_id().unwrap();
+                    let task = Task::new(
+                        id,
+                        0,
+                        format!("Task {id}"),
+                        String::new(),
+                        Priority::None,
+                        Utc::now(),
+                        None,
+                        None,
+                    );
+                    storage.insert_task(&task).unwrap();
+                }
+            }
+        });
+        let projects = thread::spawn({
+            let path = path.clone();
+            move || {
+                let storage = FileStorage::open(&path).unwrap();
+                for id in 1..=50 {
+                    storage.insert_project(&project(id)).unwrap();
+                }
+            }
+        });
+        tasks.join().unwrap();
+        projects.join().unwrap();
+
+        let projects = storage.load_projects().unwrap();
+        assert_eq!(projects.len(), 51);
+        assert_eq!(projects[0].tasks.len(), 50);
+        assert_eq!(storage.load_next_task_id().unwrap(), 50);
+        remove(&path);
+    }
+
+    #[test]
+    fn trashing_and_restoring_move_the_task_in_one_write() {
+        let path = temp_path("trash");
+        let storage = FileStorage::open(&path).unwrap();
+        storage.insert_project(&project(0)).unwrap();
+        let task = Task::new(
+            0,
+            0,
+            "Task".to_string(),
+            String::new(),
+            Priority::None,
+            Utc::now(),
+            None,
+            None,
+        );
+        storage.insert_task(&task).unwrap();
+
+        storage.trash_task(0, Utc::now()).unwrap();
+        assert!(storage.load_projects().unwrap()[0].tasks.is_empty());
+        assert_eq!(storage.load_trash().unwrap().tasks.len(), 1);
+
+        storage.restore_task(0).unwrap();
+        assert_eq!(storage.load_projects().unwrap()[0].tasks.len(), 1);
+        assert!(storage.load_trash().unwrap().tasks.is_empty());
+        assert!(storage.restore_task(0).is_err());
+        remove(&path);
+    }
+
+    #[test]
+    fn archiving_and_purging_move_the_task_in_one_write() {
+        let path = temp_path("archive");
+        let storage = FileStorage::open(&path).unwrap();
+        storage.insert_project(&project(0)).unwrap();
+        for id in [0, 1] {
+            let task = Task::new(
+                id,
+                0,
+                format!("Task {id}"),
+                String::new(),
+                Priority::None,
+                Utc::now(),
+                None,
+                Some(Utc::now()),
+            );
+            storage.insert_task(&task).unwrap();
+        }
+
+        storage.archive_tasks(&[0]).unwrap();
+        let projects = storage.load_projects().unwrap();
+        assert_eq!(projects[0].tasks.len(), 1);
+        assert_eq!(projects[0].tasks[0].id(), 1);
+        let archive = storage.load_archive().unwrap();
+        assert_eq!(archive.len(), 1);
+        assert_eq!(archive[0].id(), 0);
+
+        storage.purge_archived(&[0, 1]).unwrap();
+        assert!(storage.load_archive().unwrap().is_empty());
+        assert_eq!(storage.load_projects().unwrap()[0].tasks.len(), 1);
+        remove(&path);
+    }
+
+    #[test]
+    fn project_ids_are_given_out_once() {
+        let path = temp_path("project-ids");
+        let storage = FileStorage::open(&path).unwrap();
+        let id = storage.allocate_project_id().unwrap();
+        storage.insert_project(&project(id)).unwrap();
+        storage.delete_project(id).unwrap();
+        let reopened = FileStorage::open(&path).unwrap();
+        assert!(reopened.allocate_project_id().unwrap() > id);
+        remove(&path);
+    }
+
+    #[test]
+    fn a_transaction_writes_all_or_nothing() {
+        let path = temp_path("transaction");
+        let storage = FileStorage::open(&path).unwrap();
+        storage.insert_project(&project(0)).unwrap();
+
+        let failed = storage.transaction(&mut || {
+            storage.insert_project(&project(1))?;
+            assert_eq!(storage.load_projects()?.len(), 2);
+            Err(TodoError::conflict("changed meanwhile"))
+        });
+        assert!(failed.is_err());
+        assert_eq!(storage.load_projects().unwrap().len(), 1);
+
+        storage
+            .transaction(&mut || {
+                storage.insert_project(&project(1))?;
+                storage.insert_project(&project(2))
+            })
+            .unwrap();
+        let reopened = FileStorage::open(&path).unwrap();
+        assert_eq!(reopened.load_projects().unwrap().len(), 3);
+        remove(&path);
+    }
+}
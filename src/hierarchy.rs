@@ -1,45 +1,129 @@
-use std::usize;
+use chrono::{DateTime, Duration, Local, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
 
-use chrono::{DateTime, NaiveDate, Utc};
+use crate::{
+    command::Command,
+    error::{Result, TodoError},
+    replica::new_uuid,
+    timeutil::{parse_date, parse_duration},
+};
 
-use crate::command::Command;
-
-pub fn task_from_command(
-    command: &Command,
-    id: usize,
-    project_id: usize,
-) -> Result<Task, &'static str> {
-    let name = command
-        .parameters()
-        .tasks()
-        .get(0)
-        .cloned()
-        .ok_or("missing task name");
+pub fn task_from_command(command: &Command, id: usize, project_id: usize) -> Result<Task> {
+    let words = command.parameters().tasks();
+    let name = if words.is_empty() {
+        Err(TodoError::invalid("missing task name"))
+    } else {
+        Ok(words.join(" "))
+    };
 
     let (_, description, priority) = command.parameters().fields();
+    let estimate = command
+        .parameters()
+        .estimate()
+        .map(parse_duration)
+        .transpose()?;
+    let due = command
+        .parameters()
+        .due()
+        .map(|d| parse_date(d, Local::now().date_naive()))
+        .transpose()?;
 
-    Ok(Task {
+    let mut task = Task {
         id,
+        uuid: new_uuid(),
         project_id,
         name: name?,
         description: description.clone().unwrap_or_default(),
-        priority: priority.clone().unwrap_or_default(),
+        priority: priority.unwrap_or_default(),
         created_at: Utc::now(),
-        due_time: None,
+        due_time: due,
         completed_at: None,
-    })
+        estimate_minutes: estimate.map(|d| d.num_minutes()),
+        annotations: Vec::new(),
+        time_entries: Vec::new(),
+        tags: Vec::new(),
+        depends_on: Vec::new(),
+        version: 0,
+    };
+    apply_tags_and_dependencies(&mut task, command)?;
+    Ok(task)
+}
+
+/// Applies the fields given on a `modify` command line, leaving the others as they are.
+pub fn update_task_from_command(task: &mut Task, command: &Command) -> Result<()> {
+    let params = command.parameters();
+    let (_, description, priority) = params.fields();
+
+    if !params.tasks().is_empty() {
+        task.name = params.tasks().join(" ");
+    }
+    if let Some(description) = description {
+        task.description = description.clone();
+    }
+    if let Some(priority) = priority {
+        task.priority = *priority;
+    }
+    match params.estimate() {
+        Some("none") => task.estimate_minutes = None,
+        Some(estimate) => task.estimate_minutes = Some(parse_duration(estimate)?.num_minutes()),
+        None => {}
+    }
+    match params.due() {
+        Some("none") => task.due_time = None,
+        Some(due) => task.due_time = Some(parse_date(due, Local::now().date_naive())?),
+        None => {}
+    }
+    apply_tags_and_dependencies(task, command)
+}
+
+/// Applies `--tag` and `--depends` values; `-name` and `-id` remove instead of
+/// adding, and `--depends none` clears all dependencies. Ids may be written `#id`.
+fn apply_tags_and_dependencies(task: &mut Task, command: &Command) -> Result<()> {
+    for tag in command.parameters().tags() {
+        match tag.strip_prefix('-') {
+            Some(tag) => task.remove_tag(tag),
+            None => task.add_tag(tag),
+        }
+    }
+    for value in command.parameters().depends() {
+        if value == "none" {
+            task.depends_on.clear();
+            continue;
+        }
+        let (remove, id) = match value.strip_prefix('-') {
+            Some(id) => (true, id),
+            None => (false, value.as_str()),
+        };
+        let id: usize = id
+            .strip_prefix('#')
+            .unwrap_or(id)
+            .parse()
+            .map_err(|_| TodoError::invalid(format!("invalid task id '{value}'")))?;
+        if remove {
+            task.depends_on.retain(|&d| d != id);
+        } else if !task.depends_on.contains(&id) {
+            task.depends_on.push(id);
+        }
+    }
+    Ok(())
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Project {
     pub name: String,
     pub id: usize,
+    /// Global id, like `Task::uuid`.
+    #[serde(default)]
+    pub uuid: String,
     pub parent_id: usize,
     pub tasks: Vec<Task>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Task {
+    /// Global id, the same in every replica; `id` is only the local short form.
+    #[serde(default)]
+    uuid: String,
     name: String,
     priority: Priority,
     project_id: usize,
@@ -48,10 +132,49 @@ pub struct Task {
     created_at: DateTime<Utc>,
     due_time: Option<NaiveDate>,
     completed_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    estimate_minutes: Option<i64>,
+    #[serde(default)]
+    annotations: Vec<Annotation>,
+    #[serde(default)]
+    time_entries: Vec<TimeEntry>,
+    #[serde(default)]
+    tags: Vec<String>,
+    /// Ids of tasks that must be completed before this one.
+    #[serde(default)]
+    depends_on: Vec<usize>,
+    /// Bumped on every stored update, so a write based on a stale copy is caught.
+    #[serde(default)]
+    version: u64,
+}
+
+/// A timestamped note appended to a task with `todo note`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Annotation {
+    pub created_at: DateTime<Utc>,
+    pub text: String,
+}
+
+/// An interval of tracked work. `ended_at` is `None` while the timer runs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimeEntry {
+    pub started_at: DateTime<Utc>,
+    pub ended_at: Option<DateTime<Utc>>,
+}
+
+impl TimeEntry {
+    /// Length of the part of the entry that falls within `[from, to)`, counting a
+    /// running timer up to `now`.
+    pub fn overlap(&self, from: DateTime<Utc>, to: DateTime<Utc>, now: DateTime<Utc>) -> Duration {
+        let start = self.started_at.max(from);
+        let end = self.ended_at.unwrap_or(now).min(to);
+        (end - start).max(Duration::zero())
+    }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
 pub enum Priority {
+    #[default]
     None = 0,
     Low = 1,
     Medium = 2,
@@ -59,23 +182,35 @@ pub enum Priority {
 }
 
 impl Priority {
-    pub fn translate_priority(priority: &str) -> Self {
+    /// Parses a priority given by name (`high`, `h`), by its stored level
+    /// (`0`-`3`, higher is more important) or in the `P0`-`P3` style, where `P0`
+    /// is the most important.
+    pub fn parse(priority: &str) -> Result<Self> {
         match priority.to_lowercase().as_str() {
-            "low" | "l" => Priority::Low,
-            "medium" | "m" => Priority::Medium,
-            "high" | "h" => Priority::High,
-            _ => Priority::None,
+            "none" | "n" | "0" | "p3" => Ok(Priority::None),
+            "low" | "l" | "1" | "p2" => Ok(Priority::Low),
+            "medium" | "m" | "2" | "p1" => Ok(Priority::Medium),
+            "high" | "h" | "3" | "p0" => Ok(Priority::High),
+            _ => Err(TodoError::invalid(format!(
+                "invalid priority '{priority}', expected none, low, medium or high \
+                 (n, l, m, h), a level from 0 to 3, or P3 to P0"
+            ))),
         }
     }
-}
 
-impl Default for Priority {
-    fn default() -> Self {
-        Priority::None
+    /// The priority stored as `level`, as in the `Priority as i32` discriminant.
+    pub fn from_level(level: i64) -> Self {
+        match level {
+            1 => Priority::Low,
+            2 => Priority::Medium,
+            3 => Priority::High,
+            _ => Priority::None,
+        }
     }
 }
 
 impl Task {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         id: usize,
         project_id: usize,
@@ -88,6 +223,7 @@ impl Task {
     ) -> Self {
         Task {
             id,
+            uuid: String::new(),
             project_id,
             name,
             description,
@@ -95,15 +231,39 @@ impl Task {
             created_at,
             due_time,
             completed_at,
+            estimate_minutes: None,
+            annotations: Vec::new(),
+            time_entries: Vec::new(),
+            tags: Vec::new(),
+            depends_on: Vec::new(),
+            version: 0,
         }
     }
 
     pub fn id(&self) -> usize {
         self.id
     }
+    pub fn set_id(&mut self, id: usize) {
+        self.id = id;
+    }
+    pub fn uuid(&self) -> &str {
+        &self.uuid
+    }
+    pub fn set_uuid(&mut self, uuid: String) {
+        self.uuid = uuid;
+    }
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+    pub fn set_version(&mut self, version: u64) {
+        self.version = version;
+    }
     pub fn project_id(&self) -> usize {
         self.project_id
     }
+    pub fn set_project_id(&mut self, project_id: usize) {
+        self.project_id = project_id;
+    }
     pub fn name(&self) -> &String {
         &self.name
     }
@@ -122,8 +282,175 @@ impl Task {
     pub fn completed_at(&self) -> Option<DateTime<Utc>> {
         self.completed_at
     }
+    pub fn estimate(&self) -> Option<Duration> {
+        self.estimate_minutes.map(Duration::minutes)
+    }
+
+    pub fn set_estimate(&mut self, estimate: Option<Duration>) {
+        self.estimate_minutes = estimate.map(|d| d.num_minutes());
+    }
+
+    /// Annotations in the order they were added, which is also chronological.
+    pub fn annotations(&self) -> &[Annotation] {
+        &self.annotations
+    }
+
+    pub fn annotate(&mut self, annotation: Annotation) {
+        self.annotations.push(annotation);
+    }
+
+    pub fn set_annotations(&mut self, annotations: Vec<Annotation>) {
+        self.annotations = annotations;
+    }
+
+    /// Replaces the text of the annotation at 1-based `index`.
+    pub fn edit_annotation(&mut self, index: usize, text: String) -> Result<()> {
+        let annotation = index
+            .checked_sub(1)
+            .and_then(|i| self.annotations.get_mut(i))
+            .ok_or_else(|| TodoError::not_found(format!("task has no annotation {index}")))?;
+        annotation.text = text;
+        Ok(())
+    }
+
+    /// Removes the annotation at 1-based `index`.
+    pub fn remove_annotation(&mut self, index: usize) -> Result<Annotation> {
+        if index == 0 || index > self.annotations.len() {
+            return Err(TodoError::not_found(format!(
+                "task has no annotation {index}"
+            )));
+        }
+        Ok(self.annotations.remove(index - 1))
+    }
+
+    pub fn time_entries(&self) -> &[TimeEntry] {
+        &self.time_entries
+    }
+
+    pub fn set_time_entries(&mut self, time_entries: Vec<TimeEntry>) {
+        self.time_entries = time_entries;
+    }
+
+    pub fn add_time_entry(&mut self, entry: TimeEntry) {
+        self.time_entries.push(entry);
+    }
+
+    /// The entry of the running timer, if it is on this task.
+    pub fn active_time_entry(&mut self) -> Option<&mut TimeEntry> {
+        self.time_entries.iter_mut().find(|e| e.ended_at.is_none())
+    }
+
+    pub fn is_tracking(&self) -> bool {
+        self.time_entries.iter().any(|e| e.ended_at.is_none())
+    }
+
+    /// Time tracked on this task within `[from, to)`.
+    pub fn tracked_between(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) -> Duration {
+        self.time_entries
+            .iter()
+            .map(|e| e.overlap(from, to, now))
+            .fold(Duration::zero(), |acc, d| acc + d)
+    }
+
+    /// All time tracked on this task, counting a running timer up to `now`.
+    pub fn tracked_total(&self, now: DateTime<Utc>) -> Duration {
+        self.tracked_between(DateTime::<Utc>::MIN_UTC, DateTime::<Utc>::MAX_UTC, now)
+    }
+
+    pub fn tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    pub fn set_tags(&mut self, tags: Vec<String>) {
+        self.tags = tags;
+    }
+
+    pub fn add_tag(&mut self, tag: &str) {
+        if !self.tags.iter().any(|t| t == tag) {
+            self.tags.push(tag.to_string());
+        }
+    }
+
+    pub fn remove_tag(&mut self, tag: &str) {
+        self.tags.retain(|t| t != tag);
+    }
+
+    pub fn depends_on(&self) -> &[usize] {
+        &self.depends_on
+    }
+
+    pub fn set_depends_on(&mut self, depends_on: Vec<usize>) {
+        self.depends_on = depends_on;
+    }
+
+    /// Case-insensitive match against the name, description, tags and annotations.
+    pub fn matches(&self, needle: &str) -> bool {
+        let needle = needle.to_lowercase();
+        self.name.to_lowercase().contains(&needle)
+            || self.description.to_lowercase().contains(&needle)
+            || self.tags.iter().any(|t| t.to_lowercase() == needle)
+            || self
+                .annotations
+                .iter()
+                .any(|a| a.text.to_lowercase().contains(&needle))
+    }
+
+    pub fn set_priority(&mut self, priority: Priority) {
+        self.priority = priority;
+    }
 
     pub fn mark_complete(&mut self) {
         self.completed_at = Some(chrono::Utc::now());
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(line: &str) -> Command {
+        let args: Vec<String> = ["todo"]
+            .into_iter()
+            .chain(line.split_whitespace())
+            .map(String::from)
+            .collect();
+        Command::parse(&args).unwrap()
+    }
+
+    #[test]
+    fn estimates_are_set_and_cleared() {
+        let mut task = task_from_command(&command("add Task --estimate 1h30m"), 0, 0).unwrap();
+        assert_eq!(task.estimate(), Some(Duration::minutes(90)));
+
+        update_task_from_command(&mut task, &command("modify 0 --estimate 2h")).unwrap();
+        assert_eq!(task.estimate(), Some(Duration::hours(2)));
+        update_task_from_command(&mut task, &command("modify 0 --estimate none")).unwrap();
+        assert_eq!(task.estimate(), None);
+        assert!(update_task_from_command(&mut task, &command("modify 0 --estimate soon")).is_err());
+    }
+
+    #[test]
+    fn annotations_are_numbered_from_one() {
+        let mut task = task_from_command(&command("add Task"), 0, 0).unwrap();
+        for text in ["first", "second", "third"] {
+            task.annotate(Annotation {
+                created_at: Utc::now(),
+                text: text.to_string(),
+            });
+        }
+
+        task.edit_annotation(2, String::from("changed")).unwrap();
+        assert_eq!(task.remove_annotation(1).unwrap().text, "first");
+        let texts: Vec<&str> = task.annotations().iter().map(|a| a.text.as_str()).collect();
+        assert_eq!(texts, ["changed", "third"]);
+        assert!(task.matches("THIRD"));
+
+        assert!(task.edit_annotation(0, String::new()).is_err());
+        assert!(task.remove_annotation(3).is_err());
+    }
+}
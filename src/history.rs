@@ -0,0 +1,196 @@
+use std::cmp::Ordering;
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{hierarchy::Task, timeutil::format_duration};
+
+/// One entry of a task's history: `field` went from `old` to `new` at `at`.
+/// Lifecycle events such as creation or trashing use the same shape, with a
+/// missing value on the side where the task didn't exist or wasn't in that state.
+/// Changes belong to a task by uuid; `task_id` is only the short id the task
+/// had at the time, which a merge or purge may since have given to another.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Change {
+    pub at: DateTime<Utc>,
+    pub task_id: usize,
+    #[serde(default)]
+    pub task_uuid: String,
+    pub field: String,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+impl Change {
+    pub fn new(
+        task: &Task,
+        at: DateTime<Utc>,
+        field: &str,
+        old: Option<String>,
+        new: Option<String>,
+    ) -> Self {
+        Change::of(task.id(), task.uuid(), at, field, old, new)
+    }
+
+    /// Like `new`, for a task known only by its ids.
+    pub fn of(
+        task_id: usize,
+        task_uuid: &str,
+        at: DateTime<Utc>,
+        field: &str,
+        old: Option<String>,
+        new: Option<String>,
+    ) -> Self {
+        Change {
+            at,
+            task_id,
+            task_uuid: task_uuid.to_string(),
+            field: field.to_string(),
+            old,
+            new,
+        }
+    }
+
+    /// The task came into existence.
+    pub fn created(task: &Task, at: DateTime<Utc>) -> Self {
+        Change::new(task, at, "created", None, Some(task.name().clone()))
+    }
+
+    /// The task entered (`entered`) or left a state such as `trashed` or `archived`.
+    pub fn state(task: &Task, at: DateTime<Utc>, state: &str, entered: bool) -> Self {
+        Change::entered(task.id(), task.uuid(), at, state, entered)
+    }
+
+    /// Like `state`, for a task known only by its ids.
+    pub fn entered(
+        task_id: usize,
+        task_uuid: &str,
+        at: DateTime<Utc>,
+        state: &str,
+        entered: bool,
+    ) -> Self {
+        let value = Some(state.to_string());
+        if entered {
+            Change::of(task_id, task_uuid, at, state, None, value)
+        } else {
+            Change::of(task_id, task_uuid, at, state, value, None)
+        }
+    }
+}
+
+/// Changes that turn `old` into `new`, one per field that differs.
+pub fn diff(old: &Task, new: &Task, at: DateTime<Utc>) -> Vec<Change> {
+    let mut changes = Vec::new();
+    let mut compare = |field: &str, before: Option<String>, after: Option<String>| {
+        if before != after {
+            changes.push(Change::new(new, at, field, before, after));
+        }
+    };
+
+    compare("name", Some(old.name().clone()), Some(new.name().clone()));
+    compare(
+        "description",
+        text(old.description()),
+        text(new.description()),
+    );
+    compare(
+        "priority",
+        Some(format!("{:?}", old.priority())),
+        Some(format!("{:?}", new.priority())),
+    );
+    compare(
+        "project",
+        Some(old.project_id().to_string()),
+        Some(new.project_id().to_string()),
+    );
+    compare(
+        "due",
+        old.due_time().map(|d| d.to_string()),
+        new.due_time().map(|d| d.to_string()),
+    );
+    compare(
+        "status",
+        Some(status(old).to_string()),
+        Some(status(new).to_string()),
+    );
+    compare(
+        "estimate",
+        old.estimate().map(format_duration),
+        new.estimate().map(format_duration),
+    );
+    compare("tags", list(old.tags()), list(new.tags()));
+    compare("depends", list(old.depends_on()), list(new.depends_on()));
+    compare("tracked", tracked(old), tracked(new));
+    compare(
+        "timer",
+        old.is_tracking().then(|| String::from("running")),
+        new.is_tracking().then(|| String::from("running")),
+    );
+
+    // Annotations are only ever appended, edited or removed one at a time.
+    let (before, after) = (old.annotations(), new.annotations());
+    let first_difference = before
+        .iter()
+        .zip(after)
+        .position(|(a, b)| a.text != b.text)
+        .unwrap_or(before.len().min(after.len()));
+    let old_text = before.get(first_difference).map(|a| a.text.clone());
+    let new_text = after.get(first_difference).map(|a| a.text.clone());
+    match before.len().cmp(&after.len()) {
+        Ordering::Less => compare("annotation", None, new_text),
+        Ordering::Greater => compare("annotation", old_text, None),
+        Ordering::Equal => compare("annotation", old_text, new_text),
+    }
+    changes
+}
+
+fn text(value: &str) -> Option<String> {
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+fn list<T: ToString>(values: &[T]) -> Option<String> {
+    let joined: Vec<String> = values.iter().map(T::to_string).collect();
+    text(&joined.join(", "))
+}
+
+fn status(task: &Task) -> &'static str {
+    match task.completed_at() {
+        Some(_) => "done",
+        None => "open",
+    }
+}
+
+/// Time in finished entries, so a running timer doesn't count as a change.
+fn tracked(task: &Task) -> Option<String> {
+    let total = task
+        .time_entries()
+        .iter()
+        .filter_map(|e| e.ended_at.map(|end| end - e.started_at))
+        .fold(Duration::zero(), |acc, d| acc + d);
+    (!total.is_zero()).then(|| format_duration(total))
+}
+
+impl Change {
+    /// Human-readable summary, e.g. `priority: Low -> High`. Project values are
+    /// stored as ids and shown through `project_name`.
+    pub fn describe(&self, project_name: impl Fn(usize) -> Option<String>) -> String {
+        let value = |v: &Option<String>| match (self.field.as_str(), v) {
+            ("project", Some(id)) => id
+                .parse()
+                .ok()
+                .and_then(&project_name)
+                .unwrap_or_else(|| format!("project {id}")),
+            (_, Some(v)) => v.clone(),
+            (_, None) => String::from("-"),
+        };
+        match (self.field.as_str(), &self.old, &self.new) {
+            ("created", _, new) => format!("created as {}", value(new)),
+            ("trashed", None, _) => String::from("moved to the trash"),
+            ("trashed", Some(_), _) => String::from("restored from the trash"),
+            ("archived" | "deleted", _, _) => self.field.clone(),
+            ("annotation", None, new) => format!("annotated: {}", value(new)),
+            ("annotation", old, None) => format!("annotation removed: {}", value(old)),
+            (field, old, new) => format!("{field}: {} -> {}", value(old), value(new)),
+        }
+    }
+}
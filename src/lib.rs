@@ -3,6 +3,20 @@
 #![allow(unused_mut)]
 
 pub mod app_state;
+pub mod chart;
 pub mod command;
+pub mod completions;
+pub mod config;
 pub mod database;
+pub mod error;
+pub mod file_storage;
 pub mod hierarchy;
+pub mod history;
+pub mod render;
+pub mod replica;
+pub mod stats;
+pub mod storage;
+pub mod sync;
+pub mod timeutil;
+pub mod urgency;
+pub mod working_set;
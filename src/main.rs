@@ -2,7 +2,13 @@
 #![allow(unused_variables)]
 #![allow(unused_mut)]
 use std::{env, process::exit};
-use todo::{app_state::AppState, command::Command};
+use todo::{
+    app_state::AppState,
+    command::{Assume, COMMANDS, Command, CommandSpec, HIDDEN_COMMANDS, suggest},
+    completions,
+    config::Config,
+    error::{Result, TodoError},
+};
 
 fn main() {
     let args: Vec<String> = env::args().collect();
@@ -12,94 +18,343 @@ fn main() {
         exit(0);
     }
 
-    let command: Command = Command::new(args);
-    let mut app_state = AppState::load();
+    // `config` reads the file itself, so that it keeps working when the file
+    // doesn't load and can be used to repair it.
+    let config = match args[1].as_str() {
+        "config" => Config::default(),
+        _ => or_exit(Config::load()),
+    };
+    let args = config.expand_alias(args);
 
-    match command.op() {
-        "add" => {
-            if let Err(e) = app_state.handle_add(&command) {
-                eprintln!("error: {e}");
-                exit(1);
-            }
-        }
-        "list" | "ls" => app_state.handle_list(0),
-        "remove" | "rm" => {
-            if let Err(e) = app_state.handle_remove(&command) {
-                eprintln!("error: {e}");
-                exit(1);
-            }
+    if let Some(spec) = CommandSpec::find(&args[1]) {
+        let options = args[2..].iter().take_while(|arg| *arg != "--");
+        if options
+            .into_iter()
+            .any(|arg| arg == "--help" || arg == "-h")
+        {
+            print!("{}", spec.help());
+            return;
         }
-        "modify" | "mod" => {
-            if let Err(e) = app_state.handle_modify(&command) {
-                eprintln!("error: {e}");
-                exit(1);
-            }
-        }
-        "project" | "cr" => {
-            if let Err(e) = app_state.handle_create_project(&command) {
-                eprintln!("error: {e}");
-                exit(1);
-            }
-        }
-        "remove-project" | "rmp" => {
-            if let Err(e) = app_state.handle_remove_project(&command) {
-                eprintln!("error: {e}");
-                exit(1);
-            }
+    }
+    match args[1].as_str() {
+        "help" | "--help" | "-h" => {
+            or_exit(handle_help(&args[2..]));
+            return;
         }
-        "show" => {
-            if let Err(e) = app_state.handle_show(&command) {
-                eprintln!("error: {e}");
-                exit(1);
+        op if CommandSpec::find(op).is_none() && !HIDDEN_COMMANDS.contains(&op) => {
+            eprintln!("Unknown command: {op}");
+            let names = COMMANDS
+                .iter()
+                .flat_map(|c| [c.name].into_iter().chain(c.aliases.iter().copied()));
+            if let Some(similar) = suggest(op, names) {
+                eprintln!("Did you mean '{similar}'?");
             }
+            eprintln!("Run 'todo help' for usage information");
+            exit(1);
         }
-        "complete" | "done" => {
-            if let Err(e) = app_state.handle_complete(&command) {
-                eprintln!("error: {e}");
-                exit(1);
+        _ => {}
+    }
+
+    // `config` works on the file alone, so it must not require a usable database.
+    if args[1] == "config" {
+        or_exit(handle_config(&args[2..]));
+        return;
+    }
+    if args[1] == "completions" {
+        or_exit(handle_completions(&args[2..]));
+        return;
+    }
+
+    // Usage errors shouldn't need the data file; the arguments are parsed again
+    // once uuids in them have been looked up.
+    or_exit(Command::parse(&args));
+    let loaded = match env::var_os("TODO_DB") {
+        Some(path) => AppState::open(path),
+        None => match &config.database {
+            Some(path) => AppState::open(path),
+            None => AppState::load(),
+        },
+    };
+    let mut app_state = or_exit(loaded);
+    let mut command = or_exit(Command::parse(&or_exit(app_state.resolve_uuids(args))));
+    app_state.set_config(config);
+    or_exit(app_state.apply_working_set(&mut command));
+
+    if let Some(value) = env::var_os("TODO_ASSUME") {
+        match Assume::parse(&value.to_string_lossy()) {
+            Some(assume) => app_state.set_assume(assume),
+            None => {
+                eprintln!("error: TODO_ASSUME must be one of yes, no or ask");
+                exit(2);
             }
         }
-        "help" | "--help" | "-h" => {
-            print_help();
-        }
+    }
+
+    let result = match command.op() {
+        "add" => app_state.handle_add(&command),
+        "list" | "ls" => app_state.handle_list(&command),
+        "remove" | "rm" => app_state.handle_remove(&command),
+        "modify" | "mod" => app_state.handle_modify(&command),
+        "project" | "cr" => app_state.handle_create_project(&command),
+        "remove-project" | "rmp" => app_state.handle_remove_project(&command),
+        "show" => app_state.handle_show(&command),
+        "note" => app_state.handle_note(&command),
+        "search" => app_state.handle_search(&command),
+        "track" => app_state.handle_track(&command),
+        "timesheet" => app_state.handle_timesheet(&command),
+        "estimates" => app_state.handle_estimates(&command),
+        "stats" => app_state.handle_stats(&command),
+        "chart" => app_state.handle_chart(&command),
+        "agenda" => app_state.handle_agenda(&command),
+        "next" => app_state.handle_next(&command),
+        "archive" => app_state.handle_archive(&command),
+        "purge" => app_state.handle_purge(&command),
+        "trash" => app_state.handle_trash(&command),
+        "history" => app_state.handle_history(&command),
+        "log" => app_state.handle_log(&command),
+        "sync" => app_state.handle_sync(&command),
+        "merge" => app_state.handle_merge(&command),
+        "gc" => app_state.handle_gc(&command),
+        "__complete" => app_state.handle_query(&command),
+        "calendar" | "cal" => app_state.handle_calendar(&command),
+        "complete" | "done" => app_state.handle_complete(&command),
         _ => {
             eprintln!("Unknown command: {}", command.op());
             eprintln!("Run 'todo help' for usage information");
             exit(1);
         }
+    };
+
+    or_exit(result);
+    // Only after changes, so that reading, and completing on Tab in particular,
+    // never archives or deletes anything.
+    if command.writes() {
+        or_exit(app_state.apply_retention_policies());
+    }
+}
+
+fn or_exit<T>(result: Result<T>) -> T {
+    match result {
+        Ok(value) => value,
+        Err(e) => {
+            eprintln!("error: {e}");
+            exit(e.exit_code());
+        }
     }
 }
 
+fn handle_config(args: &[String]) -> Result<()> {
+    // Values that don't validate are still shown, with a warning, so that they
+    // can be found and set right.
+    let load = || -> Result<Config> {
+        let config = Config::load_unchecked()?;
+        if let Err(e) = config.validate() {
+            eprintln!("warning: {e}");
+        }
+        Ok(config)
+    };
+    match args {
+        [] => print!("{}", load()?.get(None)?),
+        [op] if op == "path" => println!("{}", Config::path().display()),
+        [op] if op == "get" => print!("{}", load()?.get(None)?),
+        [op, key] if op == "get" => println!("{}", load()?.get(Some(key))?),
+        [op, key, value] if op == "set" => Config::set(&Config::path(), key, value)?,
+        _ => {
+            return Err(TodoError::invalid(
+                "usage: todo config [get [key] | set <key> <value> | path]",
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn handle_help(args: &[String]) -> Result<()> {
+    match args {
+        [] => print_help(),
+        [name] => match CommandSpec::find(name) {
+            Some(spec) => print!("{}", spec.help()),
+            None => return Err(TodoError::not_found(format!("no command named '{name}'"))),
+        },
+        _ => return Err(TodoError::invalid("usage: todo help [command]")),
+    }
+    Ok(())
+}
+
+fn handle_completions(args: &[String]) -> Result<()> {
+    match args {
+        [shell] => print!("{}", completions::script(shell)?),
+        _ => return Err(TodoError::invalid("usage: todo completions bash|zsh|fish")),
+    }
+    Ok(())
+}
+
 fn print_help() {
     println!("Todo - A simple task management CLI");
     println!();
     println!("USAGE:");
     println!("    todo <COMMAND> [OPTIONS]");
+    println!("    A task or project uuid (see show) works wherever an id or project name does,");
+    println!("    and #id always means the stored id, even with working-set numbers on.");
+    println!("    Values go after the flag or as --flag=value; after -- nothing is a flag.");
+    println!("    todo <COMMAND> --help lists the options a command takes.");
+    println!("    The words of a task name need no quotes. In add and modify, due:<date>,");
+    println!("    pri:<priority>, est:<time>, dep:<id>, +tag and @Project set those fields;");
+    println!("    words after -- are all part of the name.");
     println!();
     println!("COMMANDS:");
     println!("    add <task>              Add a new task");
     println!("    list, ls                List all tasks and projects");
-    println!("    remove, rm <id>         Remove a task by ID");
+    println!("    remove, rm <id>         Move a task to the trash");
     println!("    modify, mod <id>        Modify a task by ID");
     println!("    show <id>               Show details of a task");
     println!("    complete, done <id>     Mark a task as complete");
+    println!("    note <id> <text>        Append a timestamped annotation to a task");
+    println!("    search <text>           Find tasks by name, description or annotation");
+    println!("    track start <id>        Start the timer on a task (one timer at a time)");
+    println!("    track stop              Stop the running timer");
+    println!("    track add <id> <time>   Record time spent, e.g. 1h30m (--date for another day)");
+    println!("    track                   Show the running timer");
+    println!("    timesheet               Tracked time per project (--week, --month, -p)");
+    println!("    estimates               Open estimates per project and estimate vs actual");
+    println!(
+        "    stats                   Productivity statistics (--week, --month, --since, --json)"
+    );
+    println!("    agenda                  Open tasks grouped by due date (-p)");
+    println!("    next [n]                The n most urgent unblocked tasks (-p)");
+    println!(
+        "    archive                 Move completed tasks out of list (--completed-before, -p)"
+    );
+    println!(
+        "    purge                   Permanently delete archived tasks (--completed-before, -p)"
+    );
+    println!("    calendar, cal           Month grid of tasks due per day (--month, --date, -p)");
+    println!("    chart burndown          Open tasks per day (-p, --since, --svg <file>)");
+    println!(
+        "    chart activity          Heatmap of completions per day (-p, --since, --svg <file>)"
+    );
     println!("    project <name>          Create a new project");
-    println!("    remove-project, rmp <name>  Remove a project");
-    println!("    help                    Show this help message");
+    println!("    remove-project, rmp <name>  Move a project and its tasks to the trash");
+    println!("    history <id>            Every recorded change to a task");
+    println!("    log                     Changes to all tasks (--since, default 1w)");
+    println!("    trash [list]            Show removed tasks and projects");
+    println!("    trash restore <id|name> Bring back a removed task, or a project with its tasks");
+    println!("    trash empty             Permanently delete everything in the trash");
+    println!("    sync [remote]           Merge with a git repository and push (--ours, --theirs)");
+    println!("    merge <file>            Fold another copy of the database into this one");
+    println!(
+        "    gc                      Number the open tasks from 1 again (working_set, --json)"
+    );
+    println!("    config get [key]        Show the configuration or a single key");
+    println!("    config set <key> <val>  Change a key in the configuration file");
+    println!("    config path             Print the configuration file location");
+    println!("    completions <shell>     Print a bash, zsh or fish completion script");
+    println!("    help [command]          Show this help message, or a command's own");
     println!();
     println!("OPTIONS:");
     println!("    -p, --project <name>    Specify project name");
     println!("    -d, --description <text> Add description");
-    println!("    --priority <level>      Set priority (low/l, medium/m, high/h)");
+    println!(
+        "    --priority <level>      Set priority: none, low, medium, high (n/l/m/h), 0-3 or P3-P0"
+    );
+    println!("    -e, --estimate <time>   Set the effort estimate, e.g. 2h or 1h30m");
+    println!("    -t, --tag <tag>         Add a tag; -t -tag removes it (repeatable)");
+    println!(
+        "    --completed-before <t>  With archive/purge: only tasks completed at least t ago, e.g. 30d"
+    );
+    println!("    --archived              With list: show archived tasks instead");
+    println!("    --ours, --theirs        With sync: settle conflicts with this or the other side");
+    println!("    --depends <id>          Depend on another task; -id removes, none clears");
+    println!(
+        "    --due <day>             Set the due date: YYYY-MM-DD, tomorrow, fri, +3d (none clears)"
+    );
     println!("    -f, --force             Force operation (e.g., remove project with tasks)");
+    println!("    -a, --all               List completed tasks even if list.show_completed is off");
+    println!("    --color <when>          Colour output: auto, always or never");
+    println!("    --edit <n>              With note: replace the text of annotation n");
+    println!("    --delete <n>            With note: remove annotation n");
+    println!(
+        "    --date <day>            Day for track add/timesheet or month for calendar: YYYY-MM-DD, mon..."
+    );
+    println!(
+        "    --week, --month         Timesheet period (default week) or stats buckets (default day)"
+    );
+    println!("    --since <span>          Start of a report window, e.g. 30d or 2w");
+    println!("    --json                  Machine-readable output for list, show, stats and gc");
+    println!("    --svg <file>            Write a chart to an SVG file instead of the terminal");
+    println!("    -y, --yes               Answer yes to confirmation prompts");
+    println!("    --no                    Answer no to confirmation prompts");
+    println!();
+    println!("CONFIGURATION:");
+    println!("    $XDG_CONFIG_HOME/todo/config.toml, e.g.:");
+    println!("        default_project = \"Work\"");
+    println!("        default_priority = \"medium\"");
+    println!("        date_format = \"%d/%m/%Y %H:%M\"");
+    println!("        color = \"auto\"            # auto, always or never");
+    println!("        auto_create_projects = true");
+    println!("        working_set = true         # short numbers for open tasks, renewed by gc");
+    println!("        [aliases]");
+    println!("        urgent = \"add --priority high\"");
+    println!("        [list]");
+    println!("        project = \"Work\"");
+    println!("        show_completed = false");
+    println!("        [archive]");
+    println!("        after = \"30d\"             # archive completed tasks automatically");
+    println!("        [trash]");
+    println!("        expire_after = \"30d\"      # or \"never\"");
+    println!("        [sync]");
+    println!("        remote = \"git@example.com:me/todo.git\"");
+    println!("        dir = \"/home/me/.todo-sync\"  # working copy (default ~/.todo-sync)");
+    println!("        branch = \"main\"");
+    println!("        [urgency]              # coefficients of the next/show urgency score");
+    println!("        priority_high = 6.0");
+    println!("        due = 12.0");
+    println!("        blocked = -5.0");
+    println!("        tag.waiting = -3.0");
+    println!("        project.Work = 1.0");
+    println!();
+    println!("ENVIRONMENT:");
+    println!("    TODO_CONFIG             Path of the configuration file");
+    println!("    TODO_DB                 Path of the data file (default ~/.todo.db);");
+    println!("                            a .json path selects the plain-file backend");
+    println!("    TODO_ASSUME             yes, no or ask: default answer to prompts");
+    println!("    NO_COLOR                Disable colour unless --color or the config forces it");
+    println!();
+    println!("EXIT CODES:");
+    println!("    1  unknown command");
+    println!("    2  invalid input");
+    println!("    3  task or project not found");
+    println!("    4  conflict (e.g., project already exists)");
+    println!("    5  operation cancelled");
+    println!("    6  database error");
+    println!("    7  io error");
+    println!("    8  malformed data file");
     println!();
     println!("EXAMPLES:");
     println!("    todo add \"Buy groceries\" -p Home --priority high");
+    println!("    todo add Fix login bug due:fri pri:h +backend @Work");
     println!("    todo list");
     println!("    todo show 0");
+    println!("    todo show 3f2c9a4e-8d1b-4c7a-9e55-0b6d2f41a7c3 --json");
     println!("    todo modify 0 \"Buy groceries and cook\" -d \"Updated task\"");
     println!("    todo complete 0");
+    println!("    todo note 0 \"called vendor, waiting on quote\"");
+    println!("    todo note 0 --delete 1");
+    println!("    todo track add 0 1h30m --date yesterday");
+    println!("    todo timesheet --week");
+    println!("    todo add \"File taxes\" --due 2026-04-15");
+    println!("    todo agenda -p Work");
+    println!("    todo add \"Deploy\" -t ops --depends 3");
+    println!("    todo next 10");
+    println!("    todo archive --completed-before 30d");
+    println!("    todo chart burndown -p Work --since 2w");
     println!("    todo remove 0");
+    println!("    todo trash restore 0");
+    println!("    todo history 12");
+    println!("    todo log --since 3d");
+    println!("    todo sync ~/Dropbox/todo.git");
+    println!("    todo merge /mnt/laptop/.todo.db");
+    println!("    todo gc && todo show '#1042'");
+    println!("    source <(todo completions bash)");
     println!("    todo project Work");
     println!("    todo remove-project Work --force");
 }
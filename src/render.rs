@@ -0,0 +1,424 @@
+use std::{
+    collections::BTreeMap,
+    env,
+    io::{self, IsTerminal},
+};
+
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDate, Utc};
+
+use crate::{
+    error::{Result, TodoError},
+    hierarchy::{Priority, Task},
+    timeutil::format_duration,
+    working_set::WorkingSet,
+};
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const DIM: &str = "\x1b[2m";
+const STRIKE: &str = "\x1b[9m";
+const RED: &str = "\x1b[31m";
+const YELLOW: &str = "\x1b[33m";
+const BLUE: &str = "\x1b[34m";
+
+/// Value of `--color` or the `color` config key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err(TodoError::invalid(format!(
+                "invalid color '{value}', expected auto, always or never"
+            ))),
+        }
+    }
+
+    /// Whether to emit ANSI styling on stdout. An explicit choice wins over
+    /// `NO_COLOR`, which in turn wins over terminal detection.
+    pub fn enabled(self) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                env::var_os("NO_COLOR").is_none_or(|v| v.is_empty()) && io::stdout().is_terminal()
+            }
+        }
+    }
+}
+
+/// One line of a `list` listing.
+pub enum Entry<'a> {
+    Project { depth: usize, name: &'a str },
+    Task { depth: usize, task: &'a Task },
+}
+
+/// Formats tasks for the terminal, with optional colour and width-aware truncation.
+pub struct Renderer {
+    color: bool,
+    width: Option<usize>,
+    today: NaiveDate,
+    now: DateTime<Utc>,
+    ids: WorkingSet,
+}
+
+impl Renderer {
+    pub fn new(color: bool, ids: WorkingSet) -> Self {
+        Renderer {
+            color,
+            ids,
+            width: terminal_width(),
+            today: Local::now().date_naive(),
+            now: Utc::now(),
+        }
+    }
+
+    pub fn paint(&self, text: &str, style: &str) -> String {
+        if self.color && !style.is_empty() {
+            format!("{style}{text}{RESET}")
+        } else {
+            text.to_string()
+        }
+    }
+
+    pub fn priority_style(priority: Priority) -> &'static str {
+        match priority {
+            Priority::High => RED,
+            Priority::Medium => YELLOW,
+            Priority::Low => BLUE,
+            Priority::None => "",
+        }
+    }
+
+    /// The id of `task` as the user writes it.
+    pub fn id(&self, task: &Task) -> String {
+        self.ids.label(task.id())
+    }
+
+    pub fn is_overdue(&self, task: &Task) -> bool {
+        task.completed_at().is_none() && task.due_time().is_some_and(|due| due < self.today)
+    }
+
+    pub fn header(&self, text: &str) -> String {
+        self.paint(text, BOLD)
+    }
+
+    pub fn dim(&self, text: &str) -> String {
+        self.paint(text, DIM)
+    }
+
+    /// Colour of a heatmap cell at intensity `level` (0-4).
+    pub fn heat_style(level: usize) -> &'static str {
+        match level {
+            0 => DIM,
+            1 => "\x1b[38;5;22m",
+            2 => "\x1b[38;5;28m",
+            3 => "\x1b[38;5;34m",
+            _ => "\x1b[38;5;40m",
+        }
+    }
+
+    pub fn due(&self, task: &Task) -> String {
+        let text = task.due_time().map(|d| d.to_string()).unwrap_or_default();
+        if self.is_overdue(task) {
+            self.paint(&text, &format!("{BOLD}{RED}"))
+        } else {
+            text
+        }
+    }
+
+    pub fn status(&self, task: &Task) -> &'static str {
+        if task.completed_at().is_some() {
+            "done"
+        } else if self.is_overdue(task) {
+            "overdue"
+        } else {
+            "open"
+        }
+    }
+
+    /// Renders projects as indented headings with their tasks in columns that
+    /// line up across nesting levels.
+    pub fn list(&self, entries: &[Entry]) -> String {
+        let mut id_w = "ID".len();
+        let mut due_w = "DUE".len();
+        let mut est_w = "EST".len();
+        let mut age_w = "AGE".len();
+        let mut has_tasks = false;
+        for entry in entries {
+            if let Entry::Task { depth, task } = entry {
+                has_tasks = true;
+                id_w = id_w.max(depth * 2 + self.id(task).len());
+                due_w = due_w.max(task.due_time().map_or(0, |_| "YYYY-MM-DD".len()));
+                age_w = age_w.max(self.age(task).len());
+                est_w = est_w.max(estimate(task).len());
+            }
+        }
+        let pri_w = "PRI".len();
+        let status_w = "overdue".len();
+        let fixed = id_w + pri_w + due_w + est_w + age_w + status_w + 6 * 2;
+        let name_w = self.width.map(|w| w.saturating_sub(fixed).max(10));
+
+        let mut out = String::new();
+        if has_tasks {
+            let line = format!(
+                "{:<id_w$}  {:<pri_w$}  {:<due_w$}  {:<est_w$}  {:<age_w$}  {:<status_w$}  NAME",
+                "ID", "PRI", "DUE", "EST", "AGE", "STATUS"
+            );
+            out.push_str(&self.paint(&line, DIM));
+            out.push('\n');
+        }
+
+        for entry in entries {
+            match entry {
+                Entry::Project { depth, name } => {
+                    let line = format!("{:indent$}{}", "", name, indent = depth * 2);
+                    out.push_str(&self.header(&line));
+                }
+                Entry::Task { depth, task } => {
+                    let indent = depth * 2;
+                    let done = task.completed_at().is_some();
+                    let pri = pad(priority_short(task.priority()), pri_w);
+                    // Completed rows are dimmed as a whole, so nothing inside may reset the style.
+                    let pri_style = if done {
+                        ""
+                    } else {
+                        Self::priority_style(task.priority())
+                    };
+                    let due_visible = task.due_time().map_or(0, |_| "YYYY-MM-DD".len());
+                    let name = match name_w {
+                        Some(w) => truncate(task.name(), w),
+                        None => task.name().clone(),
+                    };
+                    let line = format!(
+                        "{:indent$}{:<rest$}  {}  {}  {:<est_w$}  {:<age_w$}  {}  {}",
+                        "",
+                        self.id(task),
+                        self.paint(&pri, pri_style),
+                        pad_styled(&self.due(task), due_visible, due_w),
+                        estimate(task),
+                        self.age(task),
+                        pad(self.status(task), status_w),
+                        name,
+                        rest = id_w - indent,
+                    );
+                    if done {
+                        out.push_str(&self.paint(&line, &format!("{DIM}{STRIKE}")));
+                    } else {
+                        out.push_str(&line);
+                    }
+                }
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    /// Month grid starting on Monday for the month containing `month`, with the
+    /// number of tasks due on each day. Today is bold and past days with tasks
+    /// still due are red.
+    pub fn calendar(&self, month: NaiveDate, due: &BTreeMap<NaiveDate, usize>) -> String {
+        let first = month.with_day(1).expect("day 1 exists");
+        let mut out = self.header(&first.format("%B %Y").to_string());
+        out.push('\n');
+        out.push_str(&self.paint("Mo     Tu     We     Th     Fr     Sa     Su", DIM));
+        out.push('\n');
+
+        let mut day = first - Duration::days(first.weekday().num_days_from_monday() as i64);
+        while day.month() == first.month() || day < first {
+            for _ in 0..7 {
+                let cell = if day.month() == first.month() {
+                    let count = due.get(&day).copied().unwrap_or(0);
+                    let count_text = match count {
+                        0 => String::new(),
+                        n => format!("({n})"),
+                    };
+                    let text = format!("{:>2}{:<4}", day.day(), count_text);
+                    if day == self.today {
+                        self.paint(&text, &format!("{BOLD}{YELLOW}"))
+                    } else if count > 0 && day < self.today {
+                        self.paint(&text, RED)
+                    } else if count > 0 {
+                        self.paint(&text, BOLD)
+                    } else {
+                        text
+                    }
+                } else {
+                    " ".repeat(6)
+                };
+                out.push_str(&cell);
+                out.push(' ');
+                day += Duration::days(1);
+            }
+            out.truncate(out.trim_end().len());
+            out.push('\n');
+        }
+        out
+    }
+
+    /// Time since creation, in the largest unit that fits: `5m`, `3h`, `2d`, `6w`, `1y`.
+    pub fn age(&self, task: &Task) -> String {
+        let minutes = (self.now - *task.created_at()).num_minutes().max(0);
+        match minutes {
+            m if m < 60 => format!("{m}m"),
+            m if m < 60 * 24 => format!("{}h", m / 60),
+            m if m < 60 * 24 * 14 => format!("{}d", m / (60 * 24)),
+            m if m < 60 * 24 * 365 => format!("{}w", m / (60 * 24 * 7)),
+            m => format!("{}y", m / (60 * 24 * 365)),
+        }
+    }
+}
+
+fn estimate(task: &Task) -> String {
+    task.estimate().map(format_duration).unwrap_or_default()
+}
+
+fn priority_short(priority: Priority) -> &'static str {
+    match priority {
+        Priority::High => "H",
+        Priority::Medium => "M",
+        Priority::Low => "L",
+        Priority::None => "",
+    }
+}
+
+fn pad(text: &str, width: usize) -> String {
+    format!("{text:<width$}")
+}
+
+/// Pads text that may contain escape codes, given the length of its visible part.
+fn pad_styled(text: &str, visible: usize, width: usize) -> String {
+    format!("{text}{:pad$}", "", pad = width.saturating_sub(visible))
+}
+
+fn truncate(text: &str, width: usize) -> String {
+    if text.chars().count() <= width {
+        return text.to_string();
+    }
+    let mut out: String = text.chars().take(width.saturating_sub(1)).collect();
+    out.push('…');
+    out
+}
+
+/// Width of the terminal on stdout, or `COLUMNS` when it isn't one. `None`
+/// disables truncation, which is what we want when piping into other tools.
+fn terminal_width() -> Option<usize> {
+    if let Some((terminal_size::Width(w), _)) = terminal_size::terminal_size() {
+        return Some(w as usize);
+    }
+    env::var("COLUMNS").ok().and_then(|c| c.parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn renderer(color: bool) -> Renderer {
+        Renderer {
+            color,
+            width: None,
+            today: NaiveDate::from_ymd_opt(2026, 10, 19).unwrap(),
+            now: Utc::now(),
+            ids: WorkingSet::default(),
+        }
+    }
+
+    fn task(id: usize, name: &str, priority: Priority, due: Option<NaiveDate>) -> Task {
+        Task::new(
+            id,
+            0,
+            name.to_string(),
+            String::new(),
+            priority,
+            Utc::now(),
+            due,
+            None,
+        )
+    }
+
+    #[test]
+    fn list_columns_line_up_across_nesting() {
+        let due = NaiveDate::from_ymd_opt(2026, 10, 30);
+        let (short, nested) = (
+            task(1, "Short", Priority::None, None),
+            task(12, "Nested", Priority::High, due),
+        );
+        let entries = [
+            Entry::Project {
+                depth: 0,
+                name: "Home",
+            },
+            Entry::Task {
+                depth: 1,
+                task: &short,
+            },
+            Entry::Project {
+                depth: 1,
+                name: "Work",
+            },
+            Entry::Task {
+                depth: 2,
+                task: &nested,
+            },
+        ];
+        let out = renderer(false).list(&entries);
+        assert!(!out.contains('\x1b'));
+
+        let lines: Vec<&str> = out.lines().collect();
+        let column = |line: usize, text: &str| lines[line].find(text).unwrap();
+        assert_eq!(column(1, "Home"), 0);
+        assert_eq!(column(3, "Work"), 2);
+        assert_eq!(column(2, "Short"), column(0, "NAME"));
+        assert_eq!(column(4, "Nested"), column(0, "NAME"));
+        assert_eq!(column(4, "H "), column(0, "PRI"));
+        assert_eq!(column(4, "2026-10-30"), column(0, "DUE"));
+    }
+
+    #[test]
+    fn colour_marks_priority_and_overdue_tasks() {
+        let overdue = task(
+            0,
+            "Late",
+            Priority::High,
+            NaiveDate::from_ymd_opt(2026, 10, 1),
+        );
+        let r = renderer(true);
+        let out = r.list(&[Entry::Task {
+            depth: 0,
+            task: &overdue,
+        }]);
+        assert!(out.contains(&format!("{RED}H  {RESET}")));
+        assert!(out.contains(&format!("{BOLD}{RED}2026-10-01{RESET}")));
+        assert!(out.contains("overdue"));
+        assert_eq!(r.status(&overdue), "overdue");
+        assert_eq!(renderer(false).paint("x", RED), "x");
+    }
+
+    #[test]
+    fn calendar_starts_on_monday_and_marks_due_days() {
+        let month = NaiveDate::from_ymd_opt(2026, 10, 1).unwrap();
+        let due = BTreeMap::from([(NaiveDate::from_ymd_opt(2026, 10, 14).unwrap(), 2)]);
+        let calendar = renderer(false).calendar(month, &due);
+        let lines: Vec<&str> = calendar.lines().collect();
+        assert!(lines[0].contains("October 2026"));
+        assert_eq!(lines[1], "Mo     Tu     We     Th     Fr     Sa     Su");
+        assert_eq!(
+            lines[2],
+            format!("{} 1      2      3      4", " ".repeat(21))
+        );
+        assert_eq!(lines[4], "12     13     14(2)  15     16     17     18");
+        assert_eq!(lines[6], "26     27     28     29     30     31");
+        assert_eq!(lines.len(), 7);
+
+        let colored = renderer(true).calendar(month, &due);
+        assert!(colored.contains(&format!("{RED}14(2) {RESET}")));
+        assert!(colored.contains(&format!("{BOLD}{YELLOW}19    {RESET}")));
+    }
+}
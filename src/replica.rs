@@ -0,0 +1,83 @@
+use serde_json::{Map, Value};
+use uuid::Uuid;
+
+use crate::{
+    error::{Result, TodoError},
+    hierarchy::Task,
+};
+
+/// Task fields that are stamped and merged one by one, by their serialized
+/// names. Ids, the creation time and the version are not edited, so they have
+/// no stamp.
+pub const FIELDS: &[&str] = &[
+    "name",
+    "description",
+    "priority",
+    "project_id",
+    "due_time",
+    "completed_at",
+    "estimate_minutes",
+    "annotations",
+    "time_entries",
+    "tags",
+    "depends_on",
+];
+
+/// Pseudo-field stamped when a task goes into or comes out of the trash.
+pub const TRASHED: &str = "trashed";
+
+/// A Lamport timestamp: the replica's clock when a field was last written, with
+/// the replica id breaking ties so that every replica picks the same winner.
+/// Fields written before stamps existed have the default, which loses to all.
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Stamp {
+    pub clock: u64,
+    pub replica: String,
+}
+
+/// What `todo merge` did to the local database.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MergeReport {
+    pub added: usize,
+    pub updated: usize,
+    pub removed: usize,
+}
+
+pub fn new_uuid() -> String {
+    Uuid::new_v4().to_string()
+}
+
+/// `text` in the form uuids are stored in, if it is a uuid at all.
+pub fn parse_uuid(text: &str) -> Option<String> {
+    Uuid::try_parse(text).ok().map(|uuid| uuid.to_string())
+}
+
+/// The stamped fields of `task` as JSON values.
+pub fn fields(task: &Task) -> Result<Map<String, Value>> {
+    match serde_json::to_value(task).map_err(|e| TodoError::format(e.to_string()))? {
+        Value::Object(mut all) => Ok(FIELDS
+            .iter()
+            .filter_map(|&field| all.remove(field).map(|v| (field.to_string(), v)))
+            .collect()),
+        _ => Ok(Map::new()),
+    }
+}
+
+/// The stamped fields whose values differ between `old` and `new`.
+pub fn changed_fields(old: &Task, new: &Task) -> Result<Vec<&'static str>> {
+    let (old, new) = (fields(old)?, fields(new)?);
+    Ok(FIELDS
+        .iter()
+        .copied()
+        .filter(|&field| old.get(field) != new.get(field))
+        .collect())
+}
+
+/// `task` with the given fields replaced.
+pub fn with_fields(task: &Task, fields: Map<String, Value>) -> Result<Task> {
+    let mut value = serde_json::to_value(task).map_err(|e| TodoError::format(e.to_string()))?;
+    if let Value::Object(all) = &mut value {
+        all.extend(fields);
+    }
+    serde_json::from_value(value).map_err(|e| TodoError::format(e.to_string()))
+}
@@ -0,0 +1,232 @@
+use std::collections::BTreeMap;
+
+use chrono::{Datelike, Duration, Local, NaiveDate};
+use serde::Serialize;
+
+use crate::{command::Period, hierarchy::Project};
+
+/// Parameters of a `todo stats` run.
+#[derive(Debug, Clone, Copy)]
+pub struct StatsQuery {
+    /// First day of the created/completed histogram.
+    pub since: NaiveDate,
+    pub today: NaiveDate,
+    /// Bucket size of the histogram: per day when `None`.
+    pub period: Option<Period>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Stats {
+    pub buckets: Vec<Bucket>,
+    pub completed_total: usize,
+    /// Mean time from creation to completion, in hours.
+    pub average_lead_time_hours: Option<f64>,
+    pub open_by_project: Vec<ProjectCount>,
+    pub overdue: usize,
+    pub current_streak: usize,
+    pub longest_streak: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Bucket {
+    pub start: NaiveDate,
+    pub created: usize,
+    pub completed: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProjectCount {
+    pub project: String,
+    pub open: usize,
+}
+
+impl StatsQuery {
+    /// Start of the bucket containing `date`.
+    pub fn bucket_of(&self, date: NaiveDate) -> NaiveDate {
+        match self.period {
+            None => date,
+            Some(Period::Week) => {
+                date - Duration::days(date.weekday().num_days_from_monday() as i64)
+            }
+            Some(Period::Month) => date.with_day(1).expect("day 1 exists"),
+        }
+    }
+
+    /// Zero-filled buckets covering `since..=today`, filled from per-bucket counts.
+    pub fn buckets(
+        &self,
+        created: &BTreeMap<NaiveDate, usize>,
+        completed: &BTreeMap<NaiveDate, usize>,
+    ) -> Vec<Bucket> {
+        let mut buckets = Vec::new();
+        let mut day = self.bucket_of(self.since);
+        while day <= self.today {
+            let start = self.bucket_of(day);
+            if buckets.last().is_none_or(|b: &Bucket| b.start != start) {
+                buckets.push(Bucket {
+                    start,
+                    created: created.get(&start).copied().unwrap_or(0),
+                    completed: completed.get(&start).copied().unwrap_or(0),
+                });
+            }
+            day += Duration::days(1);
+        }
+        buckets
+    }
+}
+
+impl Stats {
+    /// Computes the statistics in memory, for backends without a query engine.
+    pub fn from_projects(projects: &[Project], query: &StatsQuery) -> Stats {
+        let mut created = BTreeMap::new();
+        let mut completed = BTreeMap::new();
+        let mut completion_days = Vec::new();
+        let mut lead_hours = Vec::new();
+        let mut overdue = 0;
+        let mut open_by_project = Vec::new();
+
+        for project in projects {
+            let mut open = 0;
+            for task in &project.tasks {
+                let created_day = task.created_at().with_timezone(&Local).date_naive();
+                if created_day >= query.since {
+                    *created.entry(query.bucket_of(created_day)).or_insert(0) += 1;
+                }
+                match task.completed_at() {
+                    Some(done) => {
+                        let done_day = done.with_timezone(&Local).date_naive();
+                        if done_day >= query.since {
+                            *completed.entry(query.bucket_of(done_day)).or_insert(0) += 1;
+                        }
+                        completion_days.push(done_day);
+                        lead_hours.push((done - *task.created_at()).num_seconds() as f64 / 3600.0);
+                    }
+                    None => {
+                        open += 1;
+                        if task.due_time().is_some_and(|due| due < query.today) {
+                            overdue += 1;
+                        }
+                    }
+                }
+            }
+            open_by_project.push(ProjectCount {
+                project: project.name.clone(),
+                open,
+            });
+        }
+
+        completion_days.sort();
+        completion_days.dedup();
+        let (current_streak, longest_streak) = streaks(&completion_days, query.today);
+
+        Stats {
+            buckets: query.buckets(&created, &completed),
+            completed_total: lead_hours.len(),
+            average_lead_time_hours: (!lead_hours.is_empty())
+                .then(|| lead_hours.iter().sum::<f64>() / lead_hours.len() as f64),
+            open_by_project,
+            overdue,
+            current_streak,
+            longest_streak,
+        }
+    }
+}
+
+/// Current and longest runs of consecutive days with at least one completion,
+/// given the distinct completion days in ascending order. The current streak
+/// survives until the end of today, so it counts a run that ended yesterday.
+pub fn streaks(days: &[NaiveDate], today: NaiveDate) -> (usize, usize) {
+    let mut longest = 0;
+    let mut run = 0;
+    let mut previous: Option<NaiveDate> = None;
+    for &day in days {
+        run = match previous {
+            Some(p) if day - p == Duration::days(1) => run + 1,
+            _ => 1,
+        };
+        longest = longest.max(run);
+        previous = Some(day);
+    }
+
+    let current = match previous {
+        Some(last) if last == today || last == today - Duration::days(1) => run,
+        _ => 0,
+    };
+    (current, longest)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::*;
+    use crate::hierarchy::{Priority, Task};
+
+    fn day(d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2026, 10, d).unwrap()
+    }
+
+    #[test]
+    fn streaks_count_consecutive_days() {
+        let days = [day(1), day(2), day(3), day(10), day(17), day(18)];
+        assert_eq!(streaks(&days, day(19)), (2, 3));
+        assert_eq!(streaks(&days, day(18)), (2, 3));
+        assert_eq!(streaks(&days, day(20)), (0, 3));
+        assert_eq!(streaks(&[], day(19)), (0, 0));
+    }
+
+    #[test]
+    fn weekly_buckets_start_on_monday_and_are_zero_filled() {
+        let query = StatsQuery {
+            since: day(7),
+            today: day(19),
+            period: Some(Period::Week),
+        };
+        let created = BTreeMap::from([(day(12), 3)]);
+        let buckets = query.buckets(&created, &BTreeMap::new());
+        let starts: Vec<NaiveDate> = buckets.iter().map(|b| b.start).collect();
+        assert_eq!(starts, [day(5), day(12), day(19)]);
+        let counts: Vec<usize> = buckets.iter().map(|b| b.created).collect();
+        assert_eq!(counts, [0, 3, 0]);
+    }
+
+    #[test]
+    fn open_and_overdue_tasks_are_counted_per_project() {
+        let task = |due: Option<NaiveDate>, done: bool| {
+            Task::new(
+                0,
+                0,
+                String::from("Task"),
+                String::new(),
+                Priority::None,
+                Utc::now(),
+                due,
+                done.then(Utc::now),
+            )
+        };
+        let project = Project {
+            id: 0,
+            uuid: String::new(),
+            name: String::from("Home"),
+            parent_id: 0,
+            tasks: vec![
+                task(Some(day(1)), false),
+                task(None, false),
+                task(Some(day(1)), true),
+            ],
+        };
+        let today = Local::now().date_naive();
+        let query = StatsQuery {
+            since: today,
+            today,
+            period: None,
+        };
+        let stats = Stats::from_projects(&[project], &query);
+        assert_eq!(stats.open_by_project[0].open, 2);
+        assert_eq!(stats.overdue, 1);
+        assert_eq!(stats.completed_total, 1);
+        assert_eq!(stats.current_streak, 1);
+        assert_eq!(stats.buckets.len(), 1);
+        assert_eq!(stats.buckets[0].created, 3);
+    }
+}
@@ -0,0 +1,449 @@
+use std::{fmt::Debug, path::Path};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{Result, TodoError},
+    hierarchy::{Project, Task},
+    history::{Change, diff},
+    replica::MergeReport,
+    stats::{Stats, StatsQuery},
+};
+
+/// A task removed with `todo remove`, kept until the trash is emptied.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrashedTask {
+    pub trashed_at: DateTime<Utc>,
+    pub task: Task,
+}
+
+/// A project removed with `todo remove-project`, holding the tasks removed with it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrashedProject {
+    pub trashed_at: DateTime<Utc>,
+    pub project: Project,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Trash {
+    pub projects: Vec<TrashedProject>,
+    pub tasks: Vec<TrashedTask>,
+}
+
+/// Persistence backend used by `AppState`.
+///
+/// Only the bulk operations are required. The finer-grained operations default
+/// to a load-modify-save round trip, which backends that can do better (such as
+/// the SQLite `Database`) override. The task operations also append to the
+/// history, which is never rewritten.
+///
+/// Several processes may share the same storage. Task ids come from
+/// `allocate_task_id` rather than a copy of the counter, and `update_task`
+/// refuses to overwrite a task that changed since it was loaded.
+pub trait Storage: Debug {
+    fn load_projects(&self) -> Result<Vec<Project>>;
+    fn save_projects(&self, projects: &[Project]) -> Result<()>;
+    fn load_next_task_id(&self) -> Result<usize>;
+    fn save_next_task_id(&self, next_task_id: usize) -> Result<()>;
+    /// Tasks moved out of their projects with `todo archive`.
+    fn load_archive(&self) -> Result<Vec<Task>>;
+    fn save_archive(&self, tasks: &[Task]) -> Result<()>;
+    fn load_trash(&self) -> Result<Trash>;
+    fn save_trash(&self, trash: &Trash) -> Result<()>;
+    /// Changes in the order they were made, for the task with uuid `task` or all,
+    /// from `since` on.
+    fn load_history(&self, task: Option<&str>, since: Option<DateTime<Utc>>)
+    -> Result<Vec<Change>>;
+    fn append_history(&self, changes: &[Change]) -> Result<()>;
+    /// Stored ids of the tasks in the working set, in number order.
+    fn load_working_set(&self) -> Result<Vec<usize>>;
+    fn save_working_set(&self, task_ids: &[usize]) -> Result<()>;
+    /// Runs `apply` so that the changes it makes through this storage are all
+    /// made or, if it fails, none are.
+    fn transaction(&self, apply: &mut dyn FnMut() -> Result<()>) -> Result<()>;
+
+    /// Folds another replica of the data, at `path`, into this one.
+    fn merge_from(&self, path: &Path) -> Result<MergeReport> {
+        Err(TodoError::invalid(format!(
+            "cannot merge {}: merging needs the SQLite backend",
+            path.display()
+        )))
+    }
+
+    /// Gives `task_id` the next working-set number and returns it.
+    fn extend_working_set(&self, task_id: usize) -> Result<usize> {
+        let mut task_ids = self.load_working_set()?;
+        task_ids.push(task_id);
+        self.save_working_set(&task_ids)?;
+        Ok(task_ids.len())
+    }
+
+    /// Reserves the next task id.
+    fn allocate_task_id(&self) -> Result<usize> {
+        let id = self.load_next_task_id()?;
+        self.save_next_task_id(id + 1)?;
+        Ok(id)
+    }
+
+    /// Reserves the next project id.
+    fn allocate_project_id(&self) -> Result<usize> {
+        Ok(next_project_id(&self.load_projects()?, &self.load_trash()?))
+    }
+
+    fn insert_project(&self, project: &Project) -> Result<()> {
+        let mut projects = self.load_projects()?;
+        projects.push(project.clone());
+        self.save_projects(&projects)
+    }
+
+    /// Stores a new name, parent or uuid for a project.
+    fn update_project(&self, project: &Project) -> Result<()> {
+        let mut projects = self.load_projects()?;
+        store_project(&mut projects, project)?;
+        self.save_projects(&projects)
+    }
+
+    /// Removes a project together with its tasks.
+    fn delete_project(&self, project_id: usize) -> Result<()> {
+        let mut projects = self.load_projects()?;
+        projects.retain(|p| p.id != project_id);
+        self.save_projects(&projects)
+    }
+
+    fn insert_task(&self, task: &Task) -> Result<()> {
+        let mut projects = self.load_projects()?;
+        let project = projects
+            .iter_mut()
+            .find(|p| p.id == task.project_id())
+            .ok_or_else(|| TodoError::not_found("project not found"))?;
+        project.tasks.push(task.clone());
+        self.save_projects(&projects)?;
+        self.append_history(&[Change::created(task, Utc::now())])
+    }
+
+    /// Stores `task` and bumps its version, unless the stored copy has moved on
+    /// since `task` was loaded.
+    fn update_task(&self, task: &mut Task) -> Result<()> {
+        let mut projects = self.load_projects()?;
+        let version = task.version() + 1;
+        let changes = store_task(&mut projects, task, version)?;
+        self.save_projects(&projects)?;
+        task.set_version(version);
+        self.append_history(&changes)
+    }
+
+    fn delete_task(&self, task_id: usize) -> Result<()> {
+        let mut projects = self.load_projects()?;
+        for project in projects.iter_mut() {
+            project.tasks.retain(|t| t.id() != task_id);
+        }
+        self.save_projects(&projects)
+    }
+
+    /// Moves tasks from their projects into the archive.
+    fn archive_tasks(&self, task_ids: &[usize]) -> Result<()> {
+        let mut projects = self.load_projects()?;
+        let mut archive = self.load_archive()?;
+        let now = Utc::now();
+        let mut changes = Vec::new();
+        for project in projects.iter_mut() {
+            let (archived, kept): (Vec<Task>, _) = project
+                .tasks
+                .drain(..)
+                .partition(|t| task_ids.contains(&t.id()));
+            project.tasks = kept;
+            changes.extend(
+                archived
+                    .iter()
+                    .map(|t| Change::state(t, now, "archived", true)),
+            );
+            archive.extend(archived);
+        }
+        self.save_archive(&archive)?;
+        self.save_projects(&projects)?;
+        self.append_history(&changes)
+    }
+
+    /// Permanently deletes archived tasks.
+    fn purge_archived(&self, task_ids: &[usize]) -> Result<()> {
+        let mut archive = self.load_archive()?;
+        let changes = purge_from_archive(&mut archive, task_ids, Utc::now());
+        self.save_archive(&archive)?;
+        self.append_history(&changes)
+    }
+
+    /// Moves a task from its project into the trash.
+    fn trash_task(&self, task_id: usize, at: DateTime<Utc>) -> Result<()> {
+        let mut projects = self.load_projects()?;
+        let mut trash = self.load_trash()?;
+        let changes = move_task_to_trash(&mut projects, &mut trash, task_id, at)?;
+        self.save_trash(&trash)?;
+        self.save_projects(&projects)?;
+        self.append_history(&changes)
+    }
+
+    /// Moves a project into the trash together with its tasks.
+    fn trash_project(&self, project_id: usize, at: DateTime<Utc>) -> Result<()> {
+        let mut projects = self.load_projects()?;
+        let mut trash = self.load_trash()?;
+        let changes = move_project_to_trash(&mut projects, &mut trash, project_id, at)?;
+        self.save_trash(&trash)?;
+        self.save_projects(&projects)?;
+        self.append_history(&changes)
+    }
+
+    /// Puts a trashed task back into its project, which must not be trashed.
+    fn restore_task(&self, task_id: usize) -> Result<()> {
+        let mut projects = self.load_projects()?;
+        let mut trash = self.load_trash()?;
+        let changes = restore_task_from_trash(&mut projects, &mut trash, task_id, Utc::now())?;
+        self.save_projects(&projects)?;
+        self.save_trash(&trash)?;
+        self.append_history(&changes)
+    }
+
+    /// Puts a trashed project back together with the tasks trashed with it.
+    fn restore_project(&self, project_id: usize) -> Result<()> {
+        let mut projects = self.load_projects()?;
+        let mut trash = self.load_trash()?;
+        let changes =
+            restore_project_from_trash(&mut projects, &mut trash, project_id, Utc::now())?;
+        self.save_projects(&projects)?;
+        self.save_trash(&trash)?;
+        self.append_history(&changes)
+    }
+
+    /// Permanently deletes what was trashed before `before`. The archived tasks
+    /// of a deleted project go with it.
+    fn empty_trash(&self, before: DateTime<Utc>) -> Result<()> {
+        let mut trash = self.load_trash()?;
+        let mut archive = self.load_archive()?;
+        let changes = expire_trash(&mut trash, &mut archive, before, Utc::now());
+        self.save_trash(&trash)?;
+        self.save_archive(&archive)?;
+        self.append_history(&changes)
+    }
+
+    /// Productivity statistics for `todo stats`. Archived tasks still count
+    /// towards the history.
+    fn stats(&self, query: &StatsQuery) -> Result<Stats> {
+        let mut projects = self.load_projects()?;
+        for task in self.load_archive()? {
+            if let Some(project) = projects.iter_mut().find(|p| p.id == task.project_id()) {
+                project.tasks.push(task);
+            }
+        }
+        Ok(Stats::from_projects(&projects, query))
+    }
+}
+
+/// Replaces the copy of `task` in `projects` with `task` at `version`, moving
+/// it if its project changed, and returns the changes for the history. Fails
+/// if the copy isn't at the version `task` was loaded at.
+pub fn store_task(projects: &mut [Project], task: &Task, version: u64) -> Result<Vec<Change>> {
+    let (p, i) = projects
+        .iter()
+        .enumerate()
+        .find_map(|(p, project)| {
+            let i = project.tasks.iter().position(|t| t.id() == task.id())?;
+            Some((p, i))
+        })
+        .ok_or_else(|| TodoError::not_found("task not found"))?;
+    let stored = &projects[p].tasks[i];
+    if stored.version() != task.version() {
+        return Err(stale_task(task.id()));
+    }
+    let changes = diff(stored, task, Utc::now());
+
+    let mut updated = task.clone();
+    updated.set_version(version);
+    if projects[p].id == task.project_id() {
+        projects[p].tasks[i] = updated;
+    } else {
+        let target = projects
+            .iter()
+            .position(|project| project.id == task.project_id())
+            .ok_or_else(|| TodoError::not_found("project not found"))?;
+        projects[p].tasks.remove(i);
+        projects[target].tasks.push(updated);
+    }
+    Ok(changes)
+}
+
+/// Stores a new name, parent or uuid for `project` in `projects`.
+pub fn store_project(projects: &mut [Project], project: &Project) -> Result<()> {
+    let stored = projects
+        .iter_mut()
+        .find(|p| p.id == project.id)
+        .ok_or_else(|| TodoError::not_found("project not found"))?;
+    stored.name = project.name.clone();
+    stored.parent_id = project.parent_id;
+    stored.uuid = project.uuid.clone();
+    Ok(())
+}
+
+/// Moves a task from its project in `projects` into `trash`.
+pub fn move_task_to_trash(
+    projects: &mut [Project],
+    trash: &mut Trash,
+    task_id: usize,
+    at: DateTime<Utc>,
+) -> Result<Vec<Change>> {
+    let project = projects
+        .iter_mut()
+        .find(|p| p.tasks.iter().any(|t| t.id() == task_id))
+        .ok_or_else(|| TodoError::not_found("task not found"))?;
+    let index = project
+        .tasks
+        .iter()
+        .position(|t| t.id() == task_id)
+        .expect("found above");
+    let task = project.tasks.remove(index);
+    let change = Change::state(&task, at, "trashed", true);
+    trash.tasks.push(TrashedTask {
+        trashed_at: at,
+        task,
+    });
+    Ok(vec![change])
+}
+
+/// Moves a project with its tasks from `projects` into `trash`.
+pub fn move_project_to_trash(
+    projects: &mut Vec<Project>,
+    trash: &mut Trash,
+    project_id: usize,
+    at: DateTime<Utc>,
+) -> Result<Vec<Change>> {
+    let index = projects
+        .iter()
+        .position(|p| p.id == project_id)
+        .ok_or_else(|| TodoError::not_found("project not found"))?;
+    let project = projects.remove(index);
+    let changes = project
+        .tasks
+        .iter()
+        .map(|t| Change::state(t, at, "trashed", true))
+        .collect();
+    trash.projects.push(TrashedProject {
+        trashed_at: at,
+        project,
+    });
+    Ok(changes)
+}
+
+/// Moves a trashed task back into its project in `projects`.
+pub fn restore_task_from_trash(
+    projects: &mut [Project],
+    trash: &mut Trash,
+    task_id: usize,
+    now: DateTime<Utc>,
+) -> Result<Vec<Change>> {
+    let index = trash
+        .tasks
+        .iter()
+        .position(|t| t.task.id() == task_id)
+        .ok_or_else(|| TodoError::not_found("task is not in the trash"))?;
+    let project = projects
+        .iter_mut()
+        .find(|p| p.id == trash.tasks[index].task.project_id())
+        .ok_or_else(|| TodoError::not_found("project not found"))?;
+    let task = trash.tasks.remove(index).task;
+    let change = Change::state(&task, now, "trashed", false);
+    project.tasks.push(task);
+    Ok(vec![change])
+}
+
+/// Moves a trashed project back into `projects` together with its tasks.
+pub fn restore_project_from_trash(
+    projects: &mut Vec<Project>,
+    trash: &mut Trash,
+    project_id: usize,
+    now: DateTime<Utc>,
+) -> Result<Vec<Change>> {
+    let index = trash
+        .projects
+        .iter()
+        .position(|p| p.project.id == project_id)
+        .ok_or_else(|| TodoError::not_found("project is not in the trash"))?;
+    let project = trash.projects.remove(index).project;
+    let changes = project
+        .tasks
+        .iter()
+        .map(|t| Change::state(t, now, "trashed", false))
+        .collect();
+    projects.push(project);
+    Ok(changes)
+}
+
+/// Drops what was trashed before `before` from `trash`, along with the archived
+/// tasks of the projects dropped.
+pub fn expire_trash(
+    trash: &mut Trash,
+    archive: &mut Vec<Task>,
+    before: DateTime<Utc>,
+    now: DateTime<Utc>,
+) -> Vec<Change> {
+    let expired: Vec<usize> = trash
+        .projects
+        .iter()
+        .filter(|p| p.trashed_at < before)
+        .map(|p| p.project.id)
+        .collect();
+    let deleted = |t: &Task| Change::state(t, now, "deleted", true);
+    let mut changes: Vec<Change> = trash
+        .tasks
+        .iter()
+        .filter(|t| t.trashed_at < before)
+        .map(|t| deleted(&t.task))
+        .collect();
+    for trashed in trash.projects.iter().filter(|p| p.trashed_at < before) {
+        changes.extend(trashed.project.tasks.iter().map(deleted));
+    }
+    trash.projects.retain(|p| p.trashed_at >= before);
+    trash.tasks.retain(|t| t.trashed_at >= before);
+
+    changes.extend(
+        archive
+            .iter()
+            .filter(|t| expired.contains(&t.project_id()))
+            .map(deleted),
+    );
+    archive.retain(|t| !expired.contains(&t.project_id()));
+    changes
+}
+
+/// Drops `task_ids` from `archive`.
+pub fn purge_from_archive(
+    archive: &mut Vec<Task>,
+    task_ids: &[usize],
+    now: DateTime<Utc>,
+) -> Vec<Change> {
+    let changes = archive
+        .iter()
+        .filter(|t| task_ids.contains(&t.id()))
+        .map(|t| Change::state(t, now, "deleted", true))
+        .collect();
+    archive.retain(|t| !task_ids.contains(&t.id()));
+    changes
+}
+
+/// The id after the highest one in use. Trashed projects keep theirs, so that
+/// they can be restored.
+pub fn next_project_id(projects: &[Project], trash: &Trash) -> usize {
+    projects
+        .iter()
+        .chain(trash.projects.iter().map(|p| &p.project))
+        .map(|p| p.id)
+        .max()
+        .unwrap_or(0)
+        + 1
+}
+
+/// Error for an update based on a copy of the task that another process has
+/// changed in the meantime.
+pub fn stale_task(task_id: usize) -> TodoError {
+    TodoError::conflict(format!(
+        "task {task_id} was changed by another todo process; run the command again"
+    ))
+}
@@ -0,0 +1,615 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs, io,
+    path::{Path, PathBuf},
+    process,
+};
+
+use serde_json::{Map, Value, json};
+
+use crate::{
+    command::Side,
+    error::{Result, TodoError},
+    hierarchy::{Project, Task},
+    replica::parse_uuid,
+    storage::Trash,
+};
+
+/// The synced data as file contents keyed by their path in the repository, e.g.
+/// `tasks/<uuid>.json`. Every value is a JSON object.
+///
+/// Files are named by uuid and refer to each other by uuid, so tasks and
+/// projects created on different replicas never share a file. Short ids are
+/// local to each replica and stay out.
+pub type Snapshot = BTreeMap<String, Value>;
+
+pub fn project_path(uuid: &str) -> String {
+    format!("projects/{uuid}.json")
+}
+
+pub fn task_path(uuid: &str) -> String {
+    format!("tasks/{uuid}.json")
+}
+
+/// The uuid in a `projects/` or `tasks/` path, whichever `dir` names.
+pub fn path_uuid<'a>(path: &'a str, dir: &str) -> Option<&'a str> {
+    let uuid = path
+        .strip_prefix(dir)?
+        .strip_prefix('/')?
+        .strip_suffix(".json")?;
+    parse_uuid(uuid).map(|_| uuid)
+}
+
+/// Where `sync` keeps its working copy unless `sync.dir` says otherwise.
+pub fn default_dir() -> PathBuf {
+    let mut path = dirs::home_dir().unwrap_or_else(|| PathBuf::from("."));
+    path.push(".todo-sync");
+    path
+}
+
+/// Active projects and their tasks, plus archived tasks marked as such. The
+/// trash is left out, so removing something reads as a deletion elsewhere; it is
+/// only looked at to name the trashed tasks that others depend on.
+pub fn snapshot(projects: &[Project], archive: &[Task], trash: &Trash) -> Result<Snapshot> {
+    let project_uuids: BTreeMap<usize, &str> =
+        projects.iter().map(|p| (p.id, p.uuid.as_str())).collect();
+    let task_uuids: BTreeMap<usize, &str> = projects
+        .iter()
+        .flat_map(|p| p.tasks.iter())
+        .chain(archive)
+        .chain(trash.tasks.iter().map(|t| &t.task))
+        .chain(trash.projects.iter().flat_map(|p| p.project.tasks.iter()))
+        .map(|t| (t.id(), t.uuid()))
+        .collect();
+
+    let mut files = Snapshot::new();
+    for project in projects {
+        files.insert(
+            project_path(&project.uuid),
+            json!({
+                "name": project.name,
+                "parent": project_uuids.get(&project.parent_id),
+            }),
+        );
+    }
+    let tasks = projects
+        .iter()
+        .flat_map(|p| p.tasks.iter().map(|t| (t, false)))
+        .chain(archive.iter().map(|t| (t, true)))
+        .filter(|(t, _)| project_uuids.contains_key(&t.project_id()));
+    for (task, archived) in tasks {
+        let mut value = serde_json::to_value(task).map_err(|e| TodoError::format(e.to_string()))?;
+        if let Value::Object(fields) = &mut value {
+            // The version only guards against other local processes.
+            for field in ["id", "version", "project_id"] {
+                fields.remove(field);
+            }
+            fields.insert(
+                String::from("project"),
+                json!(project_uuids.get(&task.project_id())),
+            );
+            let depends_on: Vec<&str> = task
+                .depends_on()
+                .iter()
+                .filter_map(|id| task_uuids.get(id).copied())
+                .collect();
+            fields.insert(String::from("depends_on"), json!(depends_on));
+            if archived {
+                fields.insert(String::from("archived"), Value::Bool(true));
+            }
+        }
+        files.insert(task_path(task.uuid()), value);
+    }
+    Ok(files)
+}
+
+/// A task file read back. The task has no id, project or dependencies yet: the
+/// file names those by uuid, and mapping them to local ids is up to the caller.
+#[derive(Debug)]
+pub struct TaskFile {
+    pub task: Task,
+    pub project: String,
+    pub depends_on: Vec<String>,
+    pub archived: bool,
+}
+
+pub fn task_from_value(path: &str, value: &Value) -> Result<TaskFile> {
+    let malformed = |e: serde_json::Error| TodoError::format(format!("{path}: {e}"));
+    let mut value = value.clone();
+    let Value::Object(fields) = &mut value else {
+        return Err(TodoError::format(format!("{path}: not an object")));
+    };
+    let archived = fields.remove("archived") == Some(Value::Bool(true));
+    let project =
+        serde_json::from_value(fields.remove("project").unwrap_or_default()).map_err(malformed)?;
+    let depends_on = serde_json::from_value(fields.remove("depends_on").unwrap_or(json!([])))
+        .map_err(malformed)?;
+    fields.insert(String::from("id"), json!(0));
+    fields.insert(String::from("project_id"), json!(0));
+    Ok(TaskFile {
+        task: serde_json::from_value(value).map_err(malformed)?,
+        project,
+        depends_on,
+        archived,
+    })
+}
+
+/// A project file read back, with its parent still named by uuid.
+#[derive(Debug)]
+pub struct ProjectFile {
+    pub name: String,
+    pub parent: Option<String>,
+}
+
+pub fn project_from_value(path: &str, value: &Value) -> Result<ProjectFile> {
+    let field = |name: &str| value.get(name).cloned().unwrap_or(Value::Null);
+    let malformed = |e: serde_json::Error| TodoError::format(format!("{path}: {e}"));
+    Ok(ProjectFile {
+        name: serde_json::from_value(field("name")).map_err(malformed)?,
+        parent: serde_json::from_value(field("parent")).map_err(malformed)?,
+    })
+}
+
+/// A change made on both sides that `merge` can't reconcile. `field` is `None`
+/// when the whole file is in question, e.g. deleted here and edited there.
+#[derive(Debug, Clone)]
+pub struct Conflict {
+    pub path: String,
+    pub field: Option<String>,
+    pub local: Option<Value>,
+    pub remote: Option<Value>,
+}
+
+impl Conflict {
+    pub fn describe(&self) -> String {
+        let show = |value: &Option<Value>| match value {
+            Some(value) => value.to_string(),
+            None => String::from("deleted"),
+        };
+        match &self.field {
+            Some(field) => format!(
+                "{} {field}: {} here, {} there",
+                self.path,
+                show(&self.local),
+                show(&self.remote)
+            ),
+            None => format!(
+                "{}: {} here, {} there",
+                self.path,
+                if self.local.is_some() {
+                    "changed"
+                } else {
+                    "deleted"
+                },
+                if self.remote.is_some() {
+                    "changed"
+                } else {
+                    "deleted"
+                },
+            ),
+        }
+    }
+}
+
+/// Three-way merge of `local` and `remote` against their common `base`, file by
+/// file and then field by field. A change on one side wins over no change on the
+/// other; different changes on both sides are conflicts, settled by `prefer` when
+/// given and otherwise returned with the local value kept in the result.
+///
+/// Projects of the same name made on different replicas, such as each one's
+/// `Home`, become one: the file with the lowest uuid stays and the others'
+/// tasks and subprojects move to it.
+pub fn merge(
+    base: &Snapshot,
+    local: &Snapshot,
+    remote: &Snapshot,
+    prefer: Option<Side>,
+) -> (Snapshot, Vec<Conflict>) {
+    let mut merged = Snapshot::new();
+    let mut conflicts = Vec::new();
+    let paths: BTreeSet<&String> = base
+        .keys()
+        .chain(local.keys())
+        .chain(remote.keys())
+        .collect();
+
+    for path in paths {
+        let (b, l, r) = (base.get(path), local.get(path), remote.get(path));
+        let value = match three_way(b, l, r) {
+            Some(value) => value.cloned(),
+            None => match (l, r) {
+                (Some(Value::Object(l)), Some(Value::Object(r))) => {
+                    let empty = Map::new();
+                    let b = match b {
+                        Some(Value::Object(b)) => b,
+                        _ => &empty,
+                    };
+                    Some(Value::Object(merge_fields(
+                        path,
+                        b,
+                        l,
+                        r,
+                        prefer,
+                        &mut conflicts,
+                    )))
+                }
+                _ => resolve(path, None, l, r, prefer, &mut conflicts).cloned(),
+            },
+        };
+        if let Some(value) = value {
+            merged.insert(path.clone(), value);
+        }
+    }
+    merge_projects_by_name(&mut merged);
+    (merged, conflicts)
+}
+
+fn merge_projects_by_name(files: &mut Snapshot) {
+    let mut kept: BTreeMap<&str, &str> = BTreeMap::new();
+    let mut renamed: BTreeMap<String, String> = BTreeMap::new();
+    // Paths are in uuid order, so the first project of a name is the one kept.
+    for (path, value) in files.iter() {
+        let name = value.get("name").and_then(Value::as_str);
+        let (Some(uuid), Some(name)) = (path_uuid(path, "projects"), name) else {
+            continue;
+        };
+        match kept.get(name) {
+            Some(&first) => {
+                renamed.insert(uuid.to_string(), first.to_string());
+            }
+            None => {
+                kept.insert(name, uuid);
+            }
+        }
+    }
+    if renamed.is_empty() {
+        return;
+    }
+
+    files.retain(|path, _| path_uuid(path, "projects").is_none_or(|u| !renamed.contains_key(u)));
+    for value in files.values_mut() {
+        for field in ["project", "parent"] {
+            let Some(Value::String(uuid)) = value.get_mut(field) else {
+                continue;
+            };
+            if let Some(new) = renamed.get(uuid.as_str()) {
+                *uuid = new.clone();
+            }
+        }
+    }
+}
+
+fn merge_fields(
+    path: &str,
+    base: &Map<String, Value>,
+    local: &Map<String, Value>,
+    remote: &Map<String, Value>,
+    prefer: Option<Side>,
+    conflicts: &mut Vec<Conflict>,
+) -> Map<String, Value> {
+    let mut merged = Map::new();
+    let fields: BTreeSet<&String> = base
+        .keys()
+        .chain(local.keys())
+        .chain(remote.keys())
+        .collect();
+    for field in fields {
+        let (b, l, r) = (base.get(field), local.get(field), remote.get(field));
+        let value = match three_way(b, l, r) {
+            Some(value) => value,
+            None => resolve(path, Some(field), l, r, prefer, conflicts),
+        };
+        if let Some(value) = value {
+            merged.insert(field.clone(), value.clone());
+        }
+    }
+    merged
+}
+
+/// The merged value when at most one side changed it, `None` when both did.
+fn three_way<'a>(
+    base: Option<&'a Value>,
+    local: Option<&'a Value>,
+    remote: Option<&'a Value>,
+) -> Option<Option<&'a Value>> {
+    if local == remote || local == base {
+        Some(remote)
+    } else if remote == base {
+        Some(local)
+    } else {
+        None
+    }
+}
+
+fn resolve<'a>(
+    path: &str,
+    field: Option<&String>,
+    local: Option<&'a Value>,
+    remote: Option<&'a Value>,
+    prefer: Option<Side>,
+    conflicts: &mut Vec<Conflict>,
+) -> Option<&'a Value> {
+    match prefer {
+        Some(Side::Ours) => local,
+        Some(Side::Theirs) => remote,
+        None => {
+            conflicts.push(Conflict {
+                path: path.to_string(),
+                field: field.cloned(),
+                local: local.cloned(),
+                remote: remote.cloned(),
+            });
+            local
+        }
+    }
+}
+
+/// A working copy of the sync repository, driven through the `git` command.
+#[derive(Debug)]
+pub struct Repo {
+    dir: PathBuf,
+}
+
+impl Repo {
+    /// Opens the working copy in `dir`, creating it on first use, and points
+    /// `origin` at `remote` when one is given.
+    pub fn open(dir: &Path, remote: Option<&str>, branch: &str) -> Result<Self> {
+        fs::create_dir_all(dir)?;
+        let repo = Repo {
+            dir: dir.to_path_buf(),
+        };
+        if !dir.join(".git").exists() {
+            repo.git(&["init", "-q", "-b", branch])?;
+        }
+        if let Some(remote) = remote {
+            // `--` keeps a remote starting with `-` from being read as an option.
+            match repo.try_git(&["remote", "get-url", "origin"]) {
+                Some(url) if url == remote => {}
+                Some(_) => {
+                    repo.git(&["remote", "set-url", "--", "origin", remote])?;
+                }
+                None => {
+                    repo.git(&["remote", "add", "--", "origin", remote])?;
+                }
+            }
+        }
+        Ok(repo)
+    }
+
+    fn git(&self, args: &[&str]) -> Result<String> {
+        let output = process::Command::new("git")
+            .arg("-C")
+            .arg(&self.dir)
+            .args(args)
+            .output()?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(TodoError::Io(io::Error::other(format!(
+                "git {} failed: {}",
+                args.join(" "),
+                stderr.trim()
+            ))));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .trim_end()
+            .to_string())
+    }
+
+    /// Output of a git command that is allowed to fail, e.g. a lookup.
+    fn try_git(&self, args: &[&str]) -> Option<String> {
+        self.git(args).ok()
+    }
+
+    /// The last synced commit, if there is one yet.
+    pub fn head(&self) -> Option<String> {
+        self.try_git(&["rev-parse", "--verify", "-q", "HEAD"])
+    }
+
+    /// Fetches `branch` from `origin`, returning its commit unless it doesn't
+    /// exist yet.
+    pub fn fetch(&self, branch: &str) -> Result<Option<String>> {
+        self.git(&["fetch", "-q", "origin"])?;
+        Ok(self.try_git(&[
+            "rev-parse",
+            "--verify",
+            "-q",
+            &format!("refs/remotes/origin/{branch}"),
+        ]))
+    }
+
+    pub fn is_ancestor(&self, ancestor: &str, commit: &str) -> bool {
+        self.git(&["merge-base", "--is-ancestor", ancestor, commit])
+            .is_ok()
+    }
+
+    pub fn merge_base(&self, a: &str, b: &str) -> Option<String> {
+        self.try_git(&["merge-base", a, b])
+    }
+
+    /// The project and task files in `commit`.
+    pub fn read(&self, commit: &str) -> Result<Snapshot> {
+        let mut files = Snapshot::new();
+        let listing = self.git(&["ls-tree", "-r", "--name-only", commit])?;
+        for path in listing.lines() {
+            // Files of other kinds, such as those named by id before uuids, are
+            // left out and so go away with the next commit.
+            if path_uuid(path, "projects").is_none() && path_uuid(path, "tasks").is_none() {
+                continue;
+            }
+            let text = self.git(&["show", &format!("{commit}:{path}")])?;
+            let value = serde_json::from_str(&text)
+                .map_err(|e| TodoError::format(format!("{path} in {commit}: {e}")))?;
+            files.insert(path.to_string(), value);
+        }
+        Ok(files)
+    }
+
+    /// Replaces the project and task files in the working copy with `files`.
+    pub fn write(&self, files: &Snapshot) -> Result<()> {
+        for dir in ["projects", "tasks"] {
+            match fs::remove_dir_all(self.dir.join(dir)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+                _ => fs::create_dir_all(self.dir.join(dir))?,
+            }
+        }
+        for (path, value) in files {
+            let mut text = serde_json::to_string_pretty(value)
+                .map_err(|e| TodoError::format(e.to_string()))?;
+            text.push('\n');
+            fs::write(self.dir.join(path), text)?;
+        }
+        Ok(())
+    }
+
+    /// Commits the working copy on top of `parents` and moves the branch there.
+    /// With a single parent whose tree already matches, nothing is committed and
+    /// that parent is returned.
+    pub fn commit(&self, parents: &[String], message: &str) -> Result<String> {
+        self.git(&["add", "-A"])?;
+        let tree = self.git(&["write-tree"])?;
+        if let [parent] = parents {
+            if self.git(&["rev-parse", &format!("{parent}^{{tree}}")])? == tree {
+                self.git(&["update-ref", "HEAD", parent])?;
+                return Ok(parent.clone());
+            }
+        }
+
+        let mut args = Vec::new();
+        // Don't make a missing git identity stop the sync.
+        if self.try_git(&["config", "user.email"]).is_none() {
+            args.extend(["-c", "user.name=todo", "-c", "user.email=todo@localhost"]);
+        }
+        args.extend(["commit-tree", &tree, "-m", message]);
+        for parent in parents {
+            args.extend(["-p", parent]);
+        }
+        let commit = self.git(&args)?;
+        self.git(&["update-ref", "HEAD", &commit])?;
+        Ok(commit)
+    }
+
+    pub fn push(&self, branch: &str) -> Result<()> {
+        self.git(&["push", "-q", "origin", &format!("HEAD:refs/heads/{branch}")])
+            .map(|_| ())
+            .map_err(|e| {
+                TodoError::conflict(format!(
+                    "{e}\nthe remote changed during the sync; run todo sync again"
+                ))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOME: &str = "00000000-0000-4000-8000-000000000001";
+    const OTHER_HOME: &str = "00000000-0000-4000-8000-000000000002";
+    const TASK: &str = "00000000-0000-4000-8000-00000000000a";
+    const OTHER_TASK: &str = "00000000-0000-4000-8000-00000000000b";
+
+    fn files(entries: &[(String, Value)]) -> Snapshot {
+        entries.iter().cloned().collect()
+    }
+
+    #[test]
+    fn three_way_takes_the_side_that_changed() {
+        let (a, b, c) = (json!(1), json!(2), json!(3));
+        assert_eq!(three_way(Some(&a), Some(&a), Some(&a)), Some(Some(&a)));
+        assert_eq!(three_way(Some(&a), Some(&b), Some(&a)), Some(Some(&b)));
+        assert_eq!(three_way(Some(&a), Some(&a), Some(&b)), Some(Some(&b)));
+        assert_eq!(three_way(Some(&a), Some(&b), Some(&b)), Some(Some(&b)));
+        assert_eq!(three_way(Some(&a), None, Some(&a)), Some(None));
+        assert_eq!(three_way(None, None, Some(&a)), Some(Some(&a)));
+        assert_eq!(three_way(Some(&a), Some(&b), Some(&c)), None);
+        assert_eq!(three_way(Some(&a), None, Some(&b)), None);
+    }
+
+    #[test]
+    fn resolve_prefers_the_side_asked_for() {
+        let (local, remote) = (json!("here"), json!("there"));
+        let mut conflicts = Vec::new();
+        let ours = resolve(
+            "p",
+            None,
+            Some(&local),
+            Some(&remote),
+            Some(Side::Ours),
+            &mut conflicts,
+        );
+        let theirs = resolve(
+            "p",
+            None,
+            Some(&local),
+            Some(&remote),
+            Some(Side::Theirs),
+            &mut conflicts,
+        );
+        assert_eq!(ours, Some(&local));
+        assert_eq!(theirs, Some(&remote));
+        assert!(conflicts.is_empty());
+    }
+
+    #[test]
+    fn resolve_without_a_preference_keeps_local_and_reports() {
+        let local = json!("here");
+        let mut conflicts = Vec::new();
+        let field = String::from("name");
+        let value = resolve("p", Some(&field), Some(&local), None, None, &mut conflicts);
+        assert_eq!(value, Some(&local));
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].field.as_deref(), Some("name"));
+        assert_eq!(conflicts[0].remote, None);
+    }
+
+    #[test]
+    fn tasks_made_on_both_sides_are_both_kept() {
+        let home = (project_path(HOME), json!({"name": "Home", "parent": HOME}));
+        let base = files(std::slice::from_ref(&home));
+        let local = files(&[
+            home.clone(),
+            (task_path(TASK), json!({"name": "Here", "project": HOME})),
+        ]);
+        let remote = files(&[
+            home,
+            (
+                task_path(OTHER_TASK),
+                json!({"name": "There", "project": HOME}),
+            ),
+        ]);
+        let (merged, conflicts) = merge(&base, &local, &remote, None);
+        assert!(conflicts.is_empty());
+        assert!(merged.contains_key(&task_path(TASK)));
+        assert!(merged.contains_key(&task_path(OTHER_TASK)));
+    }
+
+    #[test]
+    fn projects_of_the_same_name_become_one() {
+        let local = files(&[
+            (project_path(HOME), json!({"name": "Home", "parent": HOME})),
+            (task_path(TASK), json!({"name": "Here", "project": HOME})),
+        ]);
+        let remote = files(&[
+            (
+                project_path(OTHER_HOME),
+                json!({"name": "Home", "parent": OTHER_HOME}),
+            ),
+            (
+                task_path(OTHER_TASK),
+                json!({"name": "There", "project": OTHER_HOME}),
+            ),
+        ]);
+        let (merged, conflicts) = merge(&Snapshot::new(), &local, &remote, None);
+        assert!(conflicts.is_empty());
+        assert!(!merged.contains_key(&project_path(OTHER_HOME)));
+        assert_eq!(merged[&project_path(HOME)]["parent"], HOME);
+        assert_eq!(merged[&task_path(OTHER_TASK)]["project"], HOME);
+    }
+
+    #[test]
+    fn only_uuid_paths_are_synced_files() {
+        assert_eq!(path_uuid(&task_path(TASK), "tasks"), Some(TASK));
+        assert_eq!(path_uuid("tasks/12.json", "tasks"), None);
+        assert_eq!(path_uuid(&task_path(TASK), "projects"), None);
+    }
+}
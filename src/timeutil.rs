@@ -0,0 +1,150 @@
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Utc, Weekday};
+
+use crate::error::{Result, TodoError};
+
+/// Parses spans such as `45m`, `1h30m`, `2d` or `3w`.
+pub fn parse_duration(text: &str) -> Result<Duration> {
+    let invalid = || TodoError::invalid(format!("invalid duration '{text}', expected e.g. 1h30m"));
+
+    let mut total = Duration::zero();
+    let mut number = String::new();
+    for c in text.trim().chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let n: i64 = number.parse().map_err(|_| invalid())?;
+        number.clear();
+        let span = match c.to_ascii_lowercase() {
+            'w' => Duration::try_weeks(n),
+            'd' => Duration::try_days(n),
+            'h' => Duration::try_hours(n),
+            'm' => Duration::try_minutes(n),
+            _ => return Err(invalid()),
+        };
+        total = span
+            .and_then(|span| total.checked_add(&span))
+            .ok_or_else(|| out_of_range(text))?;
+    }
+
+    if !number.is_empty() || text.trim().is_empty() {
+        return Err(invalid());
+    }
+    Ok(total)
+}
+
+/// The date `span`, as in `parse_duration`, before `date`.
+pub fn date_before(span: &str, date: NaiveDate) -> Result<NaiveDate> {
+    date.checked_sub_signed(parse_duration(span)?)
+        .ok_or_else(|| out_of_range(span))
+}
+
+/// The time `span`, as in `parse_duration`, before `time`.
+pub fn time_before(span: &str, time: DateTime<Utc>) -> Result<DateTime<Utc>> {
+    time.checked_sub_signed(parse_duration(span)?)
+        .ok_or_else(|| out_of_range(span))
+}
+
+/// Error for a span or date that reaches past the dates that can be represented.
+pub fn out_of_range(text: &str) -> TodoError {
+    TodoError::invalid(format!("'{text}' is too far away"))
+}
+
+/// Formats a span as hours and minutes, e.g. `1h30m`, `2h` or `45m`.
+pub fn format_duration(duration: Duration) -> String {
+    let minutes = duration.num_minutes().max(0);
+    match (minutes / 60, minutes % 60) {
+        (0, m) => format!("{m}m"),
+        (h, 0) => format!("{h}h"),
+        (h, m) => format!("{h}h{m:02}m"),
+    }
+}
+
+/// Parses `today`, `yesterday`, `tomorrow`, a weekday name (its next
+/// occurrence), `+3d`/`-1w` offsets, or an ISO `YYYY-MM-DD` date.
+pub fn parse_date(text: &str, today: NaiveDate) -> Result<NaiveDate> {
+    let lower = text.trim().to_lowercase();
+    match lower.as_str() {
+        "today" => return Ok(today),
+        "yesterday" => return today.pred_opt().ok_or_else(|| out_of_range(text)),
+        "tomorrow" => return today.succ_opt().ok_or_else(|| out_of_range(text)),
+        _ => {}
+    }
+
+    if let Ok(weekday) = lower.parse::<Weekday>() {
+        let ahead = (7 + weekday.num_days_from_monday() as i64
+            - today.weekday().num_days_from_monday() as i64)
+            % 7;
+        return today
+            .checked_add_signed(Duration::days(if ahead == 0 { 7 } else { ahead }))
+            .ok_or_else(|| out_of_range(text));
+    }
+
+    if let Some(offset) = lower.strip_prefix('+') {
+        return today
+            .checked_add_signed(parse_duration(offset)?)
+            .ok_or_else(|| out_of_range(text));
+    }
+    if let Some(offset) = lower.strip_prefix('-') {
+        return date_before(offset, today);
+    }
+
+    NaiveDate::parse_from_str(&lower, "%Y-%m-%d").map_err(|_| {
+        TodoError::invalid(format!(
+            "invalid date '{text}', expected YYYY-MM-DD, today, tomorrow, a weekday or +3d"
+        ))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn today() -> NaiveDate {
+        NaiveDate::from_ymd_opt(2026, 10, 19).unwrap()
+    }
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("1h30m").unwrap(), Duration::minutes(90));
+        assert_eq!(parse_duration("2w3d").unwrap(), Duration::days(17));
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("5").is_err());
+        assert!(parse_duration("5y").is_err());
+    }
+
+    #[test]
+    fn overlong_durations_are_errors() {
+        assert!(parse_duration("99999999999w").is_err());
+        assert!(parse_duration("99999999999999999999m").is_err());
+        let near_max = format!("{}d", Duration::MAX.num_days());
+        assert!(parse_duration(&format!("{near_max}{near_max}")).is_err());
+    }
+
+    #[test]
+    fn offsets_past_the_calendar_are_errors() {
+        assert!(parse_date("+99999999w", today()).is_err());
+        assert!(parse_date("-99999999w", today()).is_err());
+        assert!(date_before("99999999w", today()).is_err());
+        assert!(time_before("99999999w", Utc::now()).is_err());
+        assert!(parse_date("tomorrow", NaiveDate::MAX).is_err());
+        assert!(parse_date("yesterday", NaiveDate::MIN).is_err());
+    }
+
+    #[test]
+    fn parses_dates() {
+        let today = today();
+        assert_eq!(parse_date("+3d", today).unwrap(), today + Duration::days(3));
+        assert_eq!(
+            date_before("1w", today).unwrap(),
+            today - Duration::weeks(1)
+        );
+        // 2026-10-19 is a Monday.
+        assert_eq!(
+            parse_date("mon", today).unwrap(),
+            today + Duration::weeks(1)
+        );
+        assert_eq!(parse_date("fri", today).unwrap(), today + Duration::days(4));
+        assert!(parse_date("someday", today).is_err());
+    }
+}
@@ -0,0 +1,187 @@
+use chrono::{DateTime, NaiveDate, Utc};
+
+use crate::{
+    config::UrgencyConfig,
+    hierarchy::{Priority, Task},
+};
+
+/// What a task's urgency depends on beyond the task itself.
+pub struct Context<'a> {
+    pub project: &'a str,
+    /// Some dependency of the task is still open.
+    pub blocked: bool,
+    /// Some open task depends on this one.
+    pub blocking: bool,
+    pub today: NaiveDate,
+    pub now: DateTime<Utc>,
+}
+
+/// One part of an urgency score: a factor between 0 and 1 (or a count of tags)
+/// weighted by its configured coefficient.
+#[derive(Debug, Clone)]
+pub struct Term {
+    pub name: String,
+    pub factor: f64,
+    pub coefficient: f64,
+}
+
+impl Term {
+    pub fn value(&self) -> f64 {
+        self.factor * self.coefficient
+    }
+}
+
+/// An urgency score together with the terms it was summed from.
+#[derive(Debug, Clone)]
+pub struct Urgency {
+    pub terms: Vec<Term>,
+}
+
+impl Urgency {
+    pub fn total(&self) -> f64 {
+        self.terms.iter().map(Term::value).sum()
+    }
+}
+
+/// Scores `task` with the coefficients in `config`. Terms that don't apply are
+/// left out, so the result also explains the score.
+pub fn score(task: &Task, context: &Context, config: &UrgencyConfig) -> Urgency {
+    let mut terms = Vec::new();
+    let mut add = |name: &str, factor: f64, coefficient: f64| {
+        if factor != 0.0 && coefficient != 0.0 {
+            terms.push(Term {
+                name: name.to_string(),
+                factor,
+                coefficient,
+            });
+        }
+    };
+
+    let priority = match task.priority() {
+        Priority::High => config.priority_high,
+        Priority::Medium => config.priority_medium,
+        Priority::Low => config.priority_low,
+        Priority::None => 0.0,
+    };
+    add("priority", 1.0, priority);
+
+    if let Some(due) = task.due_time() {
+        add(
+            "due",
+            due_factor((due - context.today).num_days()),
+            config.due,
+        );
+    }
+
+    let age_days = (context.now - *task.created_at()).num_hours() as f64 / 24.0;
+    add(
+        "age",
+        (age_days / config.age_max_days).clamp(0.0, 1.0),
+        config.age,
+    );
+
+    add("blocked", context.blocked as u8 as f64, config.blocked);
+    add("blocking", context.blocking as u8 as f64, config.blocking);
+    add("active", task.is_tracking() as u8 as f64, config.active);
+
+    let tagged = match task.tags().len() {
+        0 => 0.0,
+        1 => 0.8,
+        2 => 0.9,
+        _ => 1.0,
+    };
+    add("tags", tagged, config.tagged);
+    for tag in task.tags() {
+        if let Some(&coefficient) = config.tag.get(tag) {
+            add(&format!("tag {tag}"), 1.0, coefficient);
+        }
+    }
+
+    if let Some(&coefficient) = config.project.get(context.project) {
+        add(&format!("project {}", context.project), 1.0, coefficient);
+    }
+
+    Urgency { terms }
+}
+
+/// Rises linearly from 0.2 two weeks out to 1.0 a week overdue.
+fn due_factor(days_left: i64) -> f64 {
+    match days_left {
+        d if d <= -7 => 1.0,
+        d if d >= 14 => 0.2,
+        d => 0.2 + (14 - d) as f64 * 0.8 / 21.0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use chrono::Days;
+
+    use super::*;
+
+    fn context(blocked: bool) -> Context<'static> {
+        let now = Utc::now();
+        Context {
+            project: "Home",
+            blocked,
+            blocking: false,
+            today: now.date_naive(),
+            now,
+        }
+    }
+
+    fn task(priority: Priority, due: Option<NaiveDate>) -> Task {
+        Task::new(
+            1,
+            0,
+            String::from("Task"),
+            String::new(),
+            priority,
+            Utc::now(),
+            due,
+            None,
+        )
+    }
+
+    #[test]
+    fn priority_and_due_date_raise_the_score() {
+        let config = UrgencyConfig::default();
+        let today = context(false).today;
+        let plain = score(&task(Priority::None, None), &context(false), &config);
+        let high = score(&task(Priority::High, None), &context(false), &config);
+        let soon = score(&task(Priority::None, Some(today)), &context(false), &config);
+        let later = score(
+            &task(Priority::None, today.checked_add_days(Days::new(30))),
+            &context(false),
+            &config,
+        );
+        assert!(plain.terms.is_empty());
+        assert_eq!(high.total(), config.priority_high);
+        assert!(soon.total() > later.total());
+        assert!(later.total() > plain.total());
+    }
+
+    #[test]
+    fn due_factor_is_clamped_on_both_sides() {
+        assert_eq!(due_factor(30), 0.2);
+        assert_eq!(due_factor(14), 0.2);
+        assert_eq!(due_factor(-7), 1.0);
+        assert_eq!(due_factor(-30), 1.0);
+    }
+
+    #[test]
+    fn blocked_tasks_and_configured_projects_get_their_own_terms() {
+        let config = UrgencyConfig {
+            project: BTreeMap::from([(String::from("Home"), 2.0)]),
+            ..UrgencyConfig::default()
+        };
+        let mut tagged = task(Priority::None, None);
+        tagged.add_tag("home");
+        let urgency = score(&tagged, &context(true), &config);
+        let names: Vec<&str> = urgency.terms.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, ["blocked", "tags", "project Home"]);
+        assert_eq!(urgency.total(), config.blocked + 0.8 * config.tagged + 2.0);
+    }
+}
@@ -0,0 +1,84 @@
+use crate::error::{Result, TodoError};
+
+/// Short numbers for the open tasks, handed out by `todo gc` when the
+/// `working_set` option is on. Number `n` stands for the stored id at `ids[n - 1]`;
+/// tasks added later get the next number, and every number stays put until the
+/// next `gc`. Stored ids don't change, so a task outside the working set is
+/// written `#id`, a form that is always accepted.
+///
+/// With the option off the working set is empty and ids are used as they are.
+#[derive(Debug, Clone, Default)]
+pub struct WorkingSet {
+    enabled: bool,
+    ids: Vec<usize>,
+}
+
+impl WorkingSet {
+    pub fn new(ids: Vec<usize>) -> Self {
+        WorkingSet { enabled: true, ids }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn push(&mut self, id: usize) {
+        self.ids.push(id);
+    }
+
+    /// The working-set number of the task with stored id `id`.
+    pub fn number(&self, id: usize) -> Option<usize> {
+        self.ids.iter().position(|&i| i == id).map(|i| i + 1)
+    }
+
+    /// How the task with stored id `id` is written for the user.
+    pub fn label(&self, id: usize) -> String {
+        if !self.enabled {
+            return id.to_string();
+        }
+        match self.number(id) {
+            Some(number) => number.to_string(),
+            None => format!("#{id}"),
+        }
+    }
+
+    /// The stored id behind a number given on the command line.
+    pub fn resolve(&self, number: usize) -> Result<usize> {
+        if !self.enabled {
+            return Ok(number);
+        }
+        number
+            .checked_sub(1)
+            .and_then(|i| self.ids.get(i))
+            .copied()
+            .ok_or_else(|| {
+                TodoError::not_found(format!(
+                    "no task {number} in the working set; use #id or a uuid, or run todo gc"
+                ))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers_map_to_stored_ids() {
+        let mut set = WorkingSet::new(vec![40, 12]);
+        set.push(57);
+        assert_eq!(set.resolve(1).unwrap(), 40);
+        assert_eq!(set.resolve(3).unwrap(), 57);
+        assert!(set.resolve(0).is_err());
+        assert!(set.resolve(4).is_err());
+        assert_eq!(set.label(12), "2");
+        assert_eq!(set.label(99), "#99");
+    }
+
+    #[test]
+    fn a_disabled_set_passes_ids_through() {
+        let set = WorkingSet::default();
+        assert_eq!(set.resolve(1234).unwrap(), 1234);
+        assert_eq!(set.label(1234), "1234");
+    }
+}